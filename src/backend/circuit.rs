@@ -0,0 +1,322 @@
+use super::latency::now_ns;
+use crate::config::CircuitBreakerConfig;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    #[default]
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+/// Circuit breaker di un backend: troppi errori nella finestra aprono il circuito,
+/// scaduto `open_secs` passano solo poche richieste di prova (half-open)
+/// che decidono se richiuderlo o riaprirlo.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    /// `true` finché il circuito è chiuso: nel caso comune la selezione non prende il lock
+    closed: AtomicBool,
+    inner: Mutex<CircuitInner>,
+}
+
+#[derive(Debug, Default)]
+struct CircuitInner {
+    state: CircuitState,
+    open_until_ns: u64,
+    consecutive_failures: u32,
+    window_start_ns: u64,
+    window_requests: u32,
+    window_failures: u32,
+    trials_in_flight: u32,
+    trial_successes: u32,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self {
+            closed: AtomicBool::new(true),
+            inner: Mutex::new(CircuitInner::default()),
+        }
+    }
+}
+
+impl CircuitInner {
+    /// Un circuito aperto diventa half-open quando scade il tempo di apertura
+    fn refresh(&mut self, now: u64) {
+        if self.state == CircuitState::Open && now >= self.open_until_ns {
+            self.state = CircuitState::HalfOpen;
+            self.trials_in_flight = 0;
+            self.trial_successes = 0;
+        }
+    }
+
+    fn open(&mut self, now: u64, config: &CircuitBreakerConfig) {
+        self.state = CircuitState::Open;
+        self.open_until_ns = now + Duration::from_secs(config.open_secs).as_nanos() as u64;
+        self.consecutive_failures = 0;
+        self.window_requests = 0;
+        self.window_failures = 0;
+    }
+
+    fn close(&mut self, now: u64) {
+        *self = Self {
+            window_start_ns: now,
+            ..Self::default()
+        };
+    }
+}
+
+impl CircuitBreaker {
+    pub fn state(&self) -> CircuitState {
+        if self.closed.load(Ordering::Relaxed) {
+            return CircuitState::Closed;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        inner.refresh(now_ns());
+        inner.state
+    }
+
+    /// Il backend può essere scelto: circuito chiuso o half-open con prove ancora libere
+    pub fn allows_request(&self, config: &CircuitBreakerConfig) -> bool {
+        if self.closed.load(Ordering::Relaxed) {
+            return true;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        inner.refresh(now_ns());
+        match inner.state {
+            CircuitState::Closed => true,
+            CircuitState::Open => false,
+            CircuitState::HalfOpen => inner.trials_in_flight < config.half_open_requests,
+        }
+    }
+
+    /// Prenota la richiesta per il backend scelto; in half-open consuma una delle prove.
+    /// Restituisce lo stato in cui la richiesta è stata ammessa, `None` se nel frattempo
+    /// le prove sono finite o il circuito si è aperto.
+    pub fn try_acquire(&self, config: &CircuitBreakerConfig) -> Option<CircuitState> {
+        if self.closed.load(Ordering::Relaxed) {
+            return Some(CircuitState::Closed);
+        }
+        let mut inner = self.inner.lock().unwrap();
+        inner.refresh(now_ns());
+        match inner.state {
+            CircuitState::Closed => Some(CircuitState::Closed),
+            CircuitState::Open => None,
+            CircuitState::HalfOpen if inner.trials_in_flight < config.half_open_requests => {
+                inner.trials_in_flight += 1;
+                Some(CircuitState::HalfOpen)
+            }
+            CircuitState::HalfOpen => None,
+        }
+    }
+
+    /// Restituisce una prova half-open rimasta senza esito (richiesta annullata)
+    pub fn release_trial(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.state == CircuitState::HalfOpen {
+            inner.trials_in_flight = inner.trials_in_flight.saturating_sub(1);
+        }
+    }
+
+    /// Registra l'esito di una richiesta (`trial` se ammessa come prova half-open);
+    /// restituisce `(vecchio, nuovo)` se lo stato è cambiato
+    pub fn record(&self, success: bool, trial: bool, config: &CircuitBreakerConfig) -> Option<(CircuitState, CircuitState)> {
+        let now = now_ns();
+        let mut inner = self.inner.lock().unwrap();
+        inner.refresh(now);
+        let before = inner.state;
+
+        match inner.state {
+            CircuitState::Closed => {
+                let window_ns = Duration::from_secs(config.window_secs).as_nanos() as u64;
+                if now.saturating_sub(inner.window_start_ns) > window_ns {
+                    inner.window_start_ns = now;
+                    inner.window_requests = 0;
+                    inner.window_failures = 0;
+                }
+
+                inner.window_requests += 1;
+                if success {
+                    inner.consecutive_failures = 0;
+                } else {
+                    inner.consecutive_failures += 1;
+                    inner.window_failures += 1;
+                }
+
+                let too_many_consecutive = config.consecutive_failures > 0
+                    && inner.consecutive_failures >= config.consecutive_failures;
+                let error_rate_too_high = inner.window_requests >= config.min_requests
+                    && inner.window_failures * 100 >= inner.window_requests * config.error_rate_percent;
+                if too_many_consecutive || error_rate_too_high {
+                    inner.open(now, config);
+                }
+            }
+            CircuitState::HalfOpen if trial => {
+                inner.trials_in_flight = inner.trials_in_flight.saturating_sub(1);
+                if !success {
+                    inner.open(now, config);
+                } else {
+                    inner.trial_successes += 1;
+                    if inner.trial_successes >= config.half_open_requests {
+                        inner.close(now);
+                    }
+                }
+            }
+            // Risposte di richieste partite prima dell'apertura: non cambiano nulla
+            CircuitState::HalfOpen | CircuitState::Open => {}
+        }
+
+        self.closed.store(inner.state == CircuitState::Closed, Ordering::Relaxed);
+        (inner.state != before).then_some((before, inner.state))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> CircuitBreakerConfig {
+        CircuitBreakerConfig {
+            consecutive_failures: 3,
+            error_rate_percent: 50,
+            min_requests: 10,
+            window_secs: 60,
+            open_secs: 30,
+            half_open_requests: 2,
+        }
+    }
+
+    /// Come se fossero passati `open_secs`: il tempo di apertura scade adesso
+    fn expire_open(breaker: &CircuitBreaker) {
+        breaker.inner.lock().unwrap().open_until_ns = now_ns();
+    }
+
+    fn fail(breaker: &CircuitBreaker, times: u32, config: &CircuitBreakerConfig) {
+        for _ in 0..times {
+            breaker.record(false, false, config);
+        }
+    }
+
+    fn opened(config: &CircuitBreakerConfig) -> CircuitBreaker {
+        let breaker = CircuitBreaker::default();
+        fail(&breaker, config.consecutive_failures, config);
+        assert_eq!(breaker.state(), CircuitState::Open);
+        breaker
+    }
+
+    #[test]
+    fn consecutive_failures_open_the_circuit() {
+        let config = config();
+        let breaker = CircuitBreaker::default();
+        fail(&breaker, 2, &config);
+        // Un successo azzera i fallimenti consecutivi
+        assert_eq!(breaker.record(true, false, &config), None);
+        fail(&breaker, 2, &config);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+
+        assert_eq!(
+            breaker.record(false, false, &config),
+            Some((CircuitState::Closed, CircuitState::Open))
+        );
+        assert!(!breaker.allows_request(&config));
+        assert_eq!(breaker.try_acquire(&config), None);
+    }
+
+    #[test]
+    fn error_rate_opens_the_circuit_after_min_requests() {
+        let config = CircuitBreakerConfig {
+            consecutive_failures: 0,
+            ..config()
+        };
+        let breaker = CircuitBreaker::default();
+        for _ in 0..4 {
+            breaker.record(false, false, &config);
+            breaker.record(true, false, &config);
+        }
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        breaker.record(false, false, &config);
+        breaker.record(true, false, &config);
+        assert_eq!(breaker.state(), CircuitState::Open);
+    }
+
+    #[test]
+    fn open_circuit_becomes_half_open_when_the_time_expires() {
+        let config = config();
+        let breaker = opened(&config);
+        expire_open(&breaker);
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert!(breaker.allows_request(&config));
+    }
+
+    #[test]
+    fn half_open_admits_a_limited_number_of_trials() {
+        let config = config();
+        let breaker = opened(&config);
+        expire_open(&breaker);
+
+        assert_eq!(breaker.try_acquire(&config), Some(CircuitState::HalfOpen));
+        assert_eq!(breaker.try_acquire(&config), Some(CircuitState::HalfOpen));
+        assert!(!breaker.allows_request(&config));
+        assert_eq!(breaker.try_acquire(&config), None);
+
+        // Una prova annullata libera il posto
+        breaker.release_trial();
+        assert_eq!(breaker.try_acquire(&config), Some(CircuitState::HalfOpen));
+    }
+
+    #[test]
+    fn successful_trials_close_the_circuit() {
+        let config = config();
+        let breaker = opened(&config);
+        expire_open(&breaker);
+        breaker.try_acquire(&config);
+        breaker.try_acquire(&config);
+
+        assert_eq!(breaker.record(true, true, &config), None);
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert_eq!(
+            breaker.record(true, true, &config),
+            Some((CircuitState::HalfOpen, CircuitState::Closed))
+        );
+        assert!(breaker.allows_request(&config));
+        // Il conteggio riparte da zero
+        fail(&breaker, 2, &config);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[test]
+    fn failed_trial_reopens_the_circuit() {
+        let config = config();
+        let breaker = opened(&config);
+        expire_open(&breaker);
+        breaker.try_acquire(&config);
+        breaker.try_acquire(&config);
+
+        breaker.record(true, true, &config);
+        assert_eq!(
+            breaker.record(false, true, &config),
+            Some((CircuitState::HalfOpen, CircuitState::Open))
+        );
+        assert_eq!(breaker.try_acquire(&config), None);
+    }
+
+    #[test]
+    fn late_responses_do_not_change_an_open_circuit() {
+        let config = config();
+        let breaker = opened(&config);
+        // Richieste partite prima dell'apertura
+        assert_eq!(breaker.record(true, false, &config), None);
+        assert_eq!(breaker.record(false, false, &config), None);
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        expire_open(&breaker);
+        breaker.try_acquire(&config);
+        assert_eq!(breaker.record(true, false, &config), None);
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+    }
+}
@@ -1,21 +1,109 @@
 use super::pool::BackendPool;
-use super::server::BackendStatus;
-use reqwest::Client;
-use std::{time::Duration};
-use tracing::{info, warn};
+use super::server::{BackendProtocol, BackendStatus};
+use reqwest::{Client, Method};
+use regex::Regex;
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
 use crate::backend::Backend;
+use crate::config::validate::layer4_backend_address;
+use crate::config::{HealthCheckConfig, ListenerMode};
+use crate::lb::udp::{connect_udp, recv_connected};
+use crate::proxy::grpc::GRPC_STATUS;
+use reqwest::header::{CONTENT_TYPE, TE};
+
+/// Metodo del protocollo standard di health check gRPC
+const GRPC_HEALTH_CHECK_PATH: &str = "/grpc.health.v1.Health/Check";
+/// `HealthCheckResponse.ServingStatus.SERVING`
+const GRPC_SERVING: u64 = 1;
+
+/// Ogni quanto il supervisore allinea i probe ai backend del pool (reload della config)
+const SYNC_INTERVAL: Duration = Duration::from_secs(1);
 
 pub struct HealthCheck {
     pool: BackendPool,
     interval_secs: u64,
     client: Client,
-    load_balancer_url: String,
+    /// Client per i backend h2c, che accettano solo HTTP/2 in chiaro
+    h2c_client: Client,
+}
+
+/// Health check di un backend pronto all'uso (regex compilata, status già parsati)
+struct Probe {
+    url: String,
+    method: Method,
+    status_ranges: Vec<RangeInclusive<u16>>,
+    body_contains: Option<String>,
+    body_regex: Option<Regex>,
+    headers: Vec<(String, String)>,
+    timeout: Duration,
+    interval: Duration,
+    jitter_ms: u64,
+    kind: ProbeKind,
+}
+
+enum ProbeKind {
+    Http,
+    /// `grpc.health.v1` per il servizio indicato invece della richiesta HTTP
+    Grpc(String),
+    /// Backend dei listener `mode: tcp`: basta che accetti la connessione
+    Tcp(String),
+    /// Backend dei listener `mode: udp`: malato solo se la porta risulta chiusa
+    Udp(String),
+}
+
+impl Probe {
+    fn new(backend: &Backend, default_interval_secs: u64) -> Self {
+        let config: &HealthCheckConfig = &backend.health_check;
+        // La config è validata all'avvio e ad ogni reload: qui i fallback non dovrebbero servire
+        let kind = match (layer4_backend_address(&backend.url), &config.grpc_service) {
+            (Ok((ListenerMode::Tcp, address)), _) => ProbeKind::Tcp(address.to_string()),
+            (Ok((ListenerMode::Udp, address)), _) => ProbeKind::Udp(address.to_string()),
+            (Err(_), Some(service)) => ProbeKind::Grpc(service.clone()),
+            (Err(_), None) => ProbeKind::Http,
+        };
+        let path = match kind {
+            ProbeKind::Grpc(_) => GRPC_HEALTH_CHECK_PATH,
+            ProbeKind::Http | ProbeKind::Tcp(_) | ProbeKind::Udp(_) => config.path.as_str(),
+        };
+        Self {
+            url: format!("{}{}", backend.url.trim_end_matches('/'), path),
+            method: Method::from_bytes(config.method.as_bytes()).unwrap_or(Method::GET),
+            status_ranges: config.status_ranges().unwrap_or_else(|| vec![200..=299]),
+            body_contains: config.body_contains.clone(),
+            body_regex: config.body_regex.as_deref().and_then(|pattern| Regex::new(pattern).ok()),
+            headers: config.headers.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+            timeout: Duration::from_millis(config.timeout_ms),
+            interval: Duration::from_secs(config.interval_secs.unwrap_or(default_interval_secs)),
+            jitter_ms: config.jitter_ms,
+            kind,
+        }
+    }
+
+    fn next_delay(&self) -> Duration {
+        use rand::Rng;
+
+        if self.jitter_ms == 0 {
+            return self.interval;
+        }
+        self.interval + Duration::from_millis(rand::thread_rng().gen_range(0..=self.jitter_ms))
+    }
+
+    fn needs_body(&self) -> bool {
+        self.body_contains.is_some() || self.body_regex.is_some()
+    }
 }
 
 impl HealthCheck {
-    pub fn new(pool: BackendPool, interval_secs: u64, load_balancer_url: String) -> Self {
+    pub fn new(pool: BackendPool, interval_secs: u64) -> Self {
         let client = Client::builder()
-            .timeout(Duration::from_secs(3))
+            .build()
+            .expect("Failed to create HTTP client");
+        let h2c_client = Client::builder()
+            .http2_prior_knowledge()
             .build()
             .expect("Failed to create HTTP client");
 
@@ -23,7 +111,7 @@ impl HealthCheck {
             pool,
             interval_secs,
             client,
-            load_balancer_url,
+            h2c_client,
         }
     }
 
@@ -34,45 +122,309 @@ impl HealthCheck {
     }
 
     async fn run(self) {
-        let interval = Duration::from_secs(self.interval_secs);
+        // Un task per backend, ognuno con il suo intervallo
+        let mut probes: HashMap<String, (Backend, JoinHandle<()>)> = HashMap::new();
         loop {
-            self.check_all_backends().await;
-            tokio::time::sleep(interval).await;
+            self.sync_probes(&mut probes);
+            tokio::time::sleep(SYNC_INTERVAL).await;
         }
     }
 
-    async fn check_all_backends(&self) {
+    /// Avvia i probe dei backend nuovi e ferma quelli dei backend rimossi o modificati
+    fn sync_probes(&self, probes: &mut HashMap<String, (Backend, JoinHandle<()>)>) {
         let backends_status = self.pool.state.load();
-        let backends = backends_status.iter().map(|status| status.backend.clone()); 
-
-        for (index, backend) in backends.enumerate() {
-            let client = self.client.clone();
-            let backend = backend.clone();
-            let url = self.load_balancer_url.clone();
-            let pool = self.pool.clone();
-
-            tokio::spawn(async move {
-                let status = Self::check_single_backend(&client, &backend, url).await;
-                // Update status and log
-                pool.update_backend_status(index, status).await;
-                match status {
-                    BackendStatus::Healthy => info!("Backend {} ({}) is healthy", backend.name, backend.url),
-                    BackendStatus::Unhealthy => warn!("Backend {} ({}) is unhealthy", backend.name, backend.url),
+
+        probes.retain(|name, (backend, handle)| {
+            let unchanged = backends_status
+                .iter()
+                .any(|status| status.backend.name == *name && status.backend.same_settings(backend));
+            if !unchanged {
+                handle.abort();
+            }
+            unchanged
+        });
+
+        for status in backends_status.iter() {
+            if probes.contains_key(&status.backend.name) {
+                continue;
+            }
+            let backend = status.backend.clone();
+            let client = match backend.protocol {
+                BackendProtocol::H2c => self.h2c_client.clone(),
+                BackendProtocol::Http1 | BackendProtocol::H2 => self.client.clone(),
+            };
+            let handle = tokio::spawn(Self::probe_loop(
+                client,
+                self.pool.clone(),
+                backend.clone(),
+                self.interval_secs,
+            ));
+            probes.insert(backend.name.clone(), (backend, handle));
+        }
+    }
+
+    async fn probe_loop(client: Client, pool: BackendPool, backend: Backend, default_interval_secs: u64) {
+        let probe = Probe::new(&backend, default_interval_secs);
+
+        loop {
+            let status = Self::check_single_backend(&client, &probe).await;
+            debug!("Probe {} ({}): {:?}", backend.name, probe.url, status);
+
+            // Rise/fall e flap damping decidono se lo stato cambia davvero
+            if pool.get_backend_by_name(&backend.name).await.is_none() {
+                return;
+            }
+            if let Some((from, to)) = pool.record_probe(&backend.name, status == BackendStatus::Healthy).await {
+                match to {
+                    BackendStatus::Healthy => info!("Backend {} ({}) is healthy (was {:?})", backend.name, backend.url, from),
+                    BackendStatus::Unhealthy => warn!("Backend {} ({}) is unhealthy (was {:?})", backend.name, backend.url, from),
                     BackendStatus::Unknown => warn!("Backend {} ({}) status unknown", backend.name, backend.url),
                 }
-            });
+            }
+            Self::log_flap_hold(&pool, &backend).await;
+
+            tokio::time::sleep(probe.next_delay()).await;
+        }
+    }
+
+    async fn log_flap_hold(pool: &BackendPool, backend: &Backend) {
+        let Some(backend_state) = pool.get_backend_by_name(&backend.name).await else {
+            return;
+        };
+        let held_for = backend_state.health.lock().unwrap().held_for();
+        if let Some(remaining) = held_for {
+            if backend_state.status != BackendStatus::Healthy {
+                warn!("Backend {} is flapping, held out of rotation for another {}s", backend.name, remaining.as_secs());
+            }
+        }
+    }
+
+    async fn check_single_backend(client: &Client, probe: &Probe) -> BackendStatus {
+        match &probe.kind {
+            ProbeKind::Http => {}
+            ProbeKind::Grpc(service) => return Self::check_grpc_backend(client, probe, service).await,
+            ProbeKind::Tcp(address) => return Self::check_tcp_backend(address, probe.timeout).await,
+            ProbeKind::Udp(address) => return Self::check_udp_backend(address, probe.timeout).await,
+        }
+        let mut request = client
+            .request(probe.method.clone(), &probe.url)
+            .timeout(probe.timeout);
+        for (name, value) in &probe.headers {
+            request = request.header(name, value);
+        }
+
+        let response = match request.send().await {
+            Ok(response) => response,
+            Err(_) => return BackendStatus::Unhealthy,
+        };
+
+        let code = response.status().as_u16();
+        if !probe.status_ranges.iter().any(|range| range.contains(&code)) {
+            return BackendStatus::Unhealthy;
+        }
+
+        if !probe.needs_body() {
+            return BackendStatus::Healthy;
+        }
+        let body = match response.text().await {
+            Ok(body) => body,
+            Err(_) => return BackendStatus::Unhealthy,
+        };
+        let contains_ok = probe.body_contains.as_ref().is_none_or(|needle| body.contains(needle.as_str()));
+        let regex_ok = probe.body_regex.as_ref().is_none_or(|regex| regex.is_match(&body));
 
+        if contains_ok && regex_ok {
+            BackendStatus::Healthy
+        } else {
+            BackendStatus::Unhealthy
         }
     }
 
-    async fn check_single_backend(client: &Client, backend: &Backend,load_balancer_url: String) -> BackendStatus {
-        let health_url = format!("{}/health/{}",load_balancer_url, backend.name);
+    async fn check_tcp_backend(address: &str, timeout: Duration) -> BackendStatus {
+        match tokio::time::timeout(timeout, TcpStream::connect(address)).await {
+            Ok(Ok(_)) => BackendStatus::Healthy,
+            _ => BackendStatus::Unhealthy,
+        }
+    }
+
+    /// UDP non ha handshake: si invia un datagramma vuoto (DNS e syslog lo scartano) e il
+    /// backend è malato solo se il socket connesso riceve un ICMP port unreachable.
+    /// Una risposta o il silenzio entro il timeout valgono come sano.
+    async fn check_udp_backend(address: &str, timeout: Duration) -> BackendStatus {
+        let probe = async {
+            let socket = connect_udp(address).await?;
+            socket.send(&[]).await?;
+            let mut buf = [0u8; 512];
+            recv_connected(&socket, &mut buf).await.map(|_| ())
+        };
+        match tokio::time::timeout(timeout, probe).await {
+            Ok(Err(e)) => {
+                debug!("UDP health check to {} failed: {}", address, e);
+                BackendStatus::Unhealthy
+            }
+            Ok(Ok(())) | Err(_) => BackendStatus::Healthy,
+        }
+    }
 
-        match client.get(&health_url).send().await {
-            Ok(response) if response.status().is_success() => BackendStatus::Healthy,
-            Ok(_) => BackendStatus::Unhealthy,
-            Err(_) => BackendStatus::Unhealthy,
+    /// `grpc.health.v1.Health/Check`: sano solo se il servizio risponde SERVING
+    async fn check_grpc_backend(client: &Client, probe: &Probe, service: &str) -> BackendStatus {
+        let mut request = client
+            .post(&probe.url)
+            .timeout(probe.timeout)
+            .header(CONTENT_TYPE, "application/grpc")
+            .header(TE, "trailers")
+            .body(grpc_health_request(service));
+        for (name, value) in &probe.headers {
+            request = request.header(name, value);
         }
+
+        let response = match request.send().await {
+            Ok(response) => response,
+            Err(_) => return BackendStatus::Unhealthy,
+        };
+        // Un errore (es. NOT_FOUND per un servizio sconosciuto) arriva come risposta trailers-only
+        let grpc_error = response
+            .headers()
+            .get(GRPC_STATUS)
+            .is_some_and(|status| status.as_bytes() != b"0");
+        if response.status() != reqwest::StatusCode::OK || grpc_error {
+            return BackendStatus::Unhealthy;
+        }
+
+        match response.bytes().await.ok().as_deref().and_then(grpc_serving_status) {
+            Some(GRPC_SERVING) => BackendStatus::Healthy,
+            _ => BackendStatus::Unhealthy,
+        }
+    }
+}
+
+/// `HealthCheckRequest { service }` in un messaggio gRPC: flag di compressione, lunghezza, protobuf
+fn grpc_health_request(service: &str) -> Vec<u8> {
+    let mut message = Vec::new();
+    if !service.is_empty() {
+        message.push(0x0a); // campo 1, length-delimited
+        put_varint(&mut message, service.len() as u64);
+        message.extend_from_slice(service.as_bytes());
+    }
+
+    let mut frame = Vec::with_capacity(5 + message.len());
+    frame.push(0);
+    frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&message);
+    frame
+}
+
+/// Campo `status` del primo `HealthCheckResponse` nel corpo (0 = UNKNOWN se assente)
+fn grpc_serving_status(body: &[u8]) -> Option<u64> {
+    // Messaggi compressi non previsti: la richiesta non dichiara grpc-accept-encoding
+    let (&compressed, rest) = body.split_first()?;
+    if compressed != 0 {
+        return None;
+    }
+    let length = u32::from_be_bytes(rest.get(..4)?.try_into().ok()?) as usize;
+    let mut message = rest.get(4..4 + length)?;
+
+    let mut status = 0;
+    while !message.is_empty() {
+        let key = read_varint(&mut message)?;
+        match (key >> 3, key & 0x7) {
+            (1, 0) => status = read_varint(&mut message)?,
+            // Campi sconosciuti: saltati secondo il wire type
+            (_, 0) => {
+                read_varint(&mut message)?;
+            }
+            (_, 1) => message = message.get(8..)?,
+            (_, 2) => {
+                let length = read_varint(&mut message)? as usize;
+                message = message.get(length..)?;
+            }
+            (_, 5) => message = message.get(4..)?,
+            _ => return None,
+        }
+    }
+    Some(status)
+}
+
+fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn read_varint(buf: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = buf.split_first()?;
+        *buf = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Messaggio gRPC non compresso con il protobuf `message`
+    fn frame(message: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0];
+        frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        frame.extend_from_slice(message);
+        frame
+    }
+
+    #[test]
+    fn serving_and_not_serving() {
+        assert_eq!(grpc_serving_status(&frame(&[0x08, 0x01])), Some(GRPC_SERVING));
+        assert_eq!(grpc_serving_status(&frame(&[0x08, 0x02])), Some(2));
+        // Campo assente: valore di default UNKNOWN
+        assert_eq!(grpc_serving_status(&frame(&[])), Some(0));
+    }
+
+    #[test]
+    fn multi_byte_varints_and_unknown_fields() {
+        let mut message = Vec::new();
+        message.extend_from_slice(&[0x12, 3, b'a', b'b', b'c']); // campo 2, length-delimited
+        message.extend_from_slice(&[0x19, 0, 0, 0, 0, 0, 0, 0, 0]); // campo 3, fixed64
+        message.extend_from_slice(&[0x25, 0, 0, 0, 0]); // campo 4, fixed32
+        message.extend_from_slice(&[0x28, 0x96, 0x01]); // campo 5, varint
+        message.push(0x08);
+        put_varint(&mut message, 300);
+        assert_eq!(grpc_serving_status(&frame(&message)), Some(300));
+    }
+
+    #[test]
+    fn truncated_responses() {
+        let serving = frame(&[0x08, 0x01]);
+        for len in 0..serving.len() {
+            assert_eq!(grpc_serving_status(&serving[..len]), None, "{len} bytes");
+        }
+        // Varint interrotto dentro un messaggio completo
+        assert_eq!(grpc_serving_status(&frame(&[0x08, 0x81])), None);
+        // Campo length-delimited più lungo del messaggio
+        assert_eq!(grpc_serving_status(&frame(&[0x12, 5, b'a'])), None);
+    }
+
+    #[test]
+    fn malformed_responses() {
+        // Messaggio compresso
+        let mut compressed = frame(&[0x08, 0x01]);
+        compressed[0] = 1;
+        assert_eq!(grpc_serving_status(&compressed), None);
+        // Wire type 3 (group, deprecato)
+        assert_eq!(grpc_serving_status(&frame(&[0x0b])), None);
+        // Varint di oltre 10 byte
+        assert_eq!(grpc_serving_status(&frame(&[0x08, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01])), None);
+    }
+
+    #[test]
+    fn health_request_encoding() {
+        assert_eq!(grpc_health_request(""), frame(&[]));
+        assert_eq!(grpc_health_request("pkg.Svc"), frame(b"\x0a\x07pkg.Svc"));
     }
-    
 }
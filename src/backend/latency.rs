@@ -0,0 +1,77 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+/// Costante di decadimento: dopo `DECAY` senza campioni il peso dei vecchi valori è ~37%
+const DECAY: Duration = Duration::from_secs(10);
+/// Latenza ipotizzata per un backend mai misurato
+const DEFAULT_RTT: Duration = Duration::from_millis(50);
+
+/// Media mobile esponenziale "di picco" della latenza di un backend:
+/// un campione più lento della media la sostituisce subito, quelli più veloci
+/// la abbassano gradualmente. Senza nuovi campioni il valore decade verso zero,
+/// così un backend lento viene riprovato dopo un po'.
+#[derive(Debug)]
+pub struct PeakEwma {
+    /// Nanosecondi, come bit di un f64 (0 = nessun campione)
+    ewma_ns: AtomicU64,
+    /// Momento dell'ultimo aggiornamento, in nanosecondi da `epoch()`
+    updated_at_ns: AtomicU64,
+}
+
+fn epoch() -> Instant {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    *EPOCH.get_or_init(Instant::now)
+}
+
+/// Nanosecondi da un istante fisso del processo, comodo da salvare in un atomico
+pub(crate) fn now_ns() -> u64 {
+    epoch().elapsed().as_nanos() as u64
+}
+
+fn decay_weight(elapsed_ns: u64) -> f64 {
+    (-(elapsed_ns as f64) / DECAY.as_nanos() as f64).exp()
+}
+
+impl Default for PeakEwma {
+    fn default() -> Self {
+        Self::new(0.0, now_ns())
+    }
+}
+
+impl PeakEwma {
+    fn new(ewma_ns: f64, updated_at_ns: u64) -> Self {
+        Self {
+            ewma_ns: AtomicU64::new(ewma_ns.to_bits()),
+            updated_at_ns: AtomicU64::new(updated_at_ns),
+        }
+    }
+
+    pub fn observe(&self, rtt: Duration) {
+        let now = now_ns();
+        let sample = rtt.as_nanos() as f64;
+        let elapsed = now.saturating_sub(self.updated_at_ns.swap(now, Ordering::Relaxed));
+
+        // fetch_update: campioni concorrenti non si perdono
+        let _ = self.ewma_ns.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+            let current = f64::from_bits(bits);
+            let next = if current == 0.0 || sample > current {
+                sample
+            } else {
+                let w = decay_weight(elapsed);
+                current * w + sample * (1.0 - w)
+            };
+            Some(next.to_bits())
+        });
+    }
+
+    /// Latenza stimata in nanosecondi, con il decadimento dall'ultimo campione
+    pub fn estimate_ns(&self) -> f64 {
+        let current = f64::from_bits(self.ewma_ns.load(Ordering::Relaxed));
+        if current == 0.0 {
+            return DEFAULT_RTT.as_nanos() as f64;
+        }
+        let elapsed = now_ns().saturating_sub(self.updated_at_ns.load(Ordering::Relaxed));
+        current * decay_weight(elapsed)
+    }
+}
@@ -1,7 +1,11 @@
+pub mod circuit;
 pub mod healthcheck;
+pub mod latency;
+pub mod outlier;
 pub mod pool;
 pub mod server;
+pub mod tracker;
 
 pub use healthcheck::HealthCheck;
-pub use pool::BackendPool;
-pub use server::{Backend, BackendStatus, LoadBalancingStrategy};
+pub use pool::{BackendPool, SelectionContext};
+pub use server::{Backend, BackendProtocol, BackendStatus, HashKey, LoadBalancingStrategy};
@@ -0,0 +1,176 @@
+use super::latency::now_ns;
+use crate::config::OutlierDetectionConfig;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Rilevamento passivo dei backend anomali a partire dal traffico reale:
+/// errori consecutivi o tasso di errori troppo alto in una finestra fanno
+/// espellere il backend per un tempo crescente ad ogni nuova espulsione.
+#[derive(Debug, Default)]
+pub struct OutlierDetector {
+    /// Fine dell'espulsione in nanosecondi (0 = non espulso), letto ad ogni selezione
+    ejected_until_ns: AtomicU64,
+    counters: Mutex<OutlierCounters>,
+}
+
+#[derive(Debug, Default)]
+struct OutlierCounters {
+    consecutive_errors: u32,
+    window_start_ns: u64,
+    window_requests: u32,
+    window_errors: u32,
+    ejections: u32,
+}
+
+impl OutlierDetector {
+    pub fn is_ejected(&self) -> bool {
+        now_ns() < self.ejected_until_ns.load(Ordering::Relaxed)
+    }
+
+    /// Tempo restante di espulsione
+    pub fn ejected_for(&self) -> Option<Duration> {
+        let remaining = self.ejected_until_ns.load(Ordering::Relaxed).saturating_sub(now_ns());
+        (remaining > 0).then(|| Duration::from_nanos(remaining))
+    }
+
+    /// Registra l'esito di una richiesta; `true` se il backend andrebbe espulso
+    pub fn record(&self, success: bool, config: &OutlierDetectionConfig) -> bool {
+        if self.is_ejected() {
+            return false;
+        }
+
+        let now = now_ns();
+        let interval_ns = Duration::from_secs(config.interval_secs).as_nanos() as u64;
+        let mut counters = self.counters.lock().unwrap();
+
+        if now.saturating_sub(counters.window_start_ns) > interval_ns {
+            counters.window_start_ns = now;
+            counters.window_requests = 0;
+            counters.window_errors = 0;
+        }
+
+        counters.window_requests += 1;
+        if success {
+            counters.consecutive_errors = 0;
+        } else {
+            counters.consecutive_errors += 1;
+            counters.window_errors += 1;
+        }
+
+        let too_many_consecutive = config.consecutive_errors > 0
+            && counters.consecutive_errors >= config.consecutive_errors;
+        let error_rate_too_high = counters.window_requests >= config.min_requests
+            && counters.window_errors * 100 >= counters.window_requests * config.error_rate_percent;
+
+        too_many_consecutive || error_rate_too_high
+    }
+
+    /// Espelle il backend e restituisce la durata dell'espulsione
+    pub fn eject(&self, config: &OutlierDetectionConfig) -> Duration {
+        let now = now_ns();
+        let mut counters = self.counters.lock().unwrap();
+
+        // Dopo un lungo periodo senza problemi si riparte dalla durata base
+        let max_ejection = Duration::from_secs(config.max_ejection_secs);
+        let last_end = self.ejected_until_ns.load(Ordering::Relaxed);
+        if last_end > 0 && now.saturating_sub(last_end) > max_ejection.as_nanos() as u64 {
+            counters.ejections = 0;
+        }
+
+        counters.ejections += 1;
+        counters.consecutive_errors = 0;
+        counters.window_start_ns = now;
+        counters.window_requests = 0;
+        counters.window_errors = 0;
+
+        let duration = Duration::from_secs(config.base_ejection_secs)
+            .saturating_mul(counters.ejections)
+            .min(max_ejection);
+        self.ejected_until_ns.store(now + duration.as_nanos() as u64, Ordering::Relaxed);
+        duration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> OutlierDetectionConfig {
+        OutlierDetectionConfig {
+            consecutive_errors: 3,
+            error_rate_percent: 50,
+            min_requests: 10,
+            interval_secs: 60,
+            base_ejection_secs: 30,
+            max_ejection_secs: 100,
+            max_ejection_percent: 50,
+        }
+    }
+
+    /// Come se l'espulsione fosse finita `ago` fa
+    fn end_ejection(detector: &OutlierDetector, ago: Duration) {
+        let end = now_ns().saturating_sub(ago.as_nanos() as u64).max(1);
+        detector.ejected_until_ns.store(end, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn consecutive_errors_mark_an_outlier() {
+        let config = config();
+        let detector = OutlierDetector::default();
+        assert!(!detector.record(false, &config));
+        assert!(!detector.record(false, &config));
+        // Un successo azzera gli errori consecutivi
+        assert!(!detector.record(true, &config));
+        assert!(!detector.record(false, &config));
+        assert!(!detector.record(false, &config));
+        assert!(detector.record(false, &config));
+    }
+
+    #[test]
+    fn error_rate_marks_an_outlier_after_min_requests() {
+        let config = OutlierDetectionConfig {
+            consecutive_errors: 0,
+            ..config()
+        };
+        let detector = OutlierDetector::default();
+        for _ in 0..4 {
+            assert!(!detector.record(false, &config));
+            assert!(!detector.record(true, &config));
+        }
+        assert!(!detector.record(false, &config));
+        assert!(detector.record(true, &config));
+    }
+
+    #[test]
+    fn ejected_backend_ignores_outcomes_until_it_returns() {
+        let config = config();
+        let detector = OutlierDetector::default();
+        assert_eq!(detector.eject(&config), Duration::from_secs(30));
+        assert!(detector.is_ejected());
+        assert!(detector.ejected_for().is_some_and(|left| left <= Duration::from_secs(30)));
+        for _ in 0..5 {
+            assert!(!detector.record(false, &config));
+        }
+
+        end_ejection(&detector, Duration::ZERO);
+        assert!(!detector.is_ejected());
+        assert_eq!(detector.ejected_for(), None);
+        // I contatori ripartono dall'espulsione
+        assert!(!detector.record(false, &config));
+        assert!(!detector.record(false, &config));
+        assert!(detector.record(false, &config));
+    }
+
+    #[test]
+    fn ejection_time_grows_up_to_the_maximum() {
+        let config = config();
+        let detector = OutlierDetector::default();
+        let mut durations = Vec::new();
+        for _ in 0..5 {
+            durations.push(detector.eject(&config).as_secs());
+            end_ejection(&detector, Duration::from_secs(1));
+        }
+        assert_eq!(durations, [30, 60, 90, 100, 100]);
+    }
+}
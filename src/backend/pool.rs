@@ -1,9 +1,16 @@
-use super::server::{Backend, BackendStatus, LoadBalancingStrategy};
-use arc_swap::ArcSwap;
-use std::{sync::Arc};
-use tokio::sync::RwLock;
-use std::sync::atomic::AtomicU32;
-use std::sync::atomic::AtomicU64;
+use super::circuit::{CircuitBreaker, CircuitState};
+use super::latency::PeakEwma;
+use super::tracker::HealthTracker;
+use super::outlier::OutlierDetector;
+use super::server::{Backend, BackendStatus, HashKey, LoadBalancingStrategy};
+use crate::config::{BackendConfig, CircuitBreakerConfig, ListenerMode, OutlierDetectionConfig};
+use tracing::{info, warn};
+use crate::lb::algorithms::{ConsistentHashTable, SmoothWeights};
+use arc_swap::{ArcSwap, ArcSwapOption};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, AtomicU64};
 use std::sync::atomic::Ordering;
 use std::sync::atomic::AtomicUsize;
 
@@ -12,15 +19,128 @@ pub struct BackendPool {
     pub state: Arc<ArcSwap<Vec<Arc<BackendState>>>>,
     pub strategy: LoadBalancingStrategy,
     pub round_robin_idx: Arc<AtomicUsize>,
-    pub weighted_rr_state: Arc<RwLock<WeightedRRState>>,
+    pub smooth_wrr: Arc<ArcSwapOption<SmoothWeights>>,
+    pub hash_key: HashKey,
+    pub hash_table: Arc<ArcSwapOption<ConsistentHashTable>>,
+    pub outlier_detection: Option<Arc<OutlierDetectionConfig>>,
+    pub circuit_breaker: Option<Arc<CircuitBreakerConfig>>,
+}
+
+/// Informazioni sulla richiesta utili alla scelta del backend
+#[derive(Debug, Default, Clone)]
+pub struct SelectionContext {
+    /// Hash della chiave configurata in `hash_key`, se la richiesta la contiene
+    pub hash: Option<u64>,
+    /// Backend già provati per questa richiesta (retry), da non scegliere di nuovo
+    pub exclude: Vec<String>,
 }
 
 #[derive(Debug)]
 pub struct BackendState {
     pub backend: Backend,
     pub status: BackendStatus,
-    pub connections: AtomicU32,
+    /// Richieste in corso; come i campi sotto è condiviso tra le versioni dello stato,
+    /// così le guardie delle richieste in corso aggiornano sempre il contatore attuale
+    pub connections: Arc<AtomicU32>,
+    pub latency: Arc<PeakEwma>,
+    /// Condiviso tra le versioni dello stato: sopravvive ai cambi di status
+    pub health: Arc<Mutex<HealthTracker>>,
+    pub outlier: Arc<OutlierDetector>,
+    pub circuit: Arc<CircuitBreaker>,
+    pub traffic: Arc<Traffic>,
+}
+
+/// Byte scambiati con il backend da tunnel e connessioni TCP, aggiornati mentre passano
+#[derive(Debug, Default)]
+pub struct Traffic {
+    /// Dal client al backend
+    pub sent: AtomicU64,
+    /// Dal backend al client
+    pub received: AtomicU64,
+}
+
+impl BackendState {
+    pub fn new(backend: Backend) -> Self {
+        Self {
+            backend,
+            status: BackendStatus::Unknown,
+            connections: Arc::new(AtomicU32::new(0)),
+            latency: Arc::new(PeakEwma::default()),
+            health: Arc::new(Mutex::new(HealthTracker::default())),
+            outlier: Arc::new(OutlierDetector::default()),
+            circuit: Arc::new(CircuitBreaker::default()),
+            traffic: Arc::new(Traffic::default()),
+        }
+    }
+
+    /// Costo per peak-EWMA: latenza stimata moltiplicata per le richieste in corso (+1)
+    pub fn ewma_cost(&self) -> f64 {
+        self.latency.estimate_ns() * (self.connections.load(Ordering::Relaxed) as f64 + 1.0)
+    }
+
+    /// Richieste in corso al limite di `max_connections`
+    pub fn is_saturated(&self) -> bool {
+        self.backend
+            .max_connections
+            .is_some_and(|max| self.connections.load(Ordering::Relaxed) >= max)
+    }
+
+    /// Incrementa le richieste in corso senza superare `max_connections`
+    pub fn try_add_connection(&self) -> bool {
+        let max = self.backend.max_connections.unwrap_or(u32::MAX);
+        self.connections
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |current| (current < max).then_some(current + 1))
+            .is_ok()
+    }
+
+    /// Nuovo stato per lo stesso backend (o una sua versione aggiornata),
+    /// mantenendo contatori e metriche di quello attuale
+    pub fn rebuild(&self, backend: Backend, status: BackendStatus) -> Self {
+        Self {
+            backend,
+            status,
+            connections: Arc::clone(&self.connections),
+            latency: Arc::clone(&self.latency),
+            health: Arc::clone(&self.health),
+            outlier: Arc::clone(&self.outlier),
+            circuit: Arc::clone(&self.circuit),
+            traffic: Arc::clone(&self.traffic),
+        }
+    }
+}
+/// Richiesta in corso verso un backend. Il contatore `connections` scende quando
+/// la guardia viene rilasciata, anche se la richiesta viene annullata a metà.
+#[derive(Debug)]
+pub struct ConnectionGuard {
+    backend_state: Arc<BackendState>,
+    /// Richiesta di prova di un circuito half-open, da restituire se non arriva un esito
+    circuit_trial: bool,
+    reported: bool,
+}
+
+impl ConnectionGuard {
+    pub fn backend_state(&self) -> &Arc<BackendState> {
+        &self.backend_state
+    }
+}
+
+impl std::ops::Deref for ConnectionGuard {
+    type Target = BackendState;
+
+    fn deref(&self) -> &BackendState {
+        &self.backend_state
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.backend_state.connections.fetch_sub(1, Ordering::SeqCst);
+        if self.circuit_trial && !self.reported {
+            self.backend_state.circuit.release_trial();
+        }
+    }
 }
+
 // Implementa Clone manualmente
 impl Clone for BackendPool {
     fn clone(&self) -> Self {
@@ -28,16 +148,74 @@ impl Clone for BackendPool {
             state: Arc::clone(&self.state),
             strategy: self.strategy,
             round_robin_idx: Arc::clone(&self.round_robin_idx),
-            weighted_rr_state: Arc::clone(&self.weighted_rr_state),
+            smooth_wrr: Arc::clone(&self.smooth_wrr),
+            hash_key: self.hash_key.clone(),
+            hash_table: Arc::clone(&self.hash_table),
+            outlier_detection: self.outlier_detection.clone(),
+            circuit_breaker: self.circuit_breaker.clone(),
+        }
+    }
+}
+
+/// Fotografia dello stato di un backend, esposta da `/admin/backends`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackendSnapshot {
+    pub name: String,
+    pub url: String,
+    pub weight: u32,
+    pub status: BackendStatus,
+    pub connections: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_connections: Option<u32>,
+    #[serde(default)]
+    pub circuit: CircuitState,
+    #[serde(default)]
+    pub bytes_sent: u64,
+    #[serde(default)]
+    pub bytes_received: u64,
+}
+
+/// Pool di un listener aggiuntivo (`listeners` nella config), con backend propri
+#[derive(Debug, Clone)]
+pub struct ListenerPool {
+    pub name: String,
+    pub mode: ListenerMode,
+    pub bind: SocketAddr,
+    pub pool: BackendPool,
+}
+
+/// Fotografia di un listener aggiuntivo, esposta da `/admin/listeners`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ListenerSnapshot {
+    pub name: String,
+    pub mode: ListenerMode,
+    pub bind: String,
+    pub backends: Vec<BackendSnapshot>,
+}
+
+impl ListenerPool {
+    pub fn snapshot(&self) -> ListenerSnapshot {
+        ListenerSnapshot {
+            name: self.name.clone(),
+            mode: self.mode,
+            bind: self.bind.to_string(),
+            backends: self.pool.snapshot(),
         }
     }
 }
 
-#[derive(Default,Debug)]
-pub struct WeightedRRState {
-    pub expanded_list: Vec<String>, 
-    pub generation: AtomicU64,      
-    pub last_backends_hash: u64,   
+/// Esito di un reload della lista backend
+#[derive(Debug, Default)]
+pub struct ReloadSummary {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub updated: Vec<String>,
+}
+
+impl ReloadSummary {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.updated.is_empty()
+    }
 }
 
 impl BackendPool {
@@ -45,87 +223,206 @@ impl BackendPool {
         let backend_states: Vec<Arc<BackendState>> = backends
             .into_iter()
             .map(|backend| {
-                Arc::new(BackendState {
-                    backend,
-                    status: BackendStatus::Unknown,
-                    connections: AtomicU32::new(0),
-                })
+                Arc::new(BackendState::new(backend))
             })
             .collect();
 
-        let weighted_rr_state = match strategy {
-            LoadBalancingStrategy::WeightedRoundRobin => {
-                // Pre-compute expanded list for weighted round robin
-                let expanded_list = backend_states
-                    .iter()
-                    .flat_map(|state| {
-                        std::iter::repeat(state.backend.name.clone())
-                            .take(state.backend.weight as usize)
-                    })
-                    .collect();
-                
-                Arc::new(RwLock::new(WeightedRRState {
-                    expanded_list,
-                    generation: AtomicU64::new(1),
-                    last_backends_hash: 0, // Will be computed on first use
-                }))
-            }
-            _ => Arc::new(RwLock::new(WeightedRRState {
-                expanded_list: Vec::new(),
-                generation: AtomicU64::new(0),
-                last_backends_hash: 0,
-            })),
-        };
-
         Self {
             state: Arc::new(ArcSwap::new(Arc::new(backend_states))),
             strategy,
             round_robin_idx: Arc::new(AtomicUsize::new(0)),
-            weighted_rr_state,
+            smooth_wrr: Arc::new(ArcSwapOption::empty()),
+            hash_key: HashKey::default(),
+            hash_table: Arc::new(ArcSwapOption::empty()),
+            outlier_detection: None,
+            circuit_breaker: None,
+        }
+    }
+
+    pub fn with_outlier_detection(mut self, config: Option<OutlierDetectionConfig>) -> Self {
+        self.outlier_detection = config.map(Arc::new);
+        self
+    }
+
+    pub fn with_circuit_breaker(mut self, config: Option<CircuitBreakerConfig>) -> Self {
+        self.circuit_breaker = config.map(Arc::new);
+        self
+    }
+
+    pub fn with_hash_key(mut self, hash_key: HashKey) -> Self {
+        self.hash_key = hash_key;
+        self
+    }
+
+    /// Un backend può ricevere traffico
+    pub fn is_available(&self, backend_state: &BackendState) -> bool {
+        backend_state.status == BackendStatus::Healthy
+            && !backend_state.outlier.is_ejected()
+            && !backend_state.is_saturated()
+            && self
+                .circuit_breaker
+                .as_ref()
+                .is_none_or(|config| backend_state.circuit.allows_request(config))
+    }
+
+    /// Prenota la richiesta sul backend scelto: uno slot entro `max_connections`
+    /// e, se il circuit breaker è attivo, il permesso del circuito
+    fn try_claim(&self, backend_state: &Arc<BackendState>) -> Option<ConnectionGuard> {
+        if !backend_state.try_add_connection() {
+            return None;
+        }
+        let mut guard = ConnectionGuard {
+            backend_state: Arc::clone(backend_state),
+            circuit_trial: false,
+            reported: false,
+        };
+        let Some(config) = &self.circuit_breaker else {
+            return Some(guard);
+        };
+        // Se il circuito rifiuta, la guardia rilascia lo slot appena preso
+        match backend_state.circuit.try_acquire(config)? {
+            CircuitState::HalfOpen => {
+                info!("Circuit for backend {} is half-open, sending a trial request", backend_state.backend.name);
+                guard.circuit_trial = true;
+            }
+            CircuitState::Closed | CircuitState::Open => {}
+        }
+        Some(guard)
+    }
+
+    /// Esito di una richiesta reale verso un backend, per circuit breaker e outlier detection.
+    /// Non espelle mai più di `max_ejection_percent` dei backend.
+    pub fn report_outcome(&self, guard: &mut ConnectionGuard, success: bool) {
+        guard.reported = true;
+        let backend_state = guard.backend_state();
+        if let Some(config) = &self.circuit_breaker {
+            match backend_state.circuit.record(success, guard.circuit_trial, config) {
+                Some((_, CircuitState::Open)) => warn!(
+                    "Circuit for backend {} opened, no traffic for {}s",
+                    backend_state.backend.name, config.open_secs
+                ),
+                Some((_, CircuitState::Closed)) => info!("Circuit for backend {} closed", backend_state.backend.name),
+                _ => {}
+            }
+        }
+
+        let Some(config) = &self.outlier_detection else {
+            return;
+        };
+        if !backend_state.outlier.record(success, config) {
+            return;
         }
+
+        let state = self.state.load();
+        let ejected = state.iter().filter(|bs| bs.outlier.is_ejected()).count();
+        if (ejected + 1) * 100 > state.len() * config.max_ejection_percent as usize {
+            warn!(
+                "Backend {} is an outlier but {} of {} backends are already ejected (max {}%)",
+                backend_state.backend.name, ejected, state.len(), config.max_ejection_percent
+            );
+            return;
+        }
+
+        let duration = backend_state.outlier.eject(config);
+        warn!("Backend {} ejected for {}s after failing live traffic", backend_state.backend.name, duration.as_secs());
     }
 
 pub fn get_healthy_backends(&self) -> Vec<Arc<BackendState>> {
     let state = self.state.load();
     state.iter()
-        .filter(|s| s.status == BackendStatus::Healthy)
+        .filter(|s| self.is_available(s))
         .map(Arc::clone)
         .collect()
 }
-    pub async fn select_and_increment(&self) -> Option<Arc<BackendState>> {
+    pub async fn select_and_increment(&self, ctx: &SelectionContext) -> Option<ConnectionGuard> {
         // Get a snapshot of current healthy backends
         let state = self.state.load();
 
         // Filter healthy backends
-        let healthy: Vec<Arc<BackendState>> = state
+        let mut healthy: Vec<Arc<BackendState>> = state
             .iter()
-            .filter(|backend_state| backend_state.status == BackendStatus::Healthy)
+            .filter(|backend_state| self.is_available(backend_state))
+            .filter(|backend_state| !ctx.exclude.contains(&backend_state.backend.name))
             .cloned()
             .collect();
 
-        if healthy.is_empty() {
-            return None;
+        // Tra il filtro e la scelta un backend può saturarsi o esaurire le prove
+        // del circuito half-open: in quel caso si ripete la scelta senza di lui
+        loop {
+            if healthy.is_empty() {
+                return None;
+            }
+            let candidate = self.select_with_strategy(&healthy, ctx).await?;
+            if let Some(guard) = self.try_claim(&candidate) {
+                return Some(guard);
+            }
+            healthy.retain(|bs| !Arc::ptr_eq(bs, &candidate));
         }
+    }
 
-        // Select based on strategy
-        let selected = match self.strategy {
-            LoadBalancingStrategy::RoundRobin => self.round_robin_select(&healthy).await,
-            LoadBalancingStrategy::LeastConnections => self.least_connections_select(&healthy).await,
-            LoadBalancingStrategy::WeightedRoundRobin => self.weighted_round_robin_select(&healthy).await,
-            LoadBalancingStrategy::Random => self.random_select(&healthy),
-        }?;
+    async fn select_with_strategy(&self, healthy: &[Arc<BackendState>], ctx: &SelectionContext) -> Option<Arc<BackendState>> {
+        match self.strategy {
+            LoadBalancingStrategy::RoundRobin => self.round_robin_select(healthy).await,
+            LoadBalancingStrategy::LeastConnections => self.least_connections_select(healthy).await,
+            LoadBalancingStrategy::WeightedRoundRobin => self.weighted_round_robin_select(healthy),
+            LoadBalancingStrategy::Random => self.random_select(healthy),
+            LoadBalancingStrategy::PowerOfTwoChoices => self.p2c_select(healthy, |bs| bs.connections.load(Ordering::Relaxed) as f64),
+            LoadBalancingStrategy::PeakEwma => self.p2c_select(healthy, BackendState::ewma_cost),
+            LoadBalancingStrategy::RingHash | LoadBalancingStrategy::Maglev => self.consistent_hash_select(healthy, ctx.hash),
+        }
+    }
+    /// Applica l'esito di un health check tenendo conto di rise/fall e instabilità.
+    /// Restituisce `(vecchio, nuovo)` se lo stato è cambiato, `None` se è rimasto uguale
+    /// o se il backend non esiste più.
+    pub async fn record_probe(&self, name: &str, success: bool) -> Option<(BackendStatus, BackendStatus)> {
+        let backend_state = self.get_backend_by_name(name).await?;
+        let current = backend_state.status;
+        let next = backend_state
+            .health
+            .lock()
+            .unwrap()
+            .record(success, current, &backend_state.backend.health_check);
 
-        selected.connections.fetch_add(1, Ordering::SeqCst);
+        if next == current || !self.update_backend_status(name, next).await {
+            return None;
+        }
+        Some((current, next))
+    }
 
-        Some(selected)
+    /// Seleziona un backend preciso (sessioni sticky), solo se può ricevere traffico
+    pub async fn select_named_and_increment(&self, name: &str) -> Option<ConnectionGuard> {
+        self.get_backend_by_name(name).await
+            .filter(|backend_state| self.is_available(backend_state))
+            .and_then(|backend_state| self.try_claim(&backend_state))
     }
-    pub async fn decrement_connections(&self, backend_name: &str) {
-        let backend_opt = self.get_backend_by_name(backend_name).await;
-        if let Some(backend) = backend_opt{
-            let count = &backend.connections;
-            count.fetch_sub(1, Ordering::SeqCst);
 
+    /// Il backend della sessione sticky finché può ricevere traffico, altrimenti la scelta
+    /// della strategia (backend rimosso dalla config, non sano o saturo)
+    pub async fn select_pinned_or_increment(&self, pinned: Option<&str>, ctx: &SelectionContext) -> Option<ConnectionGuard> {
+        if let Some(name) = pinned {
+            if let Some(guard) = self.select_named_and_increment(name).await {
+                return Some(guard);
+            }
         }
+        self.select_and_increment(ctx).await
+    }
+
+    pub fn snapshot(&self) -> Vec<BackendSnapshot> {
+        self.state
+            .load()
+            .iter()
+            .map(|backend_state| BackendSnapshot {
+                name: backend_state.backend.name.clone(),
+                url: backend_state.backend.url.clone(),
+                weight: backend_state.backend.weight,
+                status: backend_state.status,
+                connections: backend_state.connections.load(Ordering::Relaxed),
+                max_connections: backend_state.backend.max_connections,
+                circuit: backend_state.circuit.state(),
+                bytes_sent: backend_state.traffic.sent.load(Ordering::Relaxed),
+                bytes_received: backend_state.traffic.received.load(Ordering::Relaxed),
+            })
+            .collect()
     }
 
     pub fn get_connection_count(&self, backend_name: &str) -> u32 {
@@ -136,6 +433,51 @@ pub fn get_healthy_backends(&self) -> Vec<Arc<BackendState>> {
             .map(|backend_state| backend_state.connections.load(Ordering::Relaxed))
             .unwrap_or(0)
     }
+    /// Allinea il pool alla nuova lista di backend della config.
+    /// I backend invariati mantengono lo stesso `BackendState` (connessioni e stato di salute),
+    /// quelli con peso diverso vengono ricreati copiando contatore e stato,
+    /// quelli con URL diverso ripartono da `Unknown` finché l'health check non li verifica.
+    pub fn reconcile(&self, configs: &[BackendConfig]) -> ReloadSummary {
+        let mut summary = ReloadSummary::default();
+
+        self.state.rcu(|current| {
+            summary = ReloadSummary::default();
+
+            let new_states: Vec<Arc<BackendState>> = configs
+                .iter()
+                .map(|backend_config| {
+                    let backend = Backend::from(backend_config);
+                    match current.iter().find(|bs| bs.backend.name == backend.name) {
+                        Some(existing) if existing.backend.same_settings(&backend) => existing.clone(),
+                        Some(existing) => {
+                            summary.updated.push(backend.name.clone());
+                            let status = if existing.backend.url == backend.url {
+                                existing.status
+                            } else {
+                                BackendStatus::Unknown
+                            };
+                            Arc::new(existing.rebuild(backend, status))
+                        }
+                        None => {
+                            summary.added.push(backend.name.clone());
+                            Arc::new(BackendState::new(backend))
+                        }
+                    }
+                })
+                .collect();
+
+            summary.removed = current
+                .iter()
+                .filter(|bs| !configs.iter().any(|c| c.name == bs.backend.name))
+                .map(|bs| bs.backend.name.clone())
+                .collect();
+
+            new_states
+        });
+
+        summary
+    }
+
     pub async fn get_backend_by_name(&self, name: &str) -> Option<Arc<BackendState>> {
         let backends_state = self.state.load();
         backends_state
@@ -145,3 +487,80 @@ pub fn get_healthy_backends(&self) -> Vec<Arc<BackendState>> {
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn pool(names: &[&str], config: OutlierDetectionConfig) -> BackendPool {
+        let backends = names
+            .iter()
+            .map(|name| Backend::new(format!("http://{name}"), name.to_string(), 1))
+            .collect();
+        let pool = BackendPool::new(backends, LoadBalancingStrategy::RoundRobin)
+            .with_outlier_detection(Some(config));
+        for name in names {
+            pool.update_backend_status(name, BackendStatus::Healthy).await;
+        }
+        pool
+    }
+
+    fn config() -> OutlierDetectionConfig {
+        OutlierDetectionConfig {
+            consecutive_errors: 2,
+            max_ejection_percent: 50,
+            ..OutlierDetectionConfig::default()
+        }
+    }
+
+    /// `consecutive_errors` richieste fallite verso `name`
+    async fn fail(pool: &BackendPool, name: &str) {
+        for _ in 0..2 {
+            let mut guard = pool.select_named_and_increment(name).await.unwrap();
+            pool.report_outcome(&mut guard, false);
+        }
+    }
+
+    fn ejected(pool: &BackendPool) -> Vec<String> {
+        pool.state
+            .load()
+            .iter()
+            .filter(|state| state.outlier.is_ejected())
+            .map(|state| state.backend.name.clone())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn failing_backend_is_ejected_from_selection() {
+        let pool = pool(&["a", "b"], config()).await;
+        let mut guard = pool.select_named_and_increment("a").await.unwrap();
+        pool.report_outcome(&mut guard, false);
+        assert!(ejected(&pool).is_empty());
+
+        let mut guard = pool.select_named_and_increment("a").await.unwrap();
+        pool.report_outcome(&mut guard, false);
+        assert_eq!(ejected(&pool), ["a"]);
+        assert!(pool.select_named_and_increment("a").await.is_none());
+        assert!(pool.get_healthy_backends().iter().all(|state| state.backend.name == "b"));
+    }
+
+    #[tokio::test]
+    async fn ejections_stop_at_max_ejection_percent() {
+        let pool = pool(&["a", "b", "c", "d"], config()).await;
+        fail(&pool, "a").await;
+        fail(&pool, "b").await;
+        assert_eq!(ejected(&pool), ["a", "b"]);
+
+        // Metà dei backend già espulsi: il prossimo resta in servizio
+        fail(&pool, "c").await;
+        assert_eq!(ejected(&pool), ["a", "b"]);
+        assert!(pool.select_named_and_increment("c").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn single_backend_is_never_ejected_below_the_cap() {
+        let pool = pool(&["a"], config()).await;
+        fail(&pool, "a").await;
+        assert!(ejected(&pool).is_empty());
+    }
+}
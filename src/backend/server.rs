@@ -1,28 +1,127 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use crate::config::{BackendConfig, BackendTimeouts, HealthCheckConfig};
 use std::hash::Hasher;
 use std::hash::Hash;
 use std::time::Duration;
+use std::fmt;
+use std::str::FromStr;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Backend {
     pub url: String,
     pub name: String,
     pub weight: u32,
+    #[serde(default)]
+    pub health_check: HealthCheckConfig,
+    /// Override dei timeout globali verso questo backend
+    #[serde(default)]
+    pub timeouts: BackendTimeouts,
+    #[serde(default)]
+    pub max_connections: Option<u32>,
+    #[serde(default)]
+    pub protocol: BackendProtocol,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+/// Protocollo verso il backend: HTTP/1.1, HTTP/2 su TLS (ALPN) o h2c in chiaro
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendProtocol {
+    #[default]
+    Http1,
+    H2,
+    H2c,
+}
+
+impl BackendProtocol {
+    pub fn is_http2(&self) -> bool {
+        matches!(self, BackendProtocol::H2 | BackendProtocol::H2c)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum BackendStatus {
     Healthy,
     Unhealthy,
     Unknown,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum LoadBalancingStrategy {
     RoundRobin,
     LeastConnections,
     Random,
     WeightedRoundRobin,
+    RingHash,
+    Maglev,
+    #[serde(rename = "p2c")]
+    PowerOfTwoChoices,
+    PeakEwma,
+}
+
+/// Attributo della richiesta usato come chiave dalle strategie di consistent hashing
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HashKey {
+    #[default]
+    ClientIp,
+    Header(String),
+    Cookie(String),
+    Path,
+}
+
+impl LoadBalancingStrategy {
+    pub const ALL: &'static [LoadBalancingStrategy] = &[
+        LoadBalancingStrategy::RoundRobin,
+        LoadBalancingStrategy::LeastConnections,
+        LoadBalancingStrategy::Random,
+        LoadBalancingStrategy::WeightedRoundRobin,
+        LoadBalancingStrategy::RingHash,
+        LoadBalancingStrategy::Maglev,
+        LoadBalancingStrategy::PowerOfTwoChoices,
+        LoadBalancingStrategy::PeakEwma,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LoadBalancingStrategy::RoundRobin => "round_robin",
+            LoadBalancingStrategy::LeastConnections => "least_connections",
+            LoadBalancingStrategy::Random => "random",
+            LoadBalancingStrategy::WeightedRoundRobin => "weighted_round_robin",
+            LoadBalancingStrategy::RingHash => "ring_hash",
+            LoadBalancingStrategy::Maglev => "maglev",
+            LoadBalancingStrategy::PowerOfTwoChoices => "p2c",
+            LoadBalancingStrategy::PeakEwma => "peak_ewma",
+        }
+    }
+}
+
+impl LoadBalancingStrategy {
+    pub fn uses_hash_key(&self) -> bool {
+        matches!(self, LoadBalancingStrategy::RingHash | LoadBalancingStrategy::Maglev)
+    }
+}
+
+impl fmt::Display for LoadBalancingStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for LoadBalancingStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .find(|strategy| strategy.as_str() == s)
+            .copied()
+            .ok_or_else(|| {
+                let valid: Vec<&str> = Self::ALL.iter().map(|s| s.as_str()).collect();
+                format!("unknown strategy '{s}', expected one of: {}", valid.join(", "))
+            })
+    }
 }
 // Implementa Hash e Eq per Backend
 impl Hash for Backend {
@@ -38,9 +137,55 @@ impl PartialEq for Backend {
 }
 impl Eq for Backend {}  
 
+impl From<&BackendConfig> for Backend {
+    fn from(config: &BackendConfig) -> Self {
+        Self::new(config.url.clone(), config.name.clone(), config.weight.unwrap_or(1))
+            .with_health_check(config.health_check.clone().unwrap_or_default())
+            .with_timeouts(config.timeouts.unwrap_or_default())
+            .with_max_connections(config.max_connections)
+            .with_protocol(config.protocol.unwrap_or_default())
+    }
+}
+
 impl Backend {
     pub fn new(url: String, name: String, weight: u32) -> Self {
-        Self { url, name, weight }
+        Self {
+            url,
+            name,
+            weight,
+            health_check: HealthCheckConfig::default(),
+            timeouts: BackendTimeouts::default(),
+            max_connections: None,
+            protocol: BackendProtocol::default(),
+        }
+    }
+
+    pub fn with_health_check(mut self, health_check: HealthCheckConfig) -> Self {
+        self.health_check = health_check;
+        self
+    }
+
+    pub fn with_timeouts(mut self, timeouts: BackendTimeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    pub fn with_max_connections(mut self, max_connections: Option<u32>) -> Self {
+        self.max_connections = max_connections;
+        self
+    }
+
+    pub fn with_protocol(mut self, protocol: BackendProtocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    /// Stessa configurazione (non solo stesso URL come per `PartialEq`)
+    pub fn same_settings(&self, other: &Backend) -> bool {
+        self.url == other.url && self.weight == other.weight && self.health_check == other.health_check
+            && self.timeouts == other.timeouts
+            && self.max_connections == other.max_connections
+            && self.protocol == other.protocol
     }
     pub async fn simulate_delay(&self) {
         println!("Backend {}: simulando ritardo di 1s", self.url);
@@ -0,0 +1,80 @@
+use super::server::BackendStatus;
+use crate::config::HealthCheckConfig;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Storia degli health check di un backend: probe consecutivi (rise/fall)
+/// e cambi di stato recenti per lo smorzamento dei backend instabili.
+#[derive(Debug, Default)]
+pub struct HealthTracker {
+    consecutive_successes: u32,
+    consecutive_failures: u32,
+    transitions: VecDeque<Instant>,
+    held_until: Option<Instant>,
+}
+
+impl HealthTracker {
+    /// Registra l'esito di un probe e restituisce lo stato che il backend deve avere.
+    /// Da `Unknown` decide il primo probe, così all'avvio i backend entrano subito in rotazione.
+    pub fn record(&mut self, success: bool, current: BackendStatus, config: &HealthCheckConfig) -> BackendStatus {
+        let now = Instant::now();
+
+        if success {
+            self.consecutive_successes = self.consecutive_successes.saturating_add(1);
+            self.consecutive_failures = 0;
+        } else {
+            self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+            self.consecutive_successes = 0;
+        }
+
+        let mut target = match current {
+            BackendStatus::Unknown if success => BackendStatus::Healthy,
+            BackendStatus::Unknown => BackendStatus::Unhealthy,
+            BackendStatus::Healthy if self.consecutive_failures >= config.fall => BackendStatus::Unhealthy,
+            BackendStatus::Unhealthy if self.consecutive_successes >= config.rise => BackendStatus::Healthy,
+            status => status,
+        };
+
+        // Un backend instabile resta fuori finché non scade la penalità
+        if target == BackendStatus::Healthy && current != BackendStatus::Healthy && self.is_held(now) {
+            target = BackendStatus::Unhealthy;
+        }
+
+        if target != current && current != BackendStatus::Unknown {
+            self.record_transition(now, config);
+        }
+
+        target
+    }
+
+    /// Tempo restante di esclusione per instabilità
+    pub fn held_for(&self) -> Option<Duration> {
+        self.held_until
+            .and_then(|until| until.checked_duration_since(Instant::now()))
+            .filter(|remaining| !remaining.is_zero())
+    }
+
+    fn is_held(&self, now: Instant) -> bool {
+        self.held_until.is_some_and(|until| now < until)
+    }
+
+    fn record_transition(&mut self, now: Instant, config: &HealthCheckConfig) {
+        let window = Duration::from_secs(config.flap_window_secs);
+        while self.transitions.front().is_some_and(|t| now.duration_since(*t) > window) {
+            self.transitions.pop_front();
+        }
+        self.transitions.push_back(now);
+
+        let flaps = self.transitions.len() as u32;
+        if config.flap_threshold == 0 || flaps < config.flap_threshold {
+            return;
+        }
+
+        // Penalità che raddoppia ad ogni cambio oltre la soglia, con un massimo
+        let exponent = (flaps - config.flap_threshold).min(16);
+        let hold = Duration::from_secs(config.flap_hold_secs)
+            .saturating_mul(1 << exponent)
+            .min(Duration::from_secs(config.flap_max_hold_secs));
+        self.held_until = Some(now + hold);
+    }
+}
@@ -1,79 +1,124 @@
-use crate::config::BackendConfig;
-use clap::Parser;
-use crate::config::Config;
+use clap::{Args, Parser, Subcommand};
+use crate::backend::pool::{BackendSnapshot, ListenerSnapshot};
+use crate::backend::LoadBalancingStrategy;
+use crate::config::{Config, ConfigError};
 
 #[derive(Parser, Debug)]
 #[command(name = "load-balancer")]
 #[command(about = "A high-performance load balancer written in Rust")]
 #[command(version = "1.0")]
 pub struct Cli {
-    /// Path Config 
-    #[arg(short, long, default_value = "config/config.yaml")]
+    /// Path Config
+    #[arg(short, long, default_value = "config/config.yaml", global = true)]
     pub config: String,
 
-    /// Host 
-    #[arg(long, default_value = "127.0.0.1")]
-    pub host: String,
+    #[command(flatten)]
+    pub overrides: Overrides,
+
+    /// Comando da eseguire (default: run)
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Override della config da CLI: solo i flag passati esplicitamente vengono applicati
+#[derive(Args, Debug, Default, Clone)]
+pub struct Overrides {
+    /// Host
+    #[arg(long, global = true)]
+    pub host: Option<String>,
 
     /// Port to listen on
-    #[arg(short, long, default_value_t = 3000)]
-    pub port: u16,
+    #[arg(short, long, global = true)]
+    pub port: Option<u16>,
 
     /// Load balancer algoritmo
-    #[arg(long, default_value = "round_robin")]
-    pub strategy: String,
+    #[arg(long, global = true)]
+    pub strategy: Option<LoadBalancingStrategy>,
 
     /// Intervallo Health check in secondi
-    #[arg(long, default_value_t = 10)]
-    pub health_check_interval: u64,
+    #[arg(long, global = true)]
+    pub health_check_interval: Option<u64>,
 }
 
-impl Cli {
-    pub fn parse() -> Config {
-        let cli = Self::parse_args();
-        
-        // Prova a caricare da file se no da CLI args
-        match Config::from_file(&cli.config) {
-            Ok(mut config) => {
-                // Fai Override con CLI args se dati
-                if cli.host != "127.0.0.1" {
-                    config.host = cli.host;
-                }
-                if cli.port != 3000 {
-                    config.port = cli.port;
-                }
-                if cli.strategy != "round_robin" {
-                    config.lb_strategy = cli.strategy;
-                }
-                if cli.health_check_interval != 10 {
-                    config.health_check_interval = cli.health_check_interval;
-                }
-                config
-            }
-            Err(_) => {
-                Config {
-                    host: cli.host,
-                    port: cli.port,
-                    lb_strategy: cli.strategy,
-                    health_check_interval: cli.health_check_interval,
-                    backends: vec![
-                        BackendConfig {
-                            name: "backend-1".to_string(),
-                            url: "http://127.0.0.1:8081".to_string(),
-                            weight: Some(1),
-                        },
-                        BackendConfig {
-                            name: "backend-2".to_string(),
-                            url: "http://127.0.0.1:8082".to_string(),
-                            weight: Some(1),
-                        },
-                    ],
-                }
-            }
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Avvia il load balancer
+    Run,
+    /// Parsa e valida il file di config, esce con errore se non è valido
+    CheckConfig,
+    /// Stampa la config effettiva (file + override CLI) in YAML, con i segreti oscurati
+    PrintConfig,
+    /// Mostra lo stato dei backend di un'istanza in esecuzione
+    Backends {
+        /// URL degli endpoint admin dell'istanza (default: `admin.bind` della config)
+        #[arg(long)]
+        url: Option<String>,
+        /// Token degli endpoint admin (default: `admin.token` della config)
+        #[arg(long)]
+        token: Option<String>,
+    },
+}
+
+impl Overrides {
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(host) = &self.host {
+            config.host = host.clone();
+        }
+        if let Some(port) = self.port {
+            config.port = port;
+        }
+        if let Some(strategy) = &self.strategy {
+            config.lb_strategy = *strategy;
+        }
+        if let Some(interval) = self.health_check_interval {
+            config.health_check_interval = interval;
         }
     }
+}
 
-    fn parse_args() -> Self {
+impl Cli {
+    pub fn parse_args() -> Self {
         <Self as Parser>::parse()
     }
+
+    pub fn command(&self) -> Command {
+        self.command.clone().unwrap_or(Command::Run)
+    }
+
+    /// Carica la config dal file e applica gli override della CLI
+    pub fn load_config(&self) -> anyhow::Result<Config> {
+        let mut config = Config::from_file(&self.config)?;
+        self.overrides.apply(&mut config);
+
+        // Il file è già validato: qui restano solo eventuali errori degli override
+        let issues = config.validate("");
+        if !issues.is_empty() {
+            return Err(ConfigError { path: "command line".to_string(), issues }.into());
+        }
+        Ok(config)
+    }
+}
+
+/// Interroga l'endpoint admin di un'istanza in esecuzione
+pub async fn fetch_backends(base_url: &str, token: Option<&str>) -> anyhow::Result<Vec<BackendSnapshot>> {
+    let response = admin_get(base_url, "/admin/backends", token).await?.error_for_status()?;
+    Ok(response.json().await?)
+}
+
+/// Listener aggiuntivi (TCP); vuoto se l'istanza non espone `/admin/listeners`
+pub async fn fetch_listeners(base_url: &str, token: Option<&str>) -> anyhow::Result<Vec<ListenerSnapshot>> {
+    let response = admin_get(base_url, "/admin/listeners", token).await?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(Vec::new());
+    }
+    Ok(response.error_for_status()?.json().await?)
+}
+
+async fn admin_get(base_url: &str, path: &str, token: Option<&str>) -> reqwest::Result<reqwest::Response> {
+    let url = format!("{}{}", base_url.trim_end_matches('/'), path);
+    let mut request = reqwest::Client::new().get(&url);
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+    request.send().await
 }
@@ -1,20 +1,536 @@
-use serde::Deserialize;
+pub mod validate;
+pub mod watcher;
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
+use std::ops::RangeInclusive;
+use anyhow::Context;
+use crate::backend::{BackendProtocol, HashKey, LoadBalancingStrategy};
+use crate::proxy::compress::ContentCoding;
+
+pub use validate::{ConfigError, ConfigIssue};
+pub use watcher::ConfigWatcher;
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     pub host: String,
     pub port: u16,
-    pub lb_strategy: String,
+    /// Accetta anche HTTP/2 in chiaro (h2c con prior knowledge) sulla porta HTTP
+    #[serde(default)]
+    pub h2c: bool,
+    pub lb_strategy: LoadBalancingStrategy,
+    /// Chiave per `ring_hash` e `maglev`: client_ip, path, { header: nome } o { cookie: nome }
+    #[serde(default, with = "serde_yaml::with::singleton_map")]
+    pub hash_key: HashKey,
     pub health_check_interval: u64,
     pub backends: Vec<BackendConfig>,
+    /// Se presente abilita le sessioni sticky tramite cookie
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sticky_session: Option<StickySessionConfig>,
+    /// Se presente abilita l'espulsione passiva dei backend in base al traffico reale
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outlier_detection: Option<OutlierDetectionConfig>,
+    /// Se presente abilita un circuit breaker per ogni backend
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    /// Se presente abilita i retry su un altro backend
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<RetryConfig>,
+    /// Timeout verso i backend (sovrascrivibili per backend) e verso i client
+    #[serde(default)]
+    pub timeouts: TimeoutConfig,
+    /// Limite globale di richieste in corso e coda di attesa
+    #[serde(default)]
+    pub concurrency: ConcurrencyConfig,
+    /// Compressione delle risposte negoziata con `Accept-Encoding`
+    #[serde(default)]
+    pub compression: CompressionConfig,
+    /// Se presente abilita la cache in memoria delle risposte dei backend
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache: Option<CacheConfig>,
+    /// Listener aggiuntivi con backend propri, es. `mode: tcp` per Postgres o Redis
+    /// e `mode: udp` per DNS o syslog.
+    /// Non vengono ricaricati a caldo.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub listeners: Vec<ListenerConfig>,
+    /// Endpoint di amministrazione (`/admin/...`), su un indirizzo separato dal traffico.
+    /// Non viene ricaricato a caldo.
+    #[serde(default)]
+    pub admin: AdminConfig,
+    /// Se presente i listener HTTP e HTTPS leggono l'header PROXY (v1 o v2) dalle sorgenti
+    /// fidate, per conoscere il vero client dietro un balancer TCP. Non viene ricaricato a caldo.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy_protocol: Option<ProxyProtocolConfig>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    /// Indirizzo di ascolto, es. "127.0.0.1:9901"; di default raggiungibile solo in locale
+    pub bind: String,
+    /// Se presente le richieste devono inviare `Authorization: Bearer <token>`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+// Il token non deve finire nei log
+impl std::fmt::Debug for AdminConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AdminConfig")
+            .field("bind", &self.bind)
+            .field("token", &self.token.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self {
+            bind: "127.0.0.1:9901".to_string(),
+            token: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ProxyProtocolConfig {
+    /// Reti CIDR o singoli IP da cui accettare l'header, es. "10.0.0.0/8".
+    /// Le connessioni da qui devono inviarlo; le altre sono trattate come client diretti.
+    pub trusted: Vec<String>,
+}
+
+/// Versione dell'header PROXY inviato ai backend
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyProtocolVersion {
+    /// Testuale: `PROXY TCP4 <client> <destinazione> <porta> <porta>\r\n`
+    V1,
+    /// Binario
+    V2,
+}
+
+/// Protocollo di un listener aggiuntivo
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListenerMode {
+    /// Proxy layer 4: ogni connessione viene collegata a un backend `tcp://host:porta`
+    Tcp,
+    /// Datagrammi verso backend `udp://host:porta`; ogni indirizzo client è un flusso
+    /// legato allo stesso backend finché resta attivo
+    Udp,
+}
+
+impl ListenerMode {
+    /// Schema degli url dei backend di questo tipo di listener
+    pub fn scheme(&self) -> &'static str {
+        match self {
+            ListenerMode::Tcp => "tcp",
+            ListenerMode::Udp => "udp",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ListenerConfig {
+    pub name: String,
+    pub mode: ListenerMode,
+    /// Indirizzo di ascolto, es. "0.0.0.0:5432"
+    pub bind: String,
+    #[serde(default = "default_listener_strategy")]
+    pub lb_strategy: LoadBalancingStrategy,
+    /// Connessione (o flusso UDP) senza traffico in nessuna direzione oltre cui viene chiusa;
+    /// se assente si usa `timeouts.tunnel_idle_ms` per tcp e 30 secondi per udp
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle_timeout_ms: Option<u64>,
+    /// Solo `mode: udp`: flussi aperti al massimo (default 10000); i datagrammi di nuovi
+    /// client oltre il limite vengono scartati finché qualche flusso non scade
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_flows: Option<usize>,
+    /// Solo `mode: tcp`: header PROXY inviato a ogni nuova connessione verso i backend,
+    /// così vedono l'indirizzo del client invece di quello del load balancer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub send_proxy_protocol: Option<ProxyProtocolVersion>,
+    /// Backend `tcp://host:porta` o `udp://host:porta` secondo `mode`. L'health check è
+    /// una connessione TCP entro `timeout_ms`; in UDP un datagramma vuoto, con il backend
+    /// sano a meno che non risponda con ICMP port unreachable.
+    pub backends: Vec<BackendConfig>,
+}
+
+/// Per le connessioni lunghe (database, cache) conta quante sono aperte su ogni backend
+fn default_listener_strategy() -> LoadBalancingStrategy {
+    LoadBalancingStrategy::LeastConnections
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutlierDetectionConfig {
+    /// Errori consecutivi (connessione, timeout, 5xx) che causano l'espulsione (0 = disattivo)
+    pub consecutive_errors: u32,
+    /// Percentuale di errori nella finestra che causa l'espulsione
+    pub error_rate_percent: u32,
+    /// Richieste minime nella finestra prima di valutare la percentuale
+    pub min_requests: u32,
+    /// Durata della finestra per la percentuale di errori
+    pub interval_secs: u64,
+    /// L'n-esima espulsione dura n volte questo valore, fino a `max_ejection_secs`
+    pub base_ejection_secs: u64,
+    pub max_ejection_secs: u64,
+    /// Quota massima dei backend espulsi contemporaneamente
+    pub max_ejection_percent: u32,
+}
+
+impl Default for OutlierDetectionConfig {
+    fn default() -> Self {
+        Self {
+            consecutive_errors: 5,
+            error_rate_percent: 50,
+            min_requests: 20,
+            interval_secs: 10,
+            base_ejection_secs: 30,
+            max_ejection_secs: 300,
+            max_ejection_percent: 50,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CircuitBreakerConfig {
+    /// Fallimenti consecutivi che aprono il circuito (0 = disattivo)
+    pub consecutive_failures: u32,
+    /// Percentuale di fallimenti nella finestra che apre il circuito
+    pub error_rate_percent: u32,
+    /// Richieste minime nella finestra prima di valutare la percentuale
+    pub min_requests: u32,
+    pub window_secs: u64,
+    /// Quanto resta aperto il circuito prima delle richieste di prova
+    pub open_secs: u64,
+    /// Richieste di prova in half-open: tutte devono riuscire per richiudere il circuito
+    pub half_open_requests: u32,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            consecutive_failures: 5,
+            error_rate_percent: 50,
+            min_requests: 20,
+            window_secs: 10,
+            open_secs: 30,
+            half_open_requests: 3,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryConfig {
+    /// Tentativi aggiuntivi per richiesta, ognuno su un backend diverso
+    pub max_retries: u32,
+    /// Status del backend che causano un nuovo tentativo (solo metodi idempotenti)
+    pub retry_on_status: Vec<u16>,
+    /// Corpo massimo tenuto in memoria per poter ripetere la richiesta
+    pub max_body_bytes: usize,
+    /// Retry massimi come percentuale delle richieste nella finestra
+    pub budget_percent: u32,
+    /// Retry sempre concessi nella finestra, anche con poco traffico
+    pub min_retries_per_window: u32,
+    pub budget_window_secs: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: 2,
+            retry_on_status: vec![502, 503, 504],
+            max_body_bytes: 64 * 1024,
+            budget_percent: 20,
+            min_retries_per_window: 10,
+            budget_window_secs: 10,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeoutConfig {
+    /// Apertura della connessione TCP (e TLS) verso il backend
+    pub connect_ms: u64,
+    /// Dall'invio della richiesta agli header della risposta
+    pub first_byte_ms: u64,
+    /// Durata massima di un tentativo, corpo della risposta compreso. Non vale per le
+    /// chiamate gRPC, che usano la scadenza `grpc-timeout` del client (anche per gli header)
+    pub request_ms: u64,
+    /// Pausa massima tra due blocchi del corpo della risposta (non per gli stream gRPC)
+    pub body_idle_ms: u64,
+    /// Tempo concesso al client per inviare gli header di una richiesta
+    pub header_read_ms: u64,
+    /// Una connessione client senza richieste viene chiusa dopo questo tempo
+    pub keep_alive_ms: u64,
+    /// Un tunnel (WebSocket o altro Upgrade) senza traffico in nessuna direzione viene chiuso dopo questo tempo
+    pub tunnel_idle_ms: u64,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        Self {
+            connect_ms: 5_000,
+            first_byte_ms: 30_000,
+            request_ms: 60_000,
+            body_idle_ms: 30_000,
+            header_read_ms: 10_000,
+            keep_alive_ms: 60_000,
+            tunnel_idle_ms: 300_000,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConcurrencyConfig {
+    /// Richieste servite contemporaneamente da tutti i listener
+    pub max_concurrent_requests: usize,
+    /// Richieste in attesa oltre il limite; le altre ricevono subito un 503
+    pub max_queue: usize,
+    /// Attesa massima in coda prima del 503
+    pub max_queue_ms: u64,
+    /// Valore dell'header `Retry-After` delle risposte 503 per sovraccarico
+    pub retry_after_secs: u64,
+}
+
+impl Default for ConcurrencyConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent_requests: 500,
+            max_queue: 500,
+            max_queue_ms: 1_000,
+            retry_after_secs: 1,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompressionConfig {
+    pub enabled: bool,
+    /// Codifiche offerte; a parità di q-value del client vince la prima della lista
+    pub algorithms: Vec<ContentCoding>,
+    /// Le risposte con `Content-Length` più piccolo restano non compresse
+    pub min_size: u64,
+    /// Livello gzip, 0-9
+    pub gzip_level: u32,
+    /// Livello deflate (zlib), 0-9
+    pub deflate_level: u32,
+    /// Qualità brotli, 0-11
+    pub brotli_level: u32,
+    /// Livello zstd, 1-22
+    pub zstd_level: i32,
+    /// Se non vuota si comprimono solo questi content-type ("text/*" vale per tutto il tipo)
+    pub content_types: Vec<String>,
+    /// Content-type mai compressi, anche se presenti in `content_types`
+    pub exclude_content_types: Vec<String>,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            algorithms: vec![ContentCoding::Brotli, ContentCoding::Zstd, ContentCoding::Gzip, ContentCoding::Deflate],
+            min_size: 150,
+            gzip_level: 6,
+            deflate_level: 6,
+            brotli_level: 4,
+            zstd_level: 3,
+            content_types: Vec::new(),
+            // Formati già compressi e stream di eventi, che vanno consegnati senza ritardi
+            exclude_content_types: [
+                "image/*",
+                "video/*",
+                "audio/*",
+                "font/woff",
+                "font/woff2",
+                "text/event-stream",
+                "application/octet-stream",
+                "application/zip",
+                "application/gzip",
+                "application/x-gzip",
+                "application/zstd",
+                "application/x-bzip2",
+                "application/x-xz",
+                "application/x-7z-compressed",
+                "application/x-rar-compressed",
+            ]
+            .map(String::from)
+            .to_vec(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// Dimensione massima della cache (corpi e header); oltre si scartano le risposte usate meno di recente
+    pub max_size_bytes: usize,
+    /// Le risposte più grandi non vengono memorizzate
+    pub max_entry_bytes: usize,
+    /// Richieste identiche contemporanee senza copia in cache fanno una sola richiesta al backend
+    pub coalesce_requests: bool,
+    /// Attesa massima della risposta condivisa, poi ogni richiesta prosegue da sola
+    pub coalesce_timeout_ms: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            max_size_bytes: 64 * 1024 * 1024,
+            max_entry_bytes: 1024 * 1024,
+            coalesce_requests: true,
+            coalesce_timeout_ms: 5_000,
+        }
+    }
+}
+
+/// Override per backend dei timeout verso i backend
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackendTimeouts {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connect_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_byte_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body_idle_ms: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct StickySessionConfig {
+    #[serde(default = "default_sticky_cookie_name")]
+    pub cookie_name: String,
+    /// Chiave HMAC per firmare il cookie; se assente ne viene generata una casuale all'avvio
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_age_secs: Option<u64>,
+}
+
+fn default_sticky_cookie_name() -> String {
+    "lb_backend".to_string()
 }
 
-#[derive(Debug, Deserialize, Clone)]
+// Debug a mano per non finire con il secret nei log
+impl std::fmt::Debug for StickySessionConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StickySessionConfig")
+            .field("cookie_name", &self.cookie_name)
+            .field("secret", &self.secret.as_ref().map(|_| "<redacted>"))
+            .field("max_age_secs", &self.max_age_secs)
+            .finish()
+    }
+}
+
+/// Peso massimo di un backend: tiene limitati gli slot delle tabelle di hashing
+pub const MAX_BACKEND_WEIGHT: u32 = 1_000;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct BackendConfig {
     pub name: String,
     pub url: String,
     pub weight: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health_check: Option<HealthCheckConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeouts: Option<BackendTimeouts>,
+    /// Richieste contemporanee massime verso il backend; oltre viene saltato dalle strategie
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_connections: Option<u32>,
+    /// Protocollo verso il backend: http1 (default), h2 (TLS con ALPN) o h2c (in chiaro)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<BackendProtocol>,
+}
+
+/// Health check attivo di un backend, eseguito direttamente contro il backend
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthCheckConfig {
+    pub path: String,
+    pub method: String,
+    /// Status attesi, singoli ("204") o intervalli ("200-299")
+    pub expected_status: Vec<String>,
+    /// Sottostringa che deve comparire nel body
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body_contains: Option<String>,
+    /// Regex che il body deve soddisfare
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body_regex: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    pub timeout_ms: u64,
+    /// Se assente si usa `health_check_interval`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interval_secs: Option<u64>,
+    /// Ritardo casuale massimo aggiunto ad ogni intervallo, per non sincronizzare i probe
+    pub jitter_ms: u64,
+    /// Probe riusciti consecutivi per tornare healthy
+    pub rise: u32,
+    /// Probe falliti consecutivi per diventare unhealthy
+    pub fall: u32,
+    /// Finestra in cui contare i cambi di stato
+    pub flap_window_secs: u64,
+    /// Cambi di stato nella finestra oltre i quali il backend è considerato instabile (0 = disattivo)
+    pub flap_threshold: u32,
+    /// Esclusione iniziale di un backend instabile, raddoppia ad ogni ulteriore cambio
+    pub flap_hold_secs: u64,
+    pub flap_max_hold_secs: u64,
+    /// Se presente il probe usa `grpc.health.v1.Health/Check` per questo servizio
+    /// ("" = stato dell'intero server) al posto di path, method ed expected_status
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grpc_service: Option<String>,
+}
+
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        Self {
+            path: "/".to_string(),
+            method: "GET".to_string(),
+            expected_status: vec!["200-299".to_string()],
+            body_contains: None,
+            body_regex: None,
+            headers: BTreeMap::new(),
+            timeout_ms: 3000,
+            interval_secs: None,
+            jitter_ms: 0,
+            rise: 2,
+            fall: 3,
+            flap_window_secs: 300,
+            flap_threshold: 4,
+            flap_hold_secs: 30,
+            flap_max_hold_secs: 600,
+            grpc_service: None,
+        }
+    }
+}
+
+impl HealthCheckConfig {
+    /// Intervalli di status attesi, `None` se una voce non è valida
+    pub fn status_ranges(&self) -> Option<Vec<RangeInclusive<u16>>> {
+        self.expected_status
+            .iter()
+            .map(|entry| {
+                let (start, end) = entry.split_once('-').unwrap_or((entry, entry));
+                let start: u16 = start.trim().parse().ok()?;
+                let end: u16 = end.trim().parse().ok()?;
+                ((100..=599).contains(&start) && (start..=599).contains(&end)).then_some(start..=end)
+            })
+            .collect()
+    }
 }
 
 impl Default for Config {
@@ -22,27 +538,67 @@ impl Default for Config {
         Self {
             host: "127.0.0.1".to_string(),
             port: 3000,
-            lb_strategy: "round_robin".to_string(),
+            h2c: false,
+            lb_strategy: LoadBalancingStrategy::RoundRobin,
+            hash_key: HashKey::default(),
             health_check_interval: 10,
             backends: vec![
                 BackendConfig {
                     name: "backend-1".to_string(),
                     url: "http://127.0.0.1:8081".to_string(),
                     weight: Some(1),
+                    health_check: None,
+                    timeouts: None,
+                    max_connections: None,
+                    protocol: None,
                 },
                 BackendConfig {
                     name: "backend-2".to_string(),
                     url: "http://127.0.0.1:8082".to_string(),
                     weight: Some(1),
+                    health_check: None,
+                    timeouts: None,
+                    max_connections: None,
+                    protocol: None,
                 },
             ],
+            sticky_session: None,
+            outlier_detection: None,
+            circuit_breaker: None,
+            retry: None,
+            timeouts: TimeoutConfig::default(),
+            concurrency: ConcurrencyConfig::default(),
+            compression: CompressionConfig::default(),
+            cache: None,
+            listeners: Vec::new(),
+            admin: AdminConfig::default(),
+            proxy_protocol: None,
         }
     }
 }
 impl Config {
+    /// Copia con i segreti (token admin, chiave dei cookie sticky) sostituiti, da mostrare all'utente
+    pub fn redacted(&self) -> Self {
+        let mut config = self.clone();
+        if let Some(token) = &mut config.admin.token {
+            *token = "<redacted>".to_string();
+        }
+        if let Some(secret) = config.sticky_session.as_mut().and_then(|sticky| sticky.secret.as_mut()) {
+            *secret = "<redacted>".to_string();
+        }
+        config
+    }
+
     pub fn from_file(path: &str) -> anyhow::Result<Self> {
-        let content = fs::read_to_string(path)?;
-        let config: Config = serde_yaml::from_str(&content)?;
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Cannot read config file {path}"))?;
+        let config: Config = serde_yaml::from_str(&content)
+            .map_err(|e| ConfigError::from_yaml(path, &content, e))?;
+
+        let issues = config.validate(&content);
+        if !issues.is_empty() {
+            return Err(ConfigError { path: path.to_string(), issues }.into());
+        }
         Ok(config)
     }
 
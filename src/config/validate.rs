@@ -0,0 +1,648 @@
+use super::{Config, HealthCheckConfig, ListenerConfig, ListenerMode, MAX_BACKEND_WEIGHT};
+use crate::backend::{BackendProtocol, HashKey};
+use crate::lb::proxy_protocol::TrustedSources;
+use hyper::Uri;
+use std::collections::HashSet;
+use std::fmt;
+use std::net::SocketAddr;
+
+/// Un problema nel file di config, con posizione (1-based) quando è possibile ricavarla
+#[derive(Debug, Clone)]
+pub struct ConfigIssue {
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+/// Tutti i problemi trovati in un file di config
+#[derive(Debug)]
+pub struct ConfigError {
+    pub path: String,
+    pub issues: Vec<ConfigIssue>,
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => write!(f, "{line}:{column}: {}", self.message),
+            (Some(line), None) => write!(f, "{line}: {}", self.message),
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid config {} ({} error(s))", self.path, self.issues.len())?;
+        for issue in &self.issues {
+            let separator = if issue.line.is_some() { ":" } else { ": " };
+            write!(f, "\n  {}{}{}", self.path, separator, issue)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl ConfigError {
+    /// Errore di parsing (chiave sconosciuta, tipo sbagliato...). Senza posizione da serde
+    /// si cerca la chiave nominata nel messaggio.
+    pub fn from_yaml(path: &str, source: &str, error: serde_yaml::Error) -> Self {
+        let mut message = error.to_string();
+        let issue = match error.location() {
+            Some(location) => {
+                // La posizione viene già stampata prima del messaggio
+                if let Some(suffix) = message.rfind(" at line ") {
+                    message.truncate(suffix);
+                }
+                ConfigIssue { line: Some(location.line()), column: Some(location.column()), message }
+            }
+            None => {
+                let key = message
+                    .split_once("unknown field `")
+                    .and_then(|(_, rest)| rest.split_once('`'))
+                    .map(|(key, _)| key.to_string());
+                match key {
+                    Some(key) => Locator::new(source).key(&key, message),
+                    None => ConfigIssue { line: None, column: None, message },
+                }
+            }
+        };
+        Self { path: path.to_string(), issues: vec![issue] }
+    }
+}
+
+impl Config {
+    /// Controlli semantici che serde non può fare. `source` è il testo YAML originale,
+    /// usato solo per indicare riga e colonna dei valori sbagliati.
+    pub fn validate(&self, source: &str) -> Vec<ConfigIssue> {
+        let locator = Locator::new(source);
+        let mut issues = Vec::new();
+
+        if format!("{}:{}", self.host, self.port).parse::<SocketAddr>().is_err() {
+            issues.push(locator.top_level("host", format!(
+                "host '{}' is not a valid IP address to listen on", self.host
+            )));
+        }
+
+        match &self.hash_key {
+            HashKey::Header(name) | HashKey::Cookie(name) if name.trim().is_empty() => {
+                issues.push(locator.top_level("hash_key", "hash_key header/cookie name must not be empty".to_string()));
+            }
+            HashKey::Header(name) if hyper::header::HeaderName::from_bytes(name.as_bytes()).is_err() => {
+                issues.push(locator.top_level("hash_key", format!("hash_key: '{name}' is not a valid header name")));
+            }
+            _ => {}
+        }
+
+        if let Some(sticky) = &self.sticky_session {
+            let is_token_char = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c);
+            if sticky.cookie_name.is_empty() || !sticky.cookie_name.chars().all(is_token_char) {
+                issues.push(locator.top_level("sticky_session", format!(
+                    "sticky_session: '{}' is not a valid cookie name", sticky.cookie_name
+                )));
+            }
+            if sticky.secret.as_ref().is_some_and(|secret| secret.len() < 16) {
+                issues.push(locator.top_level("sticky_session",
+                    "sticky_session: secret must be at least 16 characters".to_string()));
+            }
+        }
+
+        if let Some(outlier) = &self.outlier_detection {
+            let mut problems = Vec::new();
+            if outlier.error_rate_percent == 0 || outlier.error_rate_percent > 100 {
+                problems.push("error_rate_percent must be between 1 and 100");
+            }
+            if outlier.max_ejection_percent > 100 {
+                problems.push("max_ejection_percent must be between 0 and 100");
+            }
+            if outlier.interval_secs == 0 || outlier.base_ejection_secs == 0 {
+                problems.push("interval_secs and base_ejection_secs must be greater than 0");
+            }
+            if outlier.base_ejection_secs > outlier.max_ejection_secs {
+                problems.push("base_ejection_secs must not exceed max_ejection_secs");
+            }
+            for problem in problems {
+                issues.push(locator.top_level("outlier_detection", format!("outlier_detection: {problem}")));
+            }
+        }
+
+        if let Some(breaker) = &self.circuit_breaker {
+            let mut problems = Vec::new();
+            if breaker.error_rate_percent == 0 || breaker.error_rate_percent > 100 {
+                problems.push("error_rate_percent must be between 1 and 100");
+            }
+            if breaker.window_secs == 0 || breaker.open_secs == 0 {
+                problems.push("window_secs and open_secs must be greater than 0");
+            }
+            if breaker.half_open_requests == 0 {
+                problems.push("half_open_requests must be greater than 0");
+            }
+            for problem in problems {
+                issues.push(locator.top_level("circuit_breaker", format!("circuit_breaker: {problem}")));
+            }
+        }
+
+        if let Some(retry) = &self.retry {
+            if let Some(status) = retry.retry_on_status.iter().find(|status| !(100..=599).contains(*status)) {
+                issues.push(locator.top_level("retry", format!("retry: {status} is not a valid HTTP status")));
+            }
+            if retry.budget_window_secs == 0 {
+                issues.push(locator.top_level("retry", "retry: budget_window_secs must be greater than 0".to_string()));
+            }
+        }
+
+        let timeouts = &self.timeouts;
+        let global_timeouts = [
+            ("connect_ms", timeouts.connect_ms),
+            ("first_byte_ms", timeouts.first_byte_ms),
+            ("request_ms", timeouts.request_ms),
+            ("body_idle_ms", timeouts.body_idle_ms),
+            ("header_read_ms", timeouts.header_read_ms),
+            ("keep_alive_ms", timeouts.keep_alive_ms),
+            ("tunnel_idle_ms", timeouts.tunnel_idle_ms),
+        ];
+        for (name, value) in global_timeouts {
+            if value == 0 {
+                issues.push(locator.top_level("timeouts", format!("timeouts: {name} must be greater than 0")));
+            }
+        }
+
+        if self.concurrency.max_concurrent_requests == 0 {
+            issues.push(locator.top_level("concurrency",
+                "concurrency: max_concurrent_requests must be greater than 0".to_string()));
+        }
+
+        let compression = &self.compression;
+        let levels = [
+            ("gzip_level", compression.gzip_level <= 9, "0 and 9"),
+            ("deflate_level", compression.deflate_level <= 9, "0 and 9"),
+            ("brotli_level", compression.brotli_level <= 11, "0 and 11"),
+            ("zstd_level", (1..=22).contains(&compression.zstd_level), "1 and 22"),
+        ];
+        for (name, _, range) in levels.iter().filter(|(_, valid, _)| !valid) {
+            issues.push(locator.top_level("compression", format!("compression: {name} must be between {range}")));
+        }
+        for pattern in compression.content_types.iter().chain(&compression.exclude_content_types) {
+            if !is_media_range(pattern) {
+                issues.push(locator.top_level("compression", format!(
+                    "compression: '{pattern}' is not a content type (expected type/subtype or type/*)"
+                )));
+            }
+        }
+
+        if let Some(cache) = &self.cache {
+            if cache.max_size_bytes == 0 || cache.max_entry_bytes == 0 {
+                issues.push(locator.top_level("cache",
+                    "cache: max_size_bytes and max_entry_bytes must be greater than 0".to_string()));
+            } else if cache.max_entry_bytes > cache.max_size_bytes {
+                issues.push(locator.top_level("cache", "cache: max_entry_bytes must not exceed max_size_bytes".to_string()));
+            }
+            if cache.coalesce_timeout_ms == 0 {
+                issues.push(locator.top_level("cache", "cache: coalesce_timeout_ms must be greater than 0".to_string()));
+            }
+        }
+
+        if self.admin.bind.parse::<SocketAddr>().is_err() {
+            issues.push(locator.top_level("admin",
+                format!("admin: bind '{}' is not a valid address (expected ip:port)", self.admin.bind)));
+        }
+        if self.admin.token.as_ref().is_some_and(|token| token.trim().is_empty()) {
+            issues.push(locator.top_level("admin", "admin: token must not be empty".to_string()));
+        }
+
+        if let Some(proxy_protocol) = &self.proxy_protocol {
+            if proxy_protocol.trusted.is_empty() {
+                issues.push(locator.top_level("proxy_protocol",
+                    "proxy_protocol: trusted must list at least one source".to_string()));
+            }
+            if let Err(message) = TrustedSources::parse(&proxy_protocol.trusted) {
+                issues.push(locator.top_level("proxy_protocol", format!("proxy_protocol: trusted: {message}")));
+            }
+        }
+
+        let mut listener_names = HashSet::new();
+        for listener in &self.listeners {
+            if !listener_names.insert(listener.name.as_str()) {
+                issues.push(locator.top_level("listeners", format!("listeners: duplicate listener name '{}'", listener.name)));
+            }
+            for problem in validate_listener(listener) {
+                issues.push(locator.top_level("listeners", format!("listeners: '{}': {problem}", listener.name)));
+            }
+        }
+
+        if self.health_check_interval == 0 {
+            issues.push(locator.top_level("health_check_interval",
+                "health_check_interval must be greater than 0".to_string()));
+        }
+
+        if self.backends.is_empty() {
+            issues.push(locator.top_level("backends", "at least one backend is required".to_string()));
+        }
+
+        let mut names = HashSet::new();
+        for (index, backend) in self.backends.iter().enumerate() {
+            if backend.name.trim().is_empty() {
+                issues.push(locator.backend(index, "name", "backend name must not be empty".to_string()));
+            } else if !names.insert(backend.name.as_str()) {
+                issues.push(locator.backend(index, "name", format!(
+                    "duplicate backend name '{}'", backend.name
+                )));
+            }
+
+            if let Err(message) = validate_backend_url(&backend.url) {
+                issues.push(locator.backend(index, "url", format!(
+                    "backend '{}': invalid url '{}': {message}", backend.name, backend.url
+                )));
+            }
+
+            let scheme = backend.url.split_once("://").map(|(scheme, _)| scheme);
+            match (backend.protocol, scheme) {
+                (Some(BackendProtocol::H2), Some("http")) => issues.push(locator.backend(index, "protocol", format!(
+                    "backend '{}': protocol h2 requires an https url (use h2c for plain HTTP/2)", backend.name
+                ))),
+                (Some(BackendProtocol::H2c), Some("https")) => issues.push(locator.backend(index, "protocol", format!(
+                    "backend '{}': protocol h2c requires an http url (use h2 over TLS)", backend.name
+                ))),
+                _ => {}
+            }
+
+            if let Some(health_check) = &backend.health_check {
+                if health_check.grpc_service.is_some() && !backend.protocol.is_some_and(|protocol| protocol.is_http2()) {
+                    issues.push(locator.backend(index, "health_check", format!(
+                        "backend '{}': health_check: grpc_service requires protocol h2 or h2c", backend.name
+                    )));
+                }
+                for message in validate_health_check(health_check) {
+                    issues.push(locator.backend(index, "health_check", format!(
+                        "backend '{}': health_check: {message}", backend.name
+                    )));
+                }
+            }
+
+            if let Some(timeouts) = &backend.timeouts {
+                let overrides = [
+                    ("connect_ms", timeouts.connect_ms),
+                    ("first_byte_ms", timeouts.first_byte_ms),
+                    ("request_ms", timeouts.request_ms),
+                    ("body_idle_ms", timeouts.body_idle_ms),
+                ];
+                for (name, _) in overrides.iter().filter(|(_, value)| *value == Some(0)) {
+                    issues.push(locator.backend(index, "timeouts", format!(
+                        "backend '{}': timeouts: {name} must be greater than 0", backend.name
+                    )));
+                }
+            }
+
+            if backend.max_connections == Some(0) {
+                issues.push(locator.backend(index, "max_connections", format!(
+                    "backend '{}': max_connections must be greater than 0", backend.name
+                )));
+            }
+
+            if backend.weight == Some(0) {
+                issues.push(locator.backend(index, "weight", format!(
+                    "backend '{}': weight must be greater than 0", backend.name
+                )));
+            } else if backend.weight.is_some_and(|weight| weight > MAX_BACKEND_WEIGHT) {
+                issues.push(locator.backend(index, "weight", format!(
+                    "backend '{}': weight must be at most {MAX_BACKEND_WEIGHT}", backend.name
+                )));
+            }
+        }
+
+        issues
+    }
+}
+
+fn validate_listener(listener: &ListenerConfig) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    if listener.name.trim().is_empty() {
+        problems.push("name must not be empty".to_string());
+    }
+    if listener.bind.parse::<SocketAddr>().is_err() {
+        problems.push(format!("bind '{}' is not a valid address (expected ip:port)", listener.bind));
+    }
+    if listener.send_proxy_protocol.is_some() && listener.mode != ListenerMode::Tcp {
+        problems.push("send_proxy_protocol only applies to tcp listeners".to_string());
+    }
+    if listener.max_flows.is_some() && listener.mode != ListenerMode::Udp {
+        problems.push("max_flows only applies to udp listeners".to_string());
+    }
+    if listener.max_flows == Some(0) {
+        problems.push("max_flows must be greater than 0".to_string());
+    }
+    if listener.idle_timeout_ms == Some(0) {
+        problems.push("idle_timeout_ms must be greater than 0".to_string());
+    }
+    if listener.backends.is_empty() {
+        problems.push("at least one backend is required".to_string());
+    }
+
+    let mut names = HashSet::new();
+    for backend in &listener.backends {
+        if !names.insert(backend.name.as_str()) {
+            problems.push(format!("duplicate backend name '{}'", backend.name));
+        }
+        match layer4_backend_address(&backend.url) {
+            Ok((mode, _)) if mode == listener.mode => {}
+            Ok(_) => problems.push(format!(
+                "backend '{}': invalid url '{}': expected {}://host:port",
+                backend.name, backend.url, listener.mode.scheme()
+            )),
+            Err(message) => problems.push(format!("backend '{}': invalid url '{}': {message}", backend.name, backend.url)),
+        }
+        if backend.weight == Some(0) || backend.max_connections == Some(0) {
+            problems.push(format!("backend '{}': weight and max_connections must be greater than 0", backend.name));
+        }
+        if backend.weight.is_some_and(|weight| weight > MAX_BACKEND_WEIGHT) {
+            problems.push(format!("backend '{}': weight must be at most {MAX_BACKEND_WEIGHT}", backend.name));
+        }
+        if backend.protocol.is_some() {
+            problems.push(format!("backend '{}': protocol only applies to HTTP backends", backend.name));
+        }
+        if let Some(health_check) = &backend.health_check {
+            if health_check.timeout_ms == 0 || health_check.interval_secs == Some(0) {
+                problems.push(format!("backend '{}': health_check: timeout_ms and interval_secs must be greater than 0", backend.name));
+            }
+            if health_check.rise == 0 || health_check.fall == 0 {
+                problems.push(format!("backend '{}': health_check: rise and fall must be greater than 0", backend.name));
+            }
+            if health_check.grpc_service.is_some() {
+                problems.push(format!("backend '{}': health_check: grpc_service only applies to HTTP backends", backend.name));
+            }
+        }
+    }
+
+    problems
+}
+
+/// Modalità e indirizzo `host:porta` di un backend `tcp://host:porta` o `udp://host:porta`
+pub fn layer4_backend_address(url: &str) -> Result<(ListenerMode, &str), String> {
+    let (mode, address) = match url.split_once("://") {
+        Some(("tcp", address)) => (ListenerMode::Tcp, address),
+        Some(("udp", address)) => (ListenerMode::Udp, address),
+        _ => return Err("expected tcp://host:port or udp://host:port".to_string()),
+    };
+    match address.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok_and(|port| port > 0) => Ok((mode, address)),
+        _ => Err(format!("expected {}://host:port", mode.scheme())),
+    }
+}
+
+fn validate_health_check(health_check: &HealthCheckConfig) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    if !health_check.path.starts_with('/') {
+        problems.push(format!("path '{}' must start with '/'", health_check.path));
+    }
+    if hyper::Method::from_bytes(health_check.method.as_bytes()).is_err() {
+        problems.push(format!("invalid method '{}'", health_check.method));
+    }
+    match health_check.status_ranges() {
+        None => problems.push(format!(
+            "invalid expected_status {:?} (use codes like \"200\" or ranges like \"200-299\")",
+            health_check.expected_status
+        )),
+        Some(ranges) if ranges.is_empty() => problems.push("expected_status must not be empty".to_string()),
+        Some(_) => {}
+    }
+    if let Some(pattern) = &health_check.body_regex {
+        if let Err(e) = regex::Regex::new(pattern) {
+            problems.push(format!("invalid body_regex: {e}"));
+        }
+    }
+    for (name, value) in &health_check.headers {
+        if hyper::header::HeaderName::from_bytes(name.as_bytes()).is_err()
+            || hyper::header::HeaderValue::from_str(value).is_err()
+        {
+            problems.push(format!("invalid header '{name}'"));
+        }
+    }
+    if health_check.timeout_ms == 0 {
+        problems.push("timeout_ms must be greater than 0".to_string());
+    }
+    if health_check.rise == 0 || health_check.fall == 0 {
+        problems.push("rise and fall must be greater than 0".to_string());
+    }
+    if health_check.flap_hold_secs > health_check.flap_max_hold_secs {
+        problems.push("flap_hold_secs must not exceed flap_max_hold_secs".to_string());
+    }
+    if health_check.interval_secs == Some(0) {
+        problems.push("interval_secs must be greater than 0".to_string());
+    }
+
+    problems
+}
+
+fn validate_backend_url(url: &str) -> Result<(), String> {
+    let uri: Uri = url.parse().map_err(|e: hyper::http::uri::InvalidUri| e.to_string())?;
+
+    match uri.scheme_str() {
+        Some("http") | Some("https") => {}
+        Some(other) => return Err(format!("unsupported scheme '{other}'")),
+        None => return Err("missing scheme (http:// or https://)".to_string()),
+    }
+    if uri.host().is_none_or(str::is_empty) {
+        return Err("missing host".to_string());
+    }
+    Ok(())
+}
+
+/// "tipo/sottotipo" o "tipo/*", come negli elenchi di content-type della compressione
+fn is_media_range(pattern: &str) -> bool {
+    let is_token = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c));
+    match pattern.split_once('/') {
+        Some((kind, subtype)) => kind != "*" && is_token(kind) && (subtype == "*" || is_token(subtype)),
+        None => false,
+    }
+}
+
+/// Ricava la posizione delle chiavi nel YAML a blocchi. Per YAML in stile flow
+/// (o chiavi assenti) ripiega sulla riga della sezione più vicina.
+struct Locator<'a> {
+    lines: Vec<&'a str>,
+    /// Riga (0-based) di inizio di ogni elemento sotto `backends:`
+    backend_starts: Vec<usize>,
+    /// Prima riga dopo la sezione `backends:`
+    backends_end: usize,
+}
+
+impl<'a> Locator<'a> {
+    fn new(source: &'a str) -> Self {
+        let lines: Vec<&str> = source.lines().collect();
+        let mut backend_starts = Vec::new();
+        let mut backends_end = lines.len();
+
+        if let Some(section) = lines.iter().position(|l| l.starts_with("backends:")) {
+            // Solo i trattini alla stessa indentazione del primo elemento:
+            // liste annidate dentro un backend non contano
+            let mut item_indent = None;
+            for (i, line) in lines.iter().enumerate().skip(section + 1) {
+                let trimmed = line.trim_start();
+                if !line.is_empty() && !line.starts_with([' ', '-', '#']) {
+                    backends_end = i;
+                    break;
+                }
+                if trimmed.starts_with("- ") || trimmed == "-" {
+                    let indent = line.len() - trimmed.len();
+                    if *item_indent.get_or_insert(indent) == indent {
+                        backend_starts.push(i);
+                    }
+                }
+            }
+        }
+
+        Self { lines, backend_starts, backends_end }
+    }
+
+    fn top_level(&self, key: &str, message: String) -> ConfigIssue {
+        let prefix = format!("{key}:");
+        let found = self.lines.iter().position(|l| l.starts_with(&prefix));
+        self.issue_at(found, key, message)
+    }
+
+    /// Prima occorrenza di `key:` a qualsiasi livello, anche come primo campo di un elemento di lista
+    fn key(&self, key: &str, message: String) -> ConfigIssue {
+        let prefix = format!("{key}:");
+        let found = self
+            .lines
+            .iter()
+            .position(|l| l.trim_start().trim_start_matches("- ").trim_start().starts_with(&prefix));
+        let mut issue = self.issue_at(found, key, message);
+        // Punta alla chiave, non al valore
+        issue.column = found.and_then(|l| self.lines[l].find(&prefix)).map(|c| c + 1);
+        issue
+    }
+
+    fn backend(&self, index: usize, key: &str, message: String) -> ConfigIssue {
+        let Some(&start) = self.backend_starts.get(index) else {
+            return self.top_level("backends", message);
+        };
+        let end = self.backend_starts.get(index + 1).copied().unwrap_or(self.backends_end);
+
+        let found = (start..end).find(|&i| {
+            let trimmed = self.lines[i].trim_start().trim_start_matches("- ").trim_start();
+            trimmed.starts_with(&format!("{key}:"))
+        });
+        match found {
+            Some(line) => self.issue_at(Some(line), key, message),
+            None => self.issue_at(Some(start), "-", message),
+        }
+    }
+
+    fn issue_at(&self, line: Option<usize>, key: &str, message: String) -> ConfigIssue {
+        let column = line.and_then(|l| {
+            let text = self.lines[l];
+            // Punta al valore se presente, altrimenti alla chiave
+            let key_col = text.find(key)?;
+            let after_key = key_col + key.len();
+            let value_col = text[after_key..]
+                .find(|c: char| c != ':' && !c.is_whitespace())
+                .map(|offset| after_key + offset);
+            Some(value_col.unwrap_or(key_col) + 1)
+        });
+
+        ConfigIssue {
+            line: line.map(|l| l + 1),
+            column,
+            message,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "host: \"127.0.0.1\"\nport: 3000\nlb_strategy: round_robin\nhealth_check_interval: 10\n";
+
+    /// Problemi di un file di config, come li riporta `Config::from_file`
+    fn issues(yaml: &str) -> Vec<ConfigIssue> {
+        match serde_yaml::from_str::<Config>(yaml) {
+            Ok(config) => config.validate(yaml),
+            Err(e) => ConfigError::from_yaml("config.yaml", yaml, e).issues,
+        }
+    }
+
+    /// Unico problema atteso: (riga, colonna, messaggio)
+    fn single(yaml: &str) -> (Option<usize>, Option<usize>, String) {
+        let issues = issues(yaml);
+        assert_eq!(issues.len(), 1, "{issues:?}");
+        let issue = issues.into_iter().next().unwrap();
+        (issue.line, issue.column, issue.message)
+    }
+
+    #[test]
+    fn valid_config_has_no_issues() {
+        let yaml = format!("{HEADER}backends:\n  - name: a\n    url: \"http://127.0.0.1:8081\"\n    weight: 2\n");
+        assert!(issues(&yaml).is_empty());
+    }
+
+    #[test]
+    fn duplicate_backend_name() {
+        let yaml = format!(
+            "{HEADER}backends:\n  - name: a\n    url: \"http://127.0.0.1:8081\"\n  - name: a\n    url: \"http://127.0.0.1:8082\"\n"
+        );
+        assert_eq!(single(&yaml), (Some(8), Some(11), "duplicate backend name 'a'".to_string()));
+    }
+
+    #[test]
+    fn zero_weight() {
+        let yaml = format!("{HEADER}backends:\n  - name: a\n    url: \"http://127.0.0.1:8081\"\n    weight: 0\n");
+        assert_eq!(single(&yaml), (Some(8), Some(13), "backend 'a': weight must be greater than 0".to_string()));
+    }
+
+    #[test]
+    fn bad_backend_url() {
+        let yaml = format!("{HEADER}backends:\n  - name: a\n    url: \"ftp://127.0.0.1\"\n");
+        assert_eq!(
+            single(&yaml),
+            (Some(7), Some(10), "backend 'a': invalid url 'ftp://127.0.0.1': unsupported scheme 'ftp'".to_string())
+        );
+
+        // Primo campo dell'elemento: la riga del trattino
+        let yaml = format!("{HEADER}backends:\n  - url: \"127.0.0.1:8081\"\n    name: a\n");
+        let (line, column, message) = single(&yaml);
+        assert_eq!((line, column), (Some(6), Some(10)));
+        assert!(message.starts_with("backend 'a': invalid url '127.0.0.1:8081'"), "{message}");
+    }
+
+    #[test]
+    fn unknown_strategy() {
+        let yaml = "host: \"127.0.0.1\"\nport: 3000\nlb_strategy: fastest\nbackends: []\n";
+        let (line, column, message) = single(yaml);
+        assert_eq!((line, column), (Some(3), Some(14)));
+        assert!(message.starts_with("lb_strategy: unknown variant `fastest`"), "{message}");
+        assert!(!message.contains(" at line "), "{message}");
+    }
+
+    #[test]
+    fn misspelled_keys_fail() {
+        let yaml = format!("{HEADER}backends:\n  - name: a\n    url: \"http://127.0.0.1:8081\"\n    weigth: 2\n");
+        let (line, column, message) = single(&yaml);
+        assert_eq!((line, column), (Some(8), Some(5)));
+        assert!(message.starts_with("backends[0]: unknown field `weigth`"), "{message}");
+
+        let yaml = format!("{HEADER}concurrency:\n  max_queue_msec: 10\nbackends: []\n");
+        let (line, column, message) = single(&yaml);
+        assert_eq!((line, column), (Some(6), Some(3)));
+        assert!(message.starts_with("concurrency: unknown field `max_queue_msec`"), "{message}");
+    }
+
+    #[test]
+    fn locator_finds_nested_keys() {
+        let yaml = "backends:\n  - name: a\n    health_check:\n      timeout_ms: 5\nlisteners: []\n";
+        let locator = Locator::new(yaml);
+        let issue = locator.key("timeout_ms", String::new());
+        assert_eq!((issue.line, issue.column), (Some(4), Some(7)));
+        let issue = locator.backend(0, "health_check", String::new());
+        assert_eq!((issue.line, issue.column), (Some(3), Some(5)));
+        // Backend inesistente: la sezione
+        let issue = locator.backend(1, "url", String::new());
+        assert_eq!((issue.line, issue.column), (Some(1), Some(1)));
+        let issue = locator.top_level("listeners", String::new());
+        assert_eq!((issue.line, issue.column), (Some(5), Some(12)));
+    }
+}
@@ -0,0 +1,110 @@
+use super::Config;
+use crate::backend::BackendPool;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tracing::{error, info, warn};
+
+/// Ogni quanto controllare se il file di config è stato modificato
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Osserva il file di config (e SIGHUP) e applica al pool le modifiche ai backend
+/// senza riavviare il processo.
+pub struct ConfigWatcher {
+    path: PathBuf,
+    pool: BackendPool,
+    last_modified: Option<SystemTime>,
+}
+
+impl ConfigWatcher {
+    pub fn new(path: impl Into<PathBuf>, pool: BackendPool) -> Self {
+        let path = path.into();
+        let last_modified = Self::modified_time(&path);
+
+        Self {
+            path,
+            pool,
+            last_modified,
+        }
+    }
+
+    pub async fn start(self) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            self.run().await;
+        })
+    }
+
+    #[cfg(unix)]
+    async fn run(mut self) {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                warn!("Cannot listen for SIGHUP, falling back to file polling only: {}", e);
+                loop {
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                    self.reload_if_changed();
+                }
+            }
+        };
+
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(POLL_INTERVAL) => self.reload_if_changed(),
+                _ = hangup.recv() => {
+                    info!("SIGHUP received, reloading {}", self.path.display());
+                    self.last_modified = Self::modified_time(&self.path);
+                    self.reload();
+                }
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    async fn run(mut self) {
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            self.reload_if_changed();
+        }
+    }
+
+    fn reload_if_changed(&mut self) {
+        let modified = Self::modified_time(&self.path);
+        if modified.is_some() && modified != self.last_modified {
+            self.last_modified = modified;
+            info!("Config file {} changed, reloading", self.path.display());
+            self.reload();
+        }
+    }
+
+    fn reload(&self) {
+        // Una config rotta non deve toccare i backend attivi
+        let config = match Config::from_file(&self.path.to_string_lossy()) {
+            Ok(config) => config,
+            Err(e) => {
+                error!("Config reload failed, keeping current backends: {}", e);
+                return;
+            }
+        };
+
+        let summary = self.pool.reconcile(&config.backends);
+        if summary.is_empty() {
+            info!("Config reloaded, backends unchanged");
+            return;
+        }
+
+        for name in &summary.added {
+            info!("Backend {} added (joins rotation after its first health check)", name);
+        }
+        for name in &summary.updated {
+            info!("Backend {} updated", name);
+        }
+        for name in &summary.removed {
+            info!("Backend {} removed", name);
+        }
+    }
+
+    fn modified_time(path: &Path) -> Option<SystemTime> {
+        std::fs::metadata(path).and_then(|meta| meta.modified()).ok()
+    }
+}
@@ -1,9 +1,149 @@
 use crate::backend::pool::BackendPool;
 use crate::backend::*;
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicI64, Ordering};
 use crate::backend::pool::BackendState;
 use std::sync::Arc;
-use std::sync::atomic::AtomicU32;
+use std::hash::{Hash, Hasher};
+use std::collections::hash_map::DefaultHasher;
+use crate::config::MAX_BACKEND_WEIGHT;
+
+/// Nodi virtuali sull'anello per ogni unità di peso
+const RING_VNODES_PER_WEIGHT: u32 = 100;
+/// Dimensione della lookup table Maglev (primo, molto più grande del numero di backend)
+const MAGLEV_TABLE_SIZE: usize = 65537;
+
+/// Tabella di lookup per il consistent hashing, costruita sui backend healthy
+/// e ricostruita solo quando l'insieme dei backend healthy cambia.
+#[derive(Debug)]
+pub struct ConsistentHashTable {
+    backends_hash: u64,
+    lookup: HashLookup,
+}
+
+#[derive(Debug)]
+enum HashLookup {
+    /// Punti dell'anello ordinati: (posizione, indice nel vettore healthy)
+    Ring(Vec<(u64, usize)>),
+    /// Tabella Maglev: slot -> indice nel vettore healthy
+    Maglev(Vec<usize>),
+}
+
+/// Stato dello smooth weighted round robin per un insieme di backend healthy.
+/// Lock-free: i punteggi sono atomici e la somma resta invariata anche con scelte
+/// concorrenti, quindi le proporzioni tra i pesi vengono rispettate nel lungo periodo.
+#[derive(Debug)]
+pub struct SmoothWeights {
+    backends_hash: u64,
+    weights: Vec<i64>,
+    total: i64,
+    current: Vec<AtomicI64>,
+}
+
+impl SmoothWeights {
+    fn new(healthy: &[Arc<BackendState>], backends_hash: u64) -> Self {
+        let weights: Vec<i64> = healthy.iter().map(|bs| bs.backend.weight.max(1) as i64).collect();
+        Self {
+            backends_hash,
+            total: weights.iter().sum(),
+            current: weights.iter().map(|_| AtomicI64::new(0)).collect(),
+            weights,
+        }
+    }
+
+    /// Indice (nel vettore healthy) del prossimo backend
+    fn next(&self) -> usize {
+        let mut best = 0;
+        let mut best_score = i64::MIN;
+
+        for (idx, weight) in self.weights.iter().enumerate() {
+            let score = self.current[idx].fetch_add(*weight, Ordering::Relaxed) + weight;
+            // A parità vince il primo, come in nginx
+            if score > best_score {
+                best = idx;
+                best_score = score;
+            }
+        }
+
+        self.current[best].fetch_sub(self.total, Ordering::Relaxed);
+        best
+    }
+}
+
+pub fn hash_of<T: Hash + ?Sized>(value: &T) -> u64 {
+    // DefaultHasher::new() usa chiavi fisse: stesso input, stesso hash
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl ConsistentHashTable {
+    fn ring(healthy: &[Arc<BackendState>], backends_hash: u64) -> Self {
+        let mut ring: Vec<(u64, usize)> = healthy
+            .iter()
+            .enumerate()
+            .flat_map(|(idx, bs)| {
+                let vnodes = bs.backend.weight.clamp(1, MAX_BACKEND_WEIGHT).saturating_mul(RING_VNODES_PER_WEIGHT);
+                (0..vnodes).map(move |vnode| (hash_of(&(bs.backend.name.as_str(), vnode)), idx))
+            })
+            .collect();
+        ring.sort_unstable();
+
+        Self { backends_hash, lookup: HashLookup::Ring(ring) }
+    }
+
+    fn maglev(healthy: &[Arc<BackendState>], backends_hash: u64) -> Self {
+        let size = MAGLEV_TABLE_SIZE;
+        // Permutazione di ogni backend: offset + j * skip (mod size)
+        let permutations: Vec<(usize, usize)> = healthy
+            .iter()
+            .map(|bs| {
+                let offset = hash_of(&(bs.backend.name.as_str(), "offset")) as usize % size;
+                let skip = hash_of(&(bs.backend.name.as_str(), "skip")) as usize % (size - 1) + 1;
+                (offset, skip)
+            })
+            .collect();
+
+        let mut table = vec![usize::MAX; size];
+        let mut next = vec![0usize; healthy.len()];
+        let mut filled = 0;
+
+        'fill: loop {
+            for (idx, bs) in healthy.iter().enumerate() {
+                // Un backend con peso w occupa w slot per giro
+                for _ in 0..bs.backend.weight.max(1) {
+                    let (offset, skip) = permutations[idx];
+                    let mut slot = (offset + next[idx] * skip) % size;
+                    while table[slot] != usize::MAX {
+                        next[idx] += 1;
+                        slot = (offset + next[idx] * skip) % size;
+                    }
+                    table[slot] = idx;
+                    next[idx] += 1;
+                    filled += 1;
+                    if filled == size {
+                        break 'fill;
+                    }
+                }
+            }
+        }
+
+        Self { backends_hash, lookup: HashLookup::Maglev(table) }
+    }
+
+    fn lookup(&self, key: u64) -> Option<usize> {
+        match &self.lookup {
+            HashLookup::Ring(ring) => {
+                if ring.is_empty() {
+                    return None;
+                }
+                // Primo punto in senso orario, con wrap-around
+                let pos = ring.partition_point(|&(point, _)| point < key);
+                Some(ring[pos % ring.len()].1)
+            }
+            HashLookup::Maglev(table) => table.get(key as usize % table.len()).copied(),
+        }
+    }
+}
 
 impl BackendPool {
 
@@ -19,7 +159,8 @@ impl BackendPool {
             |current| Some((current + 1) % healthy.len())
         ).ok()?;
 
-        Some(healthy[idx].clone())
+        // Il contatore può venire da un insieme più grande (retry, backend saturi)
+        Some(healthy[idx % healthy.len()].clone())
     }
 
     pub async fn least_connections_select(&self, healthy: &[Arc<BackendState>]) -> Option<Arc<BackendState>> {
@@ -35,9 +176,6 @@ impl BackendPool {
     }
 
     fn compute_healthy_backends_hash(&self, healthy: &[Arc<BackendState>]) -> u64 {
-        use std::hash::{Hash, Hasher};
-        use std::collections::hash_map::DefaultHasher;
-
         let mut hasher = DefaultHasher::new();
         for bs in healthy {
             bs.backend.name.hash(&mut hasher);
@@ -46,44 +184,78 @@ impl BackendPool {
         hasher.finish()
     }
 
-    pub async fn weighted_round_robin_select(&self, healthy: &[Arc<BackendState>]) -> Option<Arc<BackendState>> {
+    /// Smooth weighted round robin (come nginx): ad ogni scelta ogni backend guadagna
+    /// il proprio peso, vince quello con il punteggio più alto che poi perde il peso totale.
+    /// Le scelte sono interlacciate (5:1:1 -> a a b a c a a) e la memoria non dipende dai pesi.
+    pub fn weighted_round_robin_select(&self, healthy: &[Arc<BackendState>]) -> Option<Arc<BackendState>> {
         if healthy.is_empty() {
             return None;
         }
 
-        let mut wrr_state = self.weighted_rr_state.write().await;
-
-        // Check if we need to recompute (weights or backends changed)
         let current_hash = self.compute_healthy_backends_hash(healthy);
-        if wrr_state.last_backends_hash != current_hash {
-            // Recompute expanded list
-            wrr_state.expanded_list.clear();
-            for backend_state in healthy {
-                for _ in 0..backend_state.backend.weight {
-                    wrr_state.expanded_list.push(backend_state.backend.name.clone());
-                }
+        let weights = match self.smooth_wrr.load_full() {
+            Some(weights) if weights.backends_hash == current_hash => weights,
+            _ => {
+                let weights = Arc::new(SmoothWeights::new(healthy, current_hash));
+                self.smooth_wrr.store(Some(weights.clone()));
+                weights
             }
-            wrr_state.last_backends_hash = current_hash;
-            wrr_state.generation.fetch_add(1, Ordering::SeqCst);
-        }
+        };
+
+        healthy.get(weights.next()).cloned()
+    }
 
-        if wrr_state.expanded_list.is_empty() {
+    /// Consistent hashing: la stessa chiave finisce sullo stesso backend finché resta healthy.
+    /// Senza chiave (es. header assente) si ripiega su una scelta casuale.
+    pub fn consistent_hash_select(&self, healthy: &[Arc<BackendState>], key: Option<u64>) -> Option<Arc<BackendState>> {
+        if healthy.is_empty() {
             return None;
         }
+        let Some(key) = key else {
+            return self.random_select(healthy);
+        };
 
-        let idx = self.round_robin_idx.fetch_update(
-            Ordering::SeqCst,
-            Ordering::SeqCst,
-            |current| Some((current + 1) % wrr_state.expanded_list.len())
-        ).ok()?;
+        let current_hash = self.compute_healthy_backends_hash(healthy);
+        let cached = self.hash_table.load_full();
+
+        let table = match cached {
+            Some(table) if table.backends_hash == current_hash => table,
+            _ => {
+                let table = Arc::new(match self.strategy {
+                    LoadBalancingStrategy::Maglev => ConsistentHashTable::maglev(healthy, current_hash),
+                    _ => ConsistentHashTable::ring(healthy, current_hash),
+                });
+                self.hash_table.store(Some(table.clone()));
+                table
+            }
+        };
 
-        let selected_name = &wrr_state.expanded_list[idx];
+        table.lookup(key).and_then(|idx| healthy.get(idx)).cloned()
+    }
 
-        healthy
-            .iter()
-            .find(|bs| bs.backend.name == *selected_name)
-            .cloned()
+    /// Power of two choices: due backend a caso, vince quello con il costo minore.
+    /// Con `connections` come costo è P2C classico, con `ewma_cost` è peak-EWMA.
+    pub fn p2c_select<F>(&self, healthy: &[Arc<BackendState>], cost: F) -> Option<Arc<BackendState>>
+    where
+        F: Fn(&BackendState) -> f64,
+    {
+        use rand::Rng;
+
+        match healthy.len() {
+            0 => None,
+            1 => Some(healthy[0].clone()),
+            len => {
+                let mut rng = rand::thread_rng();
+                let first = rng.gen_range(0..len);
+                // Secondo indice diverso dal primo
+                let second = (first + rng.gen_range(1..len)) % len;
+
+                let (a, b) = (&healthy[first], &healthy[second]);
+                if cost(b) < cost(a) { Some(b.clone()) } else { Some(a.clone()) }
+            }
+        }
     }
+
     pub fn random_select(&self, healthy: &[Arc<BackendState>]) -> Option<Arc<BackendState>> {
         use rand::Rng;
 
@@ -92,36 +264,191 @@ impl BackendPool {
         }
 
         let idx = rand::thread_rng().gen_range(0..healthy.len());
-        Some(healthy[idx].clone())
+        Some(healthy[idx % healthy.len()].clone())
     }
 
-    pub async fn update_backend_status(&self, index: usize, status: BackendStatus) -> bool {
-        let current_state = self.state.load();
+    pub async fn update_backend_status(&self, name: &str, status: BackendStatus) -> bool {
+        let mut found = false;
 
-        if index >= current_state.len() {
-            return false;
-        }
+        // rcu: un reload della config concorrente non viene sovrascritto
+        self.state.rcu(|current_state| {
+            found = false;
+            current_state
+                .iter()
+                .map(|backend_state| {
+                    if backend_state.backend.name == name {
+                        found = true;
+                        // Update this backend's status
+                        Arc::new(backend_state.rebuild(backend_state.backend.clone(), status))
+                    } else {
+                        backend_state.clone()
+                    }
+                })
+                .collect::<Vec<Arc<BackendState>>>()
+        });
+
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        let new_backends_state: Vec<Arc<BackendState>> = current_state
+    fn pool(weights: &[(&str, u32)]) -> (BackendPool, Vec<Arc<BackendState>>) {
+        let backends = weights
             .iter()
-            .enumerate()
-            .map(|(i, backend_state)| {
-                if i == index {
-                    // Update this backend's status
-                    Arc::new(BackendState {
-                        backend: backend_state.backend.clone(),
-                        status,
-                        connections: AtomicU32::new(
-                            backend_state.connections.load(Ordering::Relaxed)
-                        ),
-                    })
-                } else {
-                    backend_state.clone()
-                }
-            })
+            .map(|(name, weight)| Backend::new(format!("http://{name}"), name.to_string(), *weight))
             .collect();
+        let pool = BackendPool::new(backends, LoadBalancingStrategy::WeightedRoundRobin);
+        let healthy = pool.state.load().iter().cloned().collect();
+        (pool, healthy)
+    }
+
+    fn picks(pool: &BackendPool, healthy: &[Arc<BackendState>], n: usize) -> Vec<String> {
+        (0..n)
+            .map(|_| pool.weighted_round_robin_select(healthy).unwrap().backend.name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn smooth_wrr_interleaves_like_nginx() {
+        let (pool, healthy) = pool(&[("a", 5), ("b", 1), ("c", 1)]);
+        assert_eq!(picks(&pool, &healthy, 7), ["a", "a", "b", "a", "c", "a", "a"]);
+        // Sequenza periodica sul peso totale
+        assert_eq!(picks(&pool, &healthy, 7), ["a", "a", "b", "a", "c", "a", "a"]);
+    }
+
+    #[test]
+    fn smooth_wrr_equal_weights_is_round_robin() {
+        let (pool, healthy) = pool(&[("a", 1), ("b", 1), ("c", 1)]);
+        assert_eq!(picks(&pool, &healthy, 6), ["a", "b", "c", "a", "b", "c"]);
+    }
+
+    #[test]
+    fn smooth_wrr_large_weights_keep_proportions_without_bursts() {
+        let (pool, healthy) = pool(&[("a", 100), ("b", 1)]);
+        let sequence = picks(&pool, &healthy, 101);
+        assert_eq!(sequence.iter().filter(|name| *name == "b").count(), 1);
+        assert_eq!(sequence[50], "b");
+    }
+
+    #[test]
+    fn smooth_wrr_rebuilds_when_healthy_set_changes() {
+        let (pool, healthy) = pool(&[("a", 2), ("b", 1), ("c", 1)]);
+        assert_eq!(picks(&pool, &healthy, 4), ["a", "b", "c", "a"]);
+
+        let without_a = &healthy[1..];
+        assert_eq!(picks(&pool, without_a, 4), ["b", "c", "b", "c"]);
+    }
+
+    fn backends(names: &[&str]) -> Vec<Backend> {
+        names
+            .iter()
+            .map(|name| Backend::new(format!("http://{name}"), name.to_string(), 1))
+            .collect()
+    }
+
+    async fn healthy_pool(backends: Vec<Backend>, strategy: LoadBalancingStrategy) -> BackendPool {
+        let names: Vec<String> = backends.iter().map(|backend| backend.name.clone()).collect();
+        let pool = BackendPool::new(backends, strategy);
+        for name in &names {
+            pool.update_backend_status(name, BackendStatus::Healthy).await;
+        }
+        pool
+    }
+
+    async fn least_connections_pool(names: &[&str]) -> BackendPool {
+        healthy_pool(backends(names), LoadBalancingStrategy::LeastConnections).await
+    }
+
+    async fn pick(pool: &BackendPool, ctx: &SelectionContext) -> String {
+        pool.select_and_increment(ctx).await.unwrap().backend.name.clone()
+    }
+
+    #[tokio::test]
+    async fn round_robin_with_retry_exclusions() {
+        let pool = healthy_pool(backends(&["a", "b", "c"]), LoadBalancingStrategy::RoundRobin).await;
+        let ctx = SelectionContext::default();
+        assert_eq!(pick(&pool, &ctx).await, "a");
+        assert_eq!(pick(&pool, &ctx).await, "b");
+
+        // Il contatore è a 2 ma il retry lascia solo due candidati
+        let retry = SelectionContext { exclude: vec!["a".to_string()], ..SelectionContext::default() };
+        assert_eq!(pick(&pool, &retry).await, "b");
+        assert_eq!(pick(&pool, &retry).await, "c");
+        assert_eq!(pick(&pool, &ctx).await, "a");
+    }
+
+    fn connections(pool: &BackendPool) -> Vec<u32> {
+        pool.state.load().iter().map(|bs| bs.connections.load(Ordering::Relaxed)).collect()
+    }
+
+    #[tokio::test]
+    async fn round_robin_skips_saturated_backends() {
+        let mut backends = backends(&["a", "b", "c"]);
+        backends[2] = backends[2].clone().with_max_connections(Some(1));
+        let pool = healthy_pool(backends, LoadBalancingStrategy::RoundRobin).await;
+        let ctx = SelectionContext::default();
+        assert_eq!(pick(&pool, &ctx).await, "a");
+        assert_eq!(pick(&pool, &ctx).await, "b");
+
+        // Una sessione sticky satura `c` con il contatore a 2 e due soli candidati
+        let held = pool.select_named_and_increment("c").await.unwrap();
+        assert_eq!(pick(&pool, &ctx).await, "a");
+        assert_eq!(pick(&pool, &ctx).await, "b");
+        drop(held);
+        assert_eq!(pick(&pool, &ctx).await, "a");
+        assert_eq!(pick(&pool, &ctx).await, "b");
+        assert_eq!(pick(&pool, &ctx).await, "c");
+    }
+
+    #[tokio::test]
+    async fn least_connections_stays_accurate_when_requests_are_cancelled() {
+        let pool = least_connections_pool(&["a", "b"]).await;
+        let ctx = SelectionContext::default();
+
+        // Richiesta abbandonata a metà (client disconnesso, timeout)
+        let cancelled = {
+            let pool = pool.clone();
+            async move {
+                let _guard = pool.select_and_increment(&SelectionContext::default()).await.unwrap();
+                std::future::pending::<()>().await;
+            }
+        };
+        let timeout = std::time::Duration::from_millis(10);
+        assert!(tokio::time::timeout(timeout, cancelled).await.is_err());
+        assert_eq!(connections(&pool), [0, 0]);
+
+        let held = pool.select_and_increment(&ctx).await.unwrap();
+        assert_eq!(held.backend.name, "a");
+        let next = pool.select_and_increment(&ctx).await.unwrap();
+        assert_eq!(next.backend.name, "b");
+        drop(next);
+        assert_eq!(connections(&pool), [1, 0]);
+
+        // `a` ha ancora una richiesta in corso
+        assert_eq!(pool.select_and_increment(&ctx).await.unwrap().backend.name, "b");
+        drop(held);
+        assert_eq!(connections(&pool), [0, 0]);
+    }
+
+    #[tokio::test]
+    async fn least_connections_counts_survive_status_changes() {
+        let pool = least_connections_pool(&["a", "b"]).await;
+        let ctx = SelectionContext::default();
+
+        let held = pool.select_and_increment(&ctx).await.unwrap();
+        assert_eq!(held.backend.name, "a");
+
+        // Gli health check ricreano il `BackendState` mentre la richiesta è in corso
+        pool.update_backend_status("a", BackendStatus::Unhealthy).await;
+        pool.update_backend_status("a", BackendStatus::Healthy).await;
+        assert_eq!(connections(&pool), [1, 0]);
+        assert_eq!(pool.select_and_increment(&ctx).await.unwrap().backend.name, "b");
 
-        self.state.store(Arc::new(new_backends_state));
-        true
+        drop(held);
+        assert_eq!(connections(&pool), [0, 0]);
+        assert_eq!(pool.select_and_increment(&ctx).await.unwrap().backend.name, "a");
     }
 }
@@ -0,0 +1,247 @@
+use crate::backend::latency::now_ns;
+use crate::config::TimeoutConfig;
+use crate::proxy::response::GuardedBody;
+use crate::proxy::ProxyHandler;
+use hyper::server::conn::Http;
+use hyper::service::Service;
+use hyper::Version;
+use std::convert::Infallible;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tracing::debug;
+
+/// Granularità dei controlli sui timeout lato client
+const CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Attività di una connessione client: richieste in corso, ultimo traffico
+/// e inizio della richiesta successiva ancora senza header completi.
+struct ConnActivity {
+    in_flight: AtomicU32,
+    last_activity_ns: AtomicU64,
+    /// Primi byte di una nuova richiesta (0 = connessione ferma tra due richieste)
+    head_started_ns: AtomicU64,
+    /// Connessione HTTP/2: i frame di controllo (PING, WINDOW_UPDATE) non sono nuove richieste
+    http2: AtomicBool,
+    /// Inizio o fine dell'ultima richiesta, per l'inattività delle connessioni HTTP/2
+    last_request_ns: AtomicU64,
+}
+
+impl ConnActivity {
+    fn new() -> Self {
+        let now = now_ns();
+        // Appena accettata, la connessione deve inviare gli header entro `header_read_ms`
+        Self {
+            in_flight: AtomicU32::new(0),
+            last_activity_ns: AtomicU64::new(now),
+            head_started_ns: AtomicU64::new(now),
+            http2: AtomicBool::new(false),
+            last_request_ns: AtomicU64::new(now),
+        }
+    }
+
+    fn set_http2(&self) {
+        self.http2.store(true, Ordering::Relaxed);
+    }
+
+    fn on_read(&self) {
+        let now = now_ns();
+        self.last_activity_ns.store(now, Ordering::Relaxed);
+        if self.in_flight.load(Ordering::Relaxed) == 0 {
+            let _ = self.head_started_ns.compare_exchange(0, now, Ordering::Relaxed, Ordering::Relaxed);
+        }
+    }
+
+    fn on_write(&self) {
+        self.last_activity_ns.store(now_ns(), Ordering::Relaxed);
+    }
+
+    fn request_started(&self) {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        self.head_started_ns.store(0, Ordering::Relaxed);
+        self.last_request_ns.store(now_ns(), Ordering::Relaxed);
+    }
+
+    fn request_finished(&self) {
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+        let now = now_ns();
+        self.last_activity_ns.store(now, Ordering::Relaxed);
+        self.last_request_ns.store(now, Ordering::Relaxed);
+    }
+
+    /// Motivo per chiudere la connessione, se uno dei timeout è scaduto
+    fn expired(&self, header_read: Duration, keep_alive: Duration) -> Option<&'static str> {
+        if self.in_flight.load(Ordering::Relaxed) > 0 {
+            return None;
+        }
+        let now = now_ns();
+        // In HTTP/2 gli header arrivano per stream, non per connessione: conta solo da quanto
+        // non ci sono stream aperti. I peer morti li scoprono i PING di hyper.
+        if self.http2.load(Ordering::Relaxed) {
+            return (now.saturating_sub(self.last_request_ns.load(Ordering::Relaxed)) > keep_alive.as_nanos() as u64)
+                .then_some("keep-alive idle timeout");
+        }
+        let head_started = self.head_started_ns.load(Ordering::Relaxed);
+        if head_started != 0 {
+            return (now.saturating_sub(head_started) > header_read.as_nanos() as u64)
+                .then_some("header read timeout");
+        }
+        (now.saturating_sub(self.last_activity_ns.load(Ordering::Relaxed)) > keep_alive.as_nanos() as u64)
+            .then_some("keep-alive idle timeout")
+    }
+}
+
+/// Stream del client che aggiorna `ConnActivity` ad ogni lettura e scrittura
+struct TrackedIo<S> {
+    inner: S,
+    activity: Arc<ConnActivity>,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for TrackedIo<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if matches!(result, Poll::Ready(Ok(()))) && buf.filled().len() > before {
+            self.activity.on_read();
+        }
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for TrackedIo<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if matches!(result, Poll::Ready(Ok(n)) if n > 0) {
+            self.activity.on_write();
+        }
+        result
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write_vectored(cx, bufs);
+        if matches!(result, Poll::Ready(Ok(n)) if n > 0) {
+            self.activity.on_write();
+        }
+        result
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Richiesta in corso sulla connessione fino alla fine del corpo della risposta,
+/// chiusa anche se il client si disconnette
+struct InFlight(Arc<ConnActivity>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.request_finished();
+    }
+}
+
+/// Protocolli HTTP accettati su una connessione client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnProtocol {
+    Http1,
+    Http2,
+    /// HTTP/1.1 oppure h2c, riconosciuto dalla preface HTTP/2
+    Http1OrH2c,
+}
+
+impl ConnProtocol {
+    /// Protocollo scelto dal client con ALPN; senza ALPN si assume HTTP/1.1
+    pub fn from_alpn(alpn: Option<&[u8]>) -> Self {
+        match alpn {
+            Some(b"h2") => ConnProtocol::Http2,
+            _ => ConnProtocol::Http1,
+        }
+    }
+
+    fn http(self, keep_alive: Duration, header_read: Duration) -> Http {
+        let mut http = Http::new();
+        match self {
+            ConnProtocol::Http1 => { http.http1_only(true); }
+            ConnProtocol::Http2 => { http.http2_only(true); }
+            ConnProtocol::Http1OrH2c => {}
+        }
+        // PING periodici sulle connessioni HTTP/2: senza risposta il client è sparito
+        http.http2_keep_alive_interval(keep_alive);
+        http.http2_keep_alive_timeout(header_read);
+        http
+    }
+}
+
+/// Serve una connessione client (in chiaro o TLS) chiudendola se il client
+/// è troppo lento a inviare gli header o resta inattivo oltre il keep-alive.
+pub async fn serve_connection<S>(
+    io: S,
+    remote_addr: SocketAddr,
+    protocol: ConnProtocol,
+    handler: ProxyHandler,
+    timeouts: &TimeoutConfig,
+) -> Result<(), hyper::Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let activity = Arc::new(ConnActivity::new());
+    if protocol == ConnProtocol::Http2 {
+        activity.set_http2();
+    }
+    let io = TrackedIo { inner: io, activity: Arc::clone(&activity) };
+
+    let service_activity = Arc::clone(&activity);
+    let service = hyper::service::service_fn(move |mut req: hyper::Request<hyper::Body>| {
+        req.extensions_mut().insert(remote_addr);
+        // h2c si riconosce solo dalla prima richiesta
+        if req.version() == Version::HTTP_2 {
+            service_activity.set_http2();
+        }
+        service_activity.request_started();
+        let in_flight = InFlight(Arc::clone(&service_activity));
+
+        // Handler condiviso da tutte le connessioni: limite di concorrenza e client comuni
+        let mut handler = handler.clone();
+        async move {
+            let response = handler.call(req).await?;
+            // Download, SSE e stream gRPC restano in corso finché il corpo non finisce
+            Ok::<_, Infallible>(response.map(|body| GuardedBody::new(body, in_flight)))
+        }
+    });
+
+    let header_read = Duration::from_millis(timeouts.header_read_ms);
+    let keep_alive = Duration::from_millis(timeouts.keep_alive_ms);
+    // Con gli upgrade (WebSocket) la connessione passa al tunnel dopo il 101
+    let conn = protocol.http(keep_alive, header_read).serve_connection(io, service).with_upgrades();
+    tokio::pin!(conn);
+
+    let mut ticker = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        tokio::select! {
+            result = &mut conn => return result,
+            _ = ticker.tick() => {
+                if let Some(reason) = activity.expired(header_read, keep_alive) {
+                    debug!("Closing connection from {}: {}", remote_addr, reason);
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
@@ -1,11 +1,15 @@
 pub mod algorithms;
-use crate::backend::{BackendPool, HealthCheck, LoadBalancingStrategy};
-use crate::proxy::ProxyHandler;
-use crate::config::Config;
-use hyper::service::Service;
-use hyper::Server;
+pub mod listener;
+pub mod proxy_protocol;
+pub mod tcp;
+pub mod udp;
+use crate::backend::pool::ListenerPool;
+use crate::backend::{BackendPool, HealthCheck};
+use crate::config::ListenerMode;
+use crate::proxy::{ProxyHandler, ProxySettings};
+use crate::config::{Config, ConfigWatcher};
 use std::net::SocketAddr;
-use tracing::{info, error};
+use tracing::{debug, info, error};
 use std::fs::File;
 use std::io::BufReader;
 use std::sync::Arc;
@@ -15,11 +19,23 @@ use tokio_rustls::TlsAcceptor;
 use rustls_pemfile::{certs, pkcs8_private_keys};
 use std::result::Result::{Ok,Err};
 use anyhow::Context; 
+use listener::{serve_connection, ConnProtocol};
+use proxy_protocol::{client_addr, TrustedSources};
+use tcp::TcpProxy;
+use udp::UdpProxy;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
 
 pub struct LoadBalancer {
     config: Config,
     backend_pool: BackendPool,
-    load_balancer_url: String,
+    proxy_handler: ProxyHandler,
+    config_path: Option<String>,
+    /// Listener aggiuntivi (`listeners` nella config), ognuno con il suo pool
+    listeners: Vec<ListenerPool>,
+    /// Sorgenti da cui leggere l'header PROXY sui listener HTTP e HTTPS
+    proxy_protocol: Option<Arc<TrustedSources>>,
 }
 
 impl LoadBalancer {
@@ -27,42 +43,67 @@ impl LoadBalancer {
         info!("Initializing Load Balancer with config: {:?}", config);
         
         let backends = config.backends.iter()
-            .map(|backend_config| {
-                crate::backend::server::Backend::new(
-                    backend_config.url.clone(),
-                    backend_config.name.clone(),
-                    backend_config.weight.unwrap_or(1),
-                )
-            })
+            .map(crate::backend::server::Backend::from)
             .collect();
 
-        let load_balancer_url = format!("http://{}:{}",config.host.clone(),config.port.clone());
-
-
-        let strategy = match config.lb_strategy.as_str() {
-            "round_robin" => LoadBalancingStrategy::RoundRobin,
-            "random" => LoadBalancingStrategy::Random,
-            "least_connections" => LoadBalancingStrategy::LeastConnections,
-            "weighted_round_robin" => LoadBalancingStrategy::WeightedRoundRobin,
-            _ => LoadBalancingStrategy::RoundRobin,
+        let backend_pool = BackendPool::new(backends, config.lb_strategy)
+            .with_hash_key(config.hash_key.clone())
+            .with_outlier_detection(config.outlier_detection.clone())
+            .with_circuit_breaker(config.circuit_breaker.clone());
+
+        let listeners = config.listeners.iter()
+            .map(|listener| -> anyhow::Result<ListenerPool> {
+                let backends = listener.backends.iter()
+                    .map(crate::backend::server::Backend::from)
+                    .collect();
+                Ok(ListenerPool {
+                    name: listener.name.clone(),
+                    mode: listener.mode,
+                    bind: listener.bind.parse()
+                        .with_context(|| format!("Invalid bind address {} for listener {}", listener.bind, listener.name))?,
+                    pool: BackendPool::new(backends, listener.lb_strategy),
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let proxy_protocol = match &config.proxy_protocol {
+            Some(proxy_protocol) => Some(Arc::new(
+                TrustedSources::parse(&proxy_protocol.trusted)
+                    .map_err(|e| anyhow::anyhow!("Invalid proxy_protocol.trusted: {e}"))?,
+            )),
+            None => None,
         };
 
-        let backend_pool = BackendPool::new(backends, strategy);
+        let proxy_settings = Arc::new(ProxySettings::from_config(&config));
+        let proxy_handler = ProxyHandler::new(backend_pool.clone(), proxy_settings)
+            .with_listeners(listeners.clone());
 
         Ok(Self {
             config,
             backend_pool,
-            load_balancer_url,
+            proxy_handler,
+            config_path: None,
+            listeners,
+            proxy_protocol,
         })
     }
 
+    /// Abilita il reload a caldo dei backend dal file indicato (modifica del file o SIGHUP)
+    pub fn with_config_path(mut self, path: impl Into<String>) -> Self {
+        self.config_path = Some(path.into());
+        self
+    }
+
     pub async fn start(self) -> anyhow::Result<()> {
         info!("Starting Load Balancer...");
 
         self.start_health_checks().await;
+        self.start_config_watcher().await;
         let http_server = self.start_http_server();
         let https_server = self.start_https_server();
-        tokio::try_join!(http_server,https_server)
+        let listeners = self.start_listeners();
+        let admin_server = self.start_admin_server();
+        tokio::try_join!(http_server, https_server, listeners, admin_server)
             .context("Critical failure in one of the server instances")?;
 
         Ok(())
@@ -72,57 +113,130 @@ impl LoadBalancer {
         let health_check = HealthCheck::new(
             self.backend_pool.clone(),
             self.config.health_check_interval,
-            self.load_balancer_url.clone(),
         );
 
         let _handle = health_check.start().await;
         info!("Health checks started with interval: {}s", self.config.health_check_interval);
+
+        for listener in &self.listeners {
+            let health_check = HealthCheck::new(listener.pool.clone(), self.config.health_check_interval);
+            let _handle = health_check.start().await;
+        }
     }
 
-    async fn start_http_server(&self) -> anyhow::Result<()> {
-        let addr: SocketAddr = format!("{}:{}", self.config.host, self.config.port)
-            .parse()
-            .expect("Invalid host/port configuration");
+    async fn start_config_watcher(&self) {
+        if let Some(path) = &self.config_path {
+            let watcher = ConfigWatcher::new(path, self.backend_pool.clone());
+            let _handle = watcher.start().await;
+            info!("Watching {} for backend changes (send SIGHUP to force a reload)", path);
+        }
+    }
 
+    async fn start_listeners(&self) -> anyhow::Result<()> {
+        let servers = self.listeners.iter().zip(&self.config.listeners).map(|(listener, config)| {
+            let server: Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>> = match listener.mode {
+                ListenerMode::Tcp => Box::pin(TcpProxy::new(listener.clone(), config, &self.config.timeouts).run()),
+                ListenerMode::Udp => Box::pin(UdpProxy::new(listener.clone(), config).run()),
+            };
+            server
+        });
+        futures::future::try_join_all(servers).await?;
+        Ok(())
+    }
 
-        let make_service = hyper::service::make_service_fn(|conn: &hyper::server::conn::AddrStream| {
-            let remote_addr = conn.remote_addr();
-            let backend_pool = self.backend_pool.clone();
+    /// Endpoint admin su un indirizzo proprio, fuori dal listener pubblico del proxy
+    async fn start_admin_server(&self) -> anyhow::Result<()> {
+        let addr: SocketAddr = self.config.admin.bind
+            .parse()
+            .with_context(|| format!("Invalid admin bind address {}", self.config.admin.bind))?;
+        let listener = TcpListener::bind(&addr)
+            .await
+            .with_context(|| format!("Failed to bind admin endpoint {addr}"))?;
+        info!("Admin endpoint listening on http://{}", addr);
 
-            async move {
-                Ok::<_, hyper::Error>(hyper::service::service_fn(move |mut req: hyper::Request<hyper::Body>| {
-                    req.extensions_mut().insert(remote_addr);
+        loop {
+            let (stream, remote_addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    error!("Admin server error: {}", e);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+            let handler = self.proxy_handler.clone();
+            tokio::spawn(async move {
+                let service = hyper::service::service_fn(move |req| {
+                    let response = handler.handle_admin(&req);
+                    async move { Ok::<_, std::convert::Infallible>(response) }
+                });
+                if let Err(err) = hyper::server::conn::Http::new().http1_only(true).serve_connection(stream, service).await {
+                    debug!("Errore nella connessione admin da {}: {:?}", remote_addr, err);
+                }
+            });
+        }
+    }
+
+    async fn start_http_server(&self) -> anyhow::Result<()> {
+        let addr: SocketAddr = format!("{}:{}", self.config.host, self.config.port)
+            .parse()
+            .with_context(|| format!("Invalid listen address {}:{}", self.config.host, self.config.port))?;
 
-                    let mut handler = ProxyHandler::new(backend_pool.clone());
-                    handler.call(req)
-                }))
-            }
-        });
 
-        let server = Server::bind(&addr).serve(make_service);
+        let listener = TcpListener::bind(&addr)
+            .await
+            .with_context(|| format!("Failed to bind {addr}"))?;
 
         info!("Load Balancer running on http://{}", addr);
+        let protocol = if self.config.h2c {
+            info!("Accepting prior-knowledge HTTP/2 (h2c) on http://{}", addr);
+            ConnProtocol::Http1OrH2c
+        } else {
+            ConnProtocol::Http1
+        };
         info!("Load balancing strategy: {:?}", self.backend_pool.strategy);
+        if self.backend_pool.strategy.uses_hash_key() {
+            info!("Hash key: {:?}", self.backend_pool.hash_key);
+        }
         info!("Health check interval: {}s", self.config.health_check_interval);
 
-        let states = self.backend_pool.state.load();
-        let backends = states.iter().map(|backend_state| backend_state.backend.clone());
-
-        for backend in backends {
-            info!("Backend: {} -> {}", backend.name, backend.url);
+        for backend_state in self.backend_pool.state.load().iter() {
+            info!("Backend: {} -> {}", backend_state.backend.name, backend_state.backend.url);
         }
 
-        match server.await {
-            Ok(_) => Ok(()),
-            Err(e) => {
-                error!("Server error: {}", e);
-                Err(anyhow::anyhow!("Server error: {e}"))
-            }
+        loop {
+            let (stream, remote_addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    // Tipicamente troppi file aperti: meglio rallentare che girare a vuoto
+                    error!("Server error: {}", e);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+            let handler = self.proxy_handler.clone();
+            let timeouts = self.config.timeouts.clone();
+            let proxy_protocol = self.proxy_protocol.clone();
+
+            tokio::spawn(async move {
+                let mut stream = stream;
+                let header_read = Duration::from_millis(timeouts.header_read_ms);
+                let remote_addr = match client_addr(&mut stream, remote_addr, proxy_protocol.as_deref(), header_read).await {
+                    Ok(client) => client,
+                    Err(e) => {
+                        debug!("PROXY header da {} non valido: {}", remote_addr, e);
+                        return;
+                    }
+                };
+                if let Err(err) = serve_connection(stream, remote_addr, protocol, handler, &timeouts).await {
+                    debug!("Errore nella connessione HTTP: {:?}", err);
+                }
+            });
         }
     }
     async fn start_https_server(&self) -> anyhow::Result<()> {
         // 1. Configura l'indirizzo (usa una porta diversa, es: 3443)
-        let https_port = self.config.port + 443; // Esempio: 3000 + 443 = 3443
+        let https_port = self.config.port.checked_add(443) // Esempio: 3000 + 443 = 3443
+            .with_context(|| format!("Port {} too high to derive the HTTPS port", self.config.port))?;
         let addr: SocketAddr = format!("{}:{}", self.config.host, https_port).parse()?;
 
         // 2. Carica il Certificato e la Chiave Privata
@@ -146,10 +260,12 @@ impl LoadBalancer {
         let private_key = PrivateKey(keys.remove(0));
 
         // 3. Configura Rustls per il server
-        let tls_config = ServerConfig::builder()
+        let mut tls_config = ServerConfig::builder()
             .with_safe_defaults()
             .with_no_client_auth()
             .with_single_cert(cert_chain, private_key)?;
+        // HTTP/2 negoziato con ALPN, HTTP/1.1 per i client che non lo offrono
+        tls_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
 
         let acceptor = TlsAcceptor::from(Arc::new(tls_config));
         let listener = TcpListener::bind(&addr).await?;
@@ -158,29 +274,44 @@ impl LoadBalancer {
 
         // 4. Loop di accettazione
         loop {
-            let (stream, remote_addr) = listener.accept().await?;
+            let (stream, remote_addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    error!("HTTPS server error: {}", e);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
             let acceptor = acceptor.clone();
-            let backend_pool = self.backend_pool.clone();
+            let handler = self.proxy_handler.clone();
+            let timeouts = self.config.timeouts.clone();
+            let proxy_protocol = self.proxy_protocol.clone();
 
             tokio::spawn(async move {
-                // Esegue l'handshake TLS
-                match acceptor.accept(stream).await {
-                    Ok(tls_stream) => {
-                        // Configura il servizio Hyper sopra lo stream criptato
-                        let service = hyper::service::service_fn(move |mut req| {
-                            req.extensions_mut().insert(remote_addr);
-                            let mut handler = ProxyHandler::new(backend_pool.clone());
-                            async move { handler.call(req).await }
-                        });
-
-                        if let Err(err) = hyper::server::conn::Http::new()
-                            .serve_connection(tls_stream, service)
-                            .await 
-                        {
+                // L'header PROXY precede l'handshake TLS
+                let mut stream = stream;
+                let header_read = Duration::from_millis(timeouts.header_read_ms);
+                let remote_addr = match client_addr(&mut stream, remote_addr, proxy_protocol.as_deref(), header_read).await {
+                    Ok(client) => client,
+                    Err(e) => {
+                        debug!("PROXY header da {} non valido: {}", remote_addr, e);
+                        return;
+                    }
+                };
+                // Esegue l'handshake TLS, con lo stesso limite di tempo degli header
+                let handshake = tokio::time::timeout(
+                    Duration::from_millis(timeouts.header_read_ms),
+                    acceptor.accept(stream),
+                );
+                match handshake.await {
+                    Ok(Ok(tls_stream)) => {
+                        let protocol = ConnProtocol::from_alpn(tls_stream.get_ref().1.alpn_protocol());
+                        if let Err(err) = serve_connection(tls_stream, remote_addr, protocol, handler, &timeouts).await {
                             error!("Errore nella connessione HTTPS: {:?}", err);
                         }
                     }
-                    Err(e) => error!("Errore handshake TLS: {:?}", e),
+                    Ok(Err(e)) => error!("Errore handshake TLS: {:?}", e),
+                    Err(_) => debug!("Handshake TLS da {} scaduto", remote_addr),
                 }
             });
         }
@@ -0,0 +1,287 @@
+use crate::config::ProxyProtocolVersion;
+use ipnet::IpNet;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+
+/// Firma iniziale dell'header PROXY v2
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+/// Lunghezza massima di un header v1, CRLF compreso
+const V1_MAX_LEN: usize = 107;
+
+/// Sorgenti da cui l'header PROXY viene accettato (`proxy_protocol.trusted`)
+#[derive(Debug, Clone)]
+pub struct TrustedSources(Vec<IpNet>);
+
+impl TrustedSources {
+    /// Reti CIDR o singoli IP
+    pub fn parse(sources: &[String]) -> Result<Self, String> {
+        sources
+            .iter()
+            .map(|source| {
+                source
+                    .parse::<IpNet>()
+                    .or_else(|_| source.parse::<IpAddr>().map(IpNet::from))
+                    .map_err(|_| format!("'{source}' is not an IP address or CIDR network"))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(TrustedSources)
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.0.iter().any(|network| network.contains(&ip))
+    }
+}
+
+/// Indirizzo del client di una connessione appena accettata. Dalle sorgenti fidate
+/// l'header PROXY è obbligatorio e viene consumato; le altre restano client diretti.
+pub async fn client_addr(
+    stream: &mut TcpStream,
+    peer: SocketAddr,
+    trusted: Option<&TrustedSources>,
+    timeout: Duration,
+) -> io::Result<SocketAddr> {
+    match trusted {
+        Some(trusted) if trusted.contains(peer.ip()) => {
+            let source = tokio::time::timeout(timeout, read_header(stream))
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "PROXY header timeout"))??;
+            // UNKNOWN / LOCAL: connessione del balancer stesso (es. health check)
+            Ok(source.unwrap_or(peer))
+        }
+        _ => Ok(peer),
+    }
+}
+
+/// Legge l'header PROXY (v1 o v2) consumando solo i suoi byte, il resto è della richiesta.
+/// `None` se l'header non porta l'indirizzo del client.
+async fn read_header(stream: &mut TcpStream) -> io::Result<Option<SocketAddr>> {
+    // 8 byte bastano a distinguere "PROXY TC"/"PROXY UN" dalla firma v2
+    let mut head = [0u8; 16];
+    stream.read_exact(&mut head[..8]).await?;
+    if head.starts_with(b"PROXY ") {
+        read_v1(stream, &head[..8]).await
+    } else if head[..8] == V2_SIGNATURE[..8] {
+        stream.read_exact(&mut head[8..]).await?;
+        read_v2(stream, &head).await
+    } else {
+        Err(invalid("missing PROXY protocol header"))
+    }
+}
+
+async fn read_v1(stream: &mut TcpStream, start: &[u8]) -> io::Result<Option<SocketAddr>> {
+    let mut line = start.to_vec();
+    // Un byte alla volta per non leggere oltre il CRLF
+    while !line.ends_with(b"\r\n") {
+        if line.len() == V1_MAX_LEN {
+            return Err(invalid("PROXY v1 header too long"));
+        }
+        line.push(stream.read_u8().await?);
+    }
+
+    let line = std::str::from_utf8(&line[..line.len() - 2]).map_err(|_| invalid("PROXY v1 header is not ASCII"))?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", "TCP4" | "TCP6", source, _destination, source_port, _destination_port] => {
+            let ip: IpAddr = source.parse().map_err(|_| invalid("invalid PROXY v1 source address"))?;
+            let port: u16 = source_port.parse().map_err(|_| invalid("invalid PROXY v1 source port"))?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(invalid("malformed PROXY v1 header")),
+    }
+}
+
+async fn read_v2(stream: &mut TcpStream, header: &[u8; 16]) -> io::Result<Option<SocketAddr>> {
+    if header[..12] != V2_SIGNATURE {
+        return Err(invalid("invalid PROXY v2 signature"));
+    }
+    let mut payload = vec![0u8; u16::from_be_bytes([header[14], header[15]]) as usize];
+    stream.read_exact(&mut payload).await?;
+
+    if header[12] >> 4 != 2 {
+        return Err(invalid("unsupported PROXY protocol version"));
+    }
+    match header[12] & 0x0f {
+        0 => return Ok(None),
+        1 => {}
+        _ => return Err(invalid("unknown PROXY v2 command")),
+    }
+    // Famiglia nei 4 bit alti (1 = IPv4, 2 = IPv6), trasporto in quelli bassi; TLV ignorati
+    match header[13] >> 4 {
+        1 if payload.len() >= 12 => {
+            let ip: [u8; 4] = payload[0..4].try_into().unwrap();
+            Ok(Some(SocketAddr::new(ip.into(), u16::from_be_bytes([payload[8], payload[9]]))))
+        }
+        2 if payload.len() >= 36 => {
+            let ip: [u8; 16] = payload[0..16].try_into().unwrap();
+            Ok(Some(SocketAddr::new(ip.into(), u16::from_be_bytes([payload[32], payload[33]]))))
+        }
+        1 | 2 => Err(invalid("truncated PROXY v2 addresses")),
+        _ => Ok(None),
+    }
+}
+
+/// Header PROXY per una connessione TCP da `source` (il client) verso `destination`
+pub fn encode_header(version: ProxyProtocolVersion, source: SocketAddr, destination: SocketAddr) -> Vec<u8> {
+    // Famiglie diverse (client IPv4 su un listener IPv6): entrambi come IPv6
+    let (source_ip, destination_ip) = match (source.ip().to_canonical(), destination.ip().to_canonical()) {
+        (IpAddr::V4(s), IpAddr::V4(d)) => (IpAddr::V4(s), IpAddr::V4(d)),
+        (s, d) => (IpAddr::V6(to_v6(s)), IpAddr::V6(to_v6(d))),
+    };
+
+    match version {
+        ProxyProtocolVersion::V1 => {
+            let family = if source_ip.is_ipv4() { "TCP4" } else { "TCP6" };
+            format!(
+                "PROXY {family} {source_ip} {destination_ip} {} {}\r\n",
+                source.port(),
+                destination.port()
+            )
+            .into_bytes()
+        }
+        ProxyProtocolVersion::V2 => {
+            let mut header = V2_SIGNATURE.to_vec();
+            // Versione 2, comando PROXY
+            header.push(0x21);
+            let addresses = match (source_ip, destination_ip) {
+                (IpAddr::V4(s), IpAddr::V4(d)) => {
+                    header.push(0x11);
+                    [s.octets().as_slice(), d.octets().as_slice()].concat()
+                }
+                (s, d) => {
+                    header.push(0x21);
+                    [to_v6(s).octets().as_slice(), to_v6(d).octets().as_slice()].concat()
+                }
+            };
+            header.extend_from_slice(&(addresses.len() as u16 + 4).to_be_bytes());
+            header.extend_from_slice(&addresses);
+            header.extend_from_slice(&source.port().to_be_bytes());
+            header.extend_from_slice(&destination.port().to_be_bytes());
+            header
+        }
+    }
+}
+
+fn to_v6(ip: IpAddr) -> std::net::Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+
+    const REQUEST: &[u8] = b"GET / HTTP/1.1\r\n\r\n";
+
+    /// Lato server di una connessione loopback su cui il client ha già scritto `data`
+    async fn accepted(data: &[u8]) -> (TcpStream, SocketAddr) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        client.write_all(data).await.unwrap();
+        listener.accept().await.unwrap()
+    }
+
+    async fn rest(stream: &mut TcpStream) -> Vec<u8> {
+        let mut rest = Vec::new();
+        stream.read_to_end(&mut rest).await.unwrap();
+        rest
+    }
+
+    fn trusted_loopback() -> TrustedSources {
+        TrustedSources::parse(&["127.0.0.0/8".to_string()]).unwrap()
+    }
+
+    async fn round_trip(version: ProxyProtocolVersion, source: &str, destination: &str) -> SocketAddr {
+        let source: SocketAddr = source.parse().unwrap();
+        let header = encode_header(version, source, destination.parse().unwrap());
+        let (mut stream, _) = accepted(&[header.as_slice(), REQUEST].concat()).await;
+
+        let client = read_header(&mut stream).await.unwrap().unwrap();
+        // Solo i byte dell'header vengono consumati
+        assert_eq!(rest(&mut stream).await, REQUEST);
+        client
+    }
+
+    #[tokio::test]
+    async fn v1_round_trip() {
+        assert_eq!(round_trip(ProxyProtocolVersion::V1, "10.0.0.1:4000", "10.0.0.2:80").await, "10.0.0.1:4000".parse().unwrap());
+        assert_eq!(round_trip(ProxyProtocolVersion::V1, "[2001:db8::1]:4000", "[2001:db8::2]:443").await, "[2001:db8::1]:4000".parse().unwrap());
+    }
+
+    #[tokio::test]
+    async fn v2_round_trip() {
+        assert_eq!(round_trip(ProxyProtocolVersion::V2, "10.0.0.1:4000", "10.0.0.2:80").await, "10.0.0.1:4000".parse().unwrap());
+        assert_eq!(round_trip(ProxyProtocolVersion::V2, "[2001:db8::1]:4000", "[2001:db8::2]:443").await, "[2001:db8::1]:4000".parse().unwrap());
+        // Famiglie diverse: il client arriva come IPv4 mappato in IPv6
+        let client = round_trip(ProxyProtocolVersion::V2, "10.0.0.1:4000", "[2001:db8::2]:443").await;
+        assert_eq!((client.ip().to_canonical(), client.port()), ("10.0.0.1".parse().unwrap(), 4000));
+    }
+
+    #[tokio::test]
+    async fn v1_unknown_keeps_the_peer() {
+        let (mut stream, peer) = accepted(&[b"PROXY UNKNOWN\r\n".as_slice(), REQUEST].concat()).await;
+        let client = client_addr(&mut stream, peer, Some(&trusted_loopback()), Duration::from_secs(1)).await.unwrap();
+        assert_eq!(client, peer);
+        assert_eq!(rest(&mut stream).await, REQUEST);
+    }
+
+    #[tokio::test]
+    async fn v2_local_keeps_the_peer() {
+        // Versione 2, comando LOCAL, famiglia non specificata, nessun indirizzo
+        let header = [V2_SIGNATURE.as_slice(), &[0x20, 0x00, 0x00, 0x00]].concat();
+        let (mut stream, peer) = accepted(&[header.as_slice(), REQUEST].concat()).await;
+        let client = client_addr(&mut stream, peer, Some(&trusted_loopback()), Duration::from_secs(1)).await.unwrap();
+        assert_eq!(client, peer);
+        assert_eq!(rest(&mut stream).await, REQUEST);
+    }
+
+    #[tokio::test]
+    async fn v2_truncated_addresses_are_rejected() {
+        // TCP su IPv4 ma solo 4 byte di indirizzi invece di 12
+        let header = [V2_SIGNATURE.as_slice(), &[0x21, 0x11, 0x00, 0x04, 10, 0, 0, 1]].concat();
+        let (mut stream, _) = accepted(&header).await;
+        let error = read_header(&mut stream).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn v1_line_too_long_is_rejected() {
+        let line = format!("PROXY TCP4 {} 10.0.0.2 4000 80\r\n", "1".repeat(V1_MAX_LEN));
+        let (mut stream, _) = accepted(line.as_bytes()).await;
+        let error = read_header(&mut stream).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn untrusted_peer_skips_the_parser() {
+        let header = encode_header(ProxyProtocolVersion::V1, "10.0.0.1:4000".parse().unwrap(), "10.0.0.2:80".parse().unwrap());
+        let (mut stream, peer) = accepted(&header).await;
+        let trusted = TrustedSources::parse(&["192.0.2.0/24".to_string()]).unwrap();
+
+        let client = client_addr(&mut stream, peer, Some(&trusted), Duration::from_secs(1)).await.unwrap();
+        assert_eq!(client, peer);
+        // L'header non viene consumato: per il proxy fa parte della richiesta
+        assert_eq!(rest(&mut stream).await, header);
+        let (mut stream, peer) = accepted(REQUEST).await;
+        assert_eq!(client_addr(&mut stream, peer, None, Duration::from_secs(1)).await.unwrap(), peer);
+    }
+
+    #[tokio::test]
+    async fn trusted_peer_without_header_is_rejected() {
+        let (mut stream, peer) = accepted(REQUEST).await;
+        let error = client_addr(&mut stream, peer, Some(&trusted_loopback()), Duration::from_secs(1)).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,115 @@
+use crate::backend::pool::{ConnectionGuard, ListenerPool};
+use crate::backend::SelectionContext;
+use crate::config::validate::layer4_backend_address;
+use crate::config::{ListenerConfig, ProxyProtocolVersion, TimeoutConfig};
+use crate::lb::proxy_protocol::encode_header;
+use crate::lb::algorithms::hash_of;
+use crate::proxy::timeout::Timeouts;
+use crate::proxy::tunnel::splice;
+use anyhow::Context;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{error, info, warn};
+
+/// Listener `mode: tcp`: ogni connessione client viene collegata a un backend scelto
+/// dalla strategia del pool e conta tra le sue connessioni finché resta aperta
+#[derive(Clone)]
+pub struct TcpProxy {
+    listener: ListenerPool,
+    timeouts: TimeoutConfig,
+    idle: Duration,
+    send_proxy_protocol: Option<ProxyProtocolVersion>,
+}
+
+impl TcpProxy {
+    pub fn new(listener: ListenerPool, config: &ListenerConfig, timeouts: &TimeoutConfig) -> Self {
+        Self {
+            listener,
+            timeouts: timeouts.clone(),
+            idle: Duration::from_millis(config.idle_timeout_ms.unwrap_or(timeouts.tunnel_idle_ms)),
+            send_proxy_protocol: config.send_proxy_protocol,
+        }
+    }
+
+    pub async fn run(self) -> anyhow::Result<()> {
+        let listener = TcpListener::bind(self.listener.bind)
+            .await
+            .with_context(|| format!("Failed to bind {} for listener {}", self.listener.bind, self.listener.name))?;
+        info!(
+            "TCP listener {} running on {} (strategy {})",
+            self.listener.name, self.listener.bind, self.listener.pool.strategy
+        );
+
+        loop {
+            let (client, remote_addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    error!("TCP listener {} error: {}", self.listener.name, e);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+            let proxy = self.clone();
+            tokio::spawn(async move { proxy.handle(client, remote_addr).await });
+        }
+    }
+
+    async fn handle(&self, client: TcpStream, remote_addr: SocketAddr) {
+        let _ = client.set_nodelay(true);
+        let Some((mut backend, guard)) = self.connect(remote_addr).await else {
+            warn!("TCP listener {}: no backend available for {}", self.listener.name, remote_addr);
+            return;
+        };
+
+        let backend_name = guard.backend.name.clone();
+        if let Some(version) = self.send_proxy_protocol {
+            // Destinazione: l'indirizzo su cui il client si è connesso
+            let local_addr = client.local_addr().unwrap_or(self.listener.bind);
+            if let Err(e) = backend.write_all(&encode_header(version, remote_addr, local_addr)).await {
+                warn!("TCP {} -> backend {}: sending PROXY header failed: {}", remote_addr, backend_name, e);
+                return;
+            }
+        }
+        info!("TCP {} -> backend {} opened", remote_addr, backend_name);
+        match splice(client, backend, self.idle, &guard.traffic).await {
+            Ok((sent, received)) => info!(
+                "TCP {} -> backend {} closed ({} bytes sent, {} bytes received)",
+                remote_addr, backend_name, sent, received
+            ),
+            Err(e) => info!("TCP {} -> backend {} closed: {}", remote_addr, backend_name, e),
+        }
+        // La connessione smette di contare per least_connections solo qui
+        drop(guard);
+    }
+
+    /// Apre la connessione verso un backend; se fallisce prova gli altri disponibili
+    async fn connect(&self, remote_addr: SocketAddr) -> Option<(TcpStream, ConnectionGuard)> {
+        let pool = &self.listener.pool;
+        let mut ctx = SelectionContext {
+            hash: pool.strategy.uses_hash_key().then(|| hash_of(&remote_addr.ip())),
+            exclude: Vec::new(),
+        };
+
+        loop {
+            let mut guard = pool.select_and_increment(&ctx).await?;
+            let backend = &guard.backend;
+            let connect = Timeouts::resolve(&self.timeouts, &backend.timeouts).connect;
+            let address = layer4_backend_address(&backend.url).map(|(_, address)| address).unwrap_or_default().to_string();
+
+            match tokio::time::timeout(connect, TcpStream::connect(&address)).await {
+                Ok(Ok(stream)) => {
+                    let _ = stream.set_nodelay(true);
+                    pool.report_outcome(&mut guard, true);
+                    return Some((stream, guard));
+                }
+                Ok(Err(e)) => warn!("TCP listener {}: connecting to {} failed: {}", self.listener.name, address, e),
+                Err(_) => warn!("TCP listener {}: connecting to {} timed out", self.listener.name, address),
+            }
+            pool.report_outcome(&mut guard, false);
+            ctx.exclude.push(guard.backend.name.clone());
+        }
+    }
+}
+
@@ -0,0 +1,235 @@
+use crate::backend::pool::{ConnectionGuard, ListenerPool};
+use crate::backend::SelectionContext;
+use crate::config::validate::layer4_backend_address;
+use crate::config::ListenerConfig;
+use crate::lb::algorithms::hash_of;
+use anyhow::Context;
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::Interest;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
+
+/// Un datagramma UDP non supera mai questa dimensione
+const MAX_DATAGRAM: usize = 65_535;
+const DEFAULT_FLOW_IDLE: Duration = Duration::from_secs(30);
+const DEFAULT_MAX_FLOWS: usize = 10_000;
+/// Datagrammi di un client in attesa del backend; oltre vengono scartati
+const FLOW_QUEUE: usize = 64;
+
+type FlowTable = Arc<Mutex<HashMap<SocketAddr, Arc<Flow>>>>;
+
+/// Listener `mode: udp`: i datagrammi di ogni indirizzo client formano un flusso legato a
+/// un backend, così le risposte tornano al client giusto. Il flusso conta tra le connessioni
+/// del backend finché non resta inattivo per `idle`.
+#[derive(Clone)]
+pub struct UdpProxy {
+    listener: ListenerPool,
+    idle: Duration,
+    max_flows: usize,
+}
+
+/// Flusso di un indirizzo client: il suo task apre il socket verso il backend e inoltra
+/// i datagrammi in coda, così il ciclo di ricezione non aspetta DNS né backend
+struct Flow {
+    queue: mpsc::Sender<Vec<u8>>,
+    last_seen: Mutex<Instant>,
+}
+
+impl Flow {
+    fn touch(&self) {
+        *self.last_seen.lock().unwrap() = Instant::now();
+    }
+
+    fn expires_at(&self, idle: Duration) -> Instant {
+        *self.last_seen.lock().unwrap() + idle
+    }
+}
+
+impl UdpProxy {
+    pub fn new(listener: ListenerPool, config: &ListenerConfig) -> Self {
+        Self {
+            listener,
+            idle: config.idle_timeout_ms.map(Duration::from_millis).unwrap_or(DEFAULT_FLOW_IDLE),
+            max_flows: config.max_flows.unwrap_or(DEFAULT_MAX_FLOWS),
+        }
+    }
+
+    pub async fn run(self) -> anyhow::Result<()> {
+        let socket = UdpSocket::bind(self.listener.bind)
+            .await
+            .with_context(|| format!("Failed to bind {} for listener {}", self.listener.bind, self.listener.name))?;
+        let socket = Arc::new(socket);
+        let flows: FlowTable = Arc::new(Mutex::new(HashMap::new()));
+        info!(
+            "UDP listener {} running on {} (strategy {})",
+            self.listener.name, self.listener.bind, self.listener.pool.strategy
+        );
+
+        let mut buf = vec![0u8; MAX_DATAGRAM];
+        loop {
+            let (len, client) = match socket.recv_from(&mut buf).await {
+                Ok(received) => received,
+                Err(e) => {
+                    warn!("UDP listener {} error: {}", self.listener.name, e);
+                    continue;
+                }
+            };
+
+            let Some(flow) = self.flow_for(client, &socket, &flows) else {
+                debug!("UDP listener {}: {} flows open, dropping datagram from {}", self.listener.name, self.max_flows, client);
+                continue;
+            };
+            flow.touch();
+            if flow.queue.try_send(buf[..len].to_vec()).is_err() {
+                debug!("UDP listener {}: queue full for {}, dropping datagram", self.listener.name, client);
+            }
+        }
+    }
+
+    /// Flusso esistente del client, o uno nuovo se la tabella non è piena
+    fn flow_for(&self, client: SocketAddr, socket: &Arc<UdpSocket>, flows: &FlowTable) -> Option<Arc<Flow>> {
+        let mut table = flows.lock().unwrap();
+        if let Some(flow) = table.get(&client) {
+            return Some(Arc::clone(flow));
+        }
+        if table.len() >= self.max_flows {
+            return None;
+        }
+
+        let (queue, datagrams) = mpsc::channel(FLOW_QUEUE);
+        let flow = Arc::new(Flow { queue, last_seen: Mutex::new(Instant::now()) });
+        table.insert(client, Arc::clone(&flow));
+        drop(table);
+
+        let proxy = self.clone();
+        let socket = Arc::clone(socket);
+        let flows = Arc::clone(flows);
+        let task_flow = Arc::clone(&flow);
+        tokio::spawn(async move { proxy.run_flow(client, task_flow, datagrams, socket, flows).await });
+        Some(flow)
+    }
+
+    /// Sceglie il backend del client, poi inoltra i suoi datagrammi e rimanda le risposte
+    /// finché il flusso non scade
+    async fn run_flow(
+        &self,
+        client: SocketAddr,
+        flow: Arc<Flow>,
+        mut datagrams: mpsc::Receiver<Vec<u8>>,
+        socket: Arc<UdpSocket>,
+        flows: FlowTable,
+    ) {
+        let Some((backend_socket, mut guard)) = self.connect(client).await else {
+            warn!("UDP listener {}: no backend available for {}", self.listener.name, client);
+            remove_flow(&flows, client, &flow);
+            return;
+        };
+        let backend = guard.backend.name.clone();
+        let traffic = Arc::clone(&guard.traffic);
+        debug!("UDP {} -> backend {} flow opened", client, backend);
+
+        let mut buf = vec![0u8; MAX_DATAGRAM];
+        loop {
+            tokio::select! {
+                Some(datagram) = datagrams.recv() => match backend_socket.send(&datagram).await {
+                    Ok(sent) => {
+                        traffic.sent.fetch_add(sent as u64, Ordering::Relaxed);
+                    }
+                    Err(e) => debug!("UDP {} -> backend {}: send failed: {}", client, backend, e),
+                },
+                received = recv_connected(&backend_socket, &mut buf) => match received {
+                    Ok(len) => {
+                        flow.touch();
+                        traffic.received.fetch_add(len as u64, Ordering::Relaxed);
+                        if let Err(e) = socket.send_to(&buf[..len], client).await {
+                            debug!("UDP backend {} -> {}: send failed: {}", backend, client, e);
+                        }
+                    }
+                    // Di solito ICMP port unreachable: il backend non ascolta più
+                    Err(e) => {
+                        warn!("UDP listener {}: backend {} failed: {}", self.listener.name, backend, e);
+                        self.listener.pool.report_outcome(&mut guard, false);
+                        break;
+                    }
+                },
+                _ = tokio::time::sleep_until(flow.expires_at(self.idle)) => {
+                    if flow.expires_at(self.idle) <= Instant::now() {
+                        break;
+                    }
+                }
+            }
+        }
+
+        remove_flow(&flows, client, &flow);
+        debug!("UDP {} -> backend {} flow closed", client, backend);
+        drop(guard);
+    }
+
+    /// Socket connesso al backend; se non si riesce ad aprirlo prova gli altri disponibili
+    async fn connect(&self, client: SocketAddr) -> Option<(UdpSocket, ConnectionGuard)> {
+        let pool = &self.listener.pool;
+        let mut ctx = SelectionContext {
+            hash: pool.strategy.uses_hash_key().then(|| hash_of(&client.ip())),
+            exclude: Vec::new(),
+        };
+
+        loop {
+            let mut guard = pool.select_and_increment(&ctx).await?;
+            let address = layer4_backend_address(&guard.backend.url).map(|(_, address)| address).unwrap_or_default();
+            match connect_udp(address).await {
+                Ok(socket) => {
+                    pool.report_outcome(&mut guard, true);
+                    return Some((socket, guard));
+                }
+                Err(e) => warn!("UDP listener {}: connecting to {} failed: {}", self.listener.name, address, e),
+            }
+            pool.report_outcome(&mut guard, false);
+            ctx.exclude.push(guard.backend.name.clone());
+        }
+    }
+}
+
+fn remove_flow(flows: &FlowTable, client: SocketAddr, flow: &Arc<Flow>) {
+    let mut flows = flows.lock().unwrap();
+    if flows.get(&client).is_some_and(|current| Arc::ptr_eq(current, flow)) {
+        flows.remove(&client);
+    }
+}
+
+/// Socket UDP connesso a `host:porta`, della stessa famiglia dell'indirizzo risolto
+pub async fn connect_udp(address: &str) -> io::Result<UdpSocket> {
+    let target = tokio::net::lookup_host(address)
+        .await?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{address} did not resolve")))?;
+    let local: SocketAddr = if target.is_ipv4() { ([0, 0, 0, 0], 0).into() } else { ([0u16; 8], 0).into() };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(target).await?;
+    Ok(socket)
+}
+
+/// `recv` su un socket connesso che restituisce anche gli errori ICMP (port unreachable):
+/// `UdpSocket::recv` aspetta solo la readiness in lettura e non si sveglia per quella di errore
+pub async fn recv_connected(socket: &UdpSocket, buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+        let ready = socket.ready(Interest::READABLE | Interest::ERROR).await?;
+        if ready.is_error() {
+            if let Some(e) = socket.take_error()? {
+                return Err(e);
+            }
+            // Nessun errore in sospeso: va azzerata la readiness per non ciclare a vuoto
+            let _ = socket.try_io(Interest::ERROR, || Err::<(), _>(io::ErrorKind::WouldBlock.into()));
+        }
+        match socket.try_recv(buf) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+            result => return result,
+        }
+    }
+}
@@ -1,28 +1,86 @@
 use anyhow::Result;
 use tracing::info;
-use load_balancer_rs::config::Config;
+use load_balancer_rs::cli::{self, Cli, Command};
 use load_balancer_rs::lb::LoadBalancer;
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let cli = Cli::parse_args();
+
+    match cli.command() {
+        Command::Run => run(&cli).await,
+        Command::CheckConfig => check_config(&cli),
+        Command::PrintConfig => print_config(&cli),
+        Command::Backends { url, token } => print_backends(&cli, url, token).await,
+    }
+}
+
+async fn run(cli: &Cli) -> Result<()> {
     // Setup logging
     tracing_subscriber::fmt::init();
 
     info!("Starting Load Balancer...");
 
-    // Parse CLI e carica config 
-    let config = match Config::from_file("config/config.yaml"){
-        Ok(config) => config,
-        Err(error) => {
-            eprintln!("Failed to load config file: {error}");
-            // Usa configurazione di default o esci
-            Config::default()
-        }
-    };
+    // Carica config e applica override CLI: una config rotta ferma l'avvio
+    let config = cli.load_config()?;
 
     // Crea e inzia load balancer
-    let lb = LoadBalancer::new(config).await?;
+    let lb = LoadBalancer::new(config).await?.with_config_path(cli.config.clone());
     lb.start().await?;
 
     Ok(())
 }
+
+fn check_config(cli: &Cli) -> Result<()> {
+    let config = cli.load_config()?;
+    println!("{}: OK ({} backends, strategy {})", cli.config, config.backends.len(), config.lb_strategy);
+    Ok(())
+}
+
+fn print_config(cli: &Cli) -> Result<()> {
+    let config = cli.load_config()?;
+    print!("{}", serde_yaml::to_string(&config.redacted())?);
+    Ok(())
+}
+
+async fn print_backends(cli: &Cli, url: Option<String>, token: Option<String>) -> Result<()> {
+    let (url, token) = match url {
+        Some(url) => (url, token),
+        None => {
+            let config = cli.load_config()?;
+            (format!("http://{}", config.admin.bind), token.or(config.admin.token))
+        }
+    };
+
+    let backends = cli::fetch_backends(&url, token.as_deref()).await?;
+    println!("{:<20} {:<30} {:>6} {:>10} {:>12} {:>10}", "NAME", "URL", "WEIGHT", "STATUS", "CONNECTIONS", "CIRCUIT");
+    for backend in backends {
+        println!(
+            "{:<20} {:<30} {:>6} {:>10} {:>12} {:>10}",
+            backend.name,
+            backend.url,
+            backend.weight,
+            format!("{:?}", backend.status),
+            backend.connections,
+            format!("{:?}", backend.circuit)
+        );
+    }
+
+    for listener in cli::fetch_listeners(&url, token.as_deref()).await? {
+        println!();
+        println!("Listener {} ({:?} on {})", listener.name, listener.mode, listener.bind);
+        println!("{:<20} {:<30} {:>10} {:>12} {:>14} {:>14}", "NAME", "URL", "STATUS", "CONNECTIONS", "BYTES SENT", "BYTES RECV");
+        for backend in listener.backends {
+            println!(
+                "{:<20} {:<30} {:>10} {:>12} {:>14} {:>14}",
+                backend.name,
+                backend.url,
+                format!("{:?}", backend.status),
+                backend.connections,
+                backend.bytes_sent,
+                backend.bytes_received
+            );
+        }
+    }
+    Ok(())
+}
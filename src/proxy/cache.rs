@@ -0,0 +1,661 @@
+use crate::config::CacheConfig;
+use crate::proxy::compress::parse_accept_encoding;
+use futures::Stream;
+use hyper::body::{Bytes, HttpBody};
+use hyper::header::{
+    HeaderMap, HeaderName, HeaderValue, ACCEPT_ENCODING, AGE, AUTHORIZATION, CACHE_CONTROL, CONTENT_ENCODING,
+    CONTENT_LENGTH, CONTENT_LOCATION, CONTENT_RANGE, DATE, ETAG, EXPIRES, HOST, IF_MODIFIED_SINCE, IF_NONE_MATCH,
+    LAST_MODIFIED, SET_COOKIE, TRANSFER_ENCODING, VARY,
+};
+use hyper::{Request, Response, StatusCode};
+use lru::LruCache;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Status memorizzabili senza indicazioni particolari (RFC 9110, sezione 15.1)
+const CACHEABLE_STATUS: [u16; 11] = [200, 203, 204, 300, 301, 308, 404, 405, 410, 414, 501];
+
+/// Header della risposta che indica come è stata servita: HIT, STALE, REVALIDATED, COALESCED o MISS
+pub const X_CACHE: &str = "x-cache";
+
+/// Direttive di `Cache-Control` (di richiesta o di risposta) rilevanti per la cache
+#[derive(Debug, Default, Clone)]
+pub struct CacheControl {
+    pub no_store: bool,
+    pub no_cache: bool,
+    pub private: bool,
+    pub public: bool,
+    pub must_revalidate: bool,
+    pub max_age: Option<u64>,
+    pub s_maxage: Option<u64>,
+    pub stale_while_revalidate: Option<u64>,
+    pub stale_if_error: Option<u64>,
+}
+
+impl CacheControl {
+    pub fn parse(headers: &HeaderMap) -> Self {
+        let mut control = Self::default();
+        let directives = headers
+            .get_all(CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','));
+        for directive in directives {
+            let (name, value) = match directive.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+                None => (directive.trim(), None),
+            };
+            let seconds = value.and_then(|value| value.parse().ok());
+            match name.to_ascii_lowercase().as_str() {
+                "no-store" => control.no_store = true,
+                // no-cache="campo" e private="campo" valgono come le forme semplici: più prudente
+                "no-cache" => control.no_cache = true,
+                "private" => control.private = true,
+                "public" => control.public = true,
+                "must-revalidate" | "proxy-revalidate" => control.must_revalidate = true,
+                "max-age" => control.max_age = seconds,
+                "s-maxage" => control.s_maxage = seconds,
+                "stale-while-revalidate" => control.stale_while_revalidate = seconds,
+                "stale-if-error" => control.stale_if_error = seconds,
+                _ => {}
+            }
+        }
+        control
+    }
+}
+
+/// Risposta completa in cache, per una combinazione dei valori degli header in `Vary`
+pub struct CachedResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    /// Header della richiesta elencati in `Vary`, come li ha inviati il client che ha popolato la voce
+    vary: Vec<(HeaderName, String)>,
+    control: CacheControl,
+    lifetime: Duration,
+    /// Età già accumulata a monte quando la risposta è arrivata (header `Age`)
+    initial_age: Duration,
+    stored_at: Instant,
+    size: usize,
+    revalidating: AtomicBool,
+}
+
+impl CachedResponse {
+    fn new(
+        status: StatusCode,
+        headers: HeaderMap,
+        body: Bytes,
+        vary: Vec<(HeaderName, String)>,
+        stored_at: Instant,
+    ) -> Self {
+        let control = CacheControl::parse(&headers);
+        let lifetime = freshness_lifetime(&control, &headers).unwrap_or(Duration::ZERO);
+        let initial_age = headers
+            .get(AGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::ZERO);
+        let size = body.len()
+            + headers.iter().map(|(name, value)| name.as_str().len() + value.len()).sum::<usize>()
+            + vary.iter().map(|(name, value)| name.as_str().len() + value.len()).sum::<usize>();
+        Self {
+            status,
+            headers,
+            body,
+            vary,
+            control,
+            lifetime,
+            initial_age,
+            stored_at,
+            size,
+            revalidating: AtomicBool::new(false),
+        }
+    }
+
+    fn age(&self) -> Duration {
+        self.initial_age + self.stored_at.elapsed()
+    }
+
+    fn staleness(&self) -> Duration {
+        self.age().saturating_sub(self.lifetime)
+    }
+
+    /// Può essere servita senza contattare il backend
+    pub fn is_fresh(&self, request: &CacheControl) -> bool {
+        let age = self.age();
+        !self.control.no_cache
+            && !request.no_cache
+            && age < self.lifetime
+            && request.max_age.is_none_or(|max_age| age <= Duration::from_secs(max_age))
+    }
+
+    /// Scaduta ma ancora servibile mentre la si rinnova in background (stale-while-revalidate)
+    pub fn serve_while_revalidating(&self, request: &CacheControl) -> bool {
+        !self.control.must_revalidate
+            && !self.control.no_cache
+            && !request.no_cache
+            && request.max_age.is_none()
+            && self.control.stale_while_revalidate.is_some_and(|secs| self.staleness().as_secs() <= secs)
+    }
+
+    /// Scaduta ma ancora servibile se il backend risponde con un errore (stale-if-error)
+    pub fn serve_on_error(&self, request: &CacheControl) -> bool {
+        let allowed = request.stale_if_error.or(self.control.stale_if_error);
+        !self.control.must_revalidate && allowed.is_some_and(|secs| self.staleness().as_secs() <= secs)
+    }
+
+    pub fn has_validators(&self) -> bool {
+        self.headers.contains_key(ETAG) || self.headers.contains_key(LAST_MODIFIED)
+    }
+
+    /// Sostituisce le condizioni del client con i validatori della copia in cache
+    pub fn add_validators(&self, headers: &mut HeaderMap) {
+        headers.remove(IF_NONE_MATCH);
+        headers.remove(IF_MODIFIED_SINCE);
+        if let Some(etag) = self.headers.get(ETAG) {
+            headers.insert(IF_NONE_MATCH, etag.clone());
+        }
+        if let Some(last_modified) = self.headers.get(LAST_MODIFIED) {
+            headers.insert(IF_MODIFIED_SINCE, last_modified.clone());
+        }
+    }
+
+    /// Una sola revalidazione in background per volta
+    pub fn start_revalidation(&self) -> bool {
+        !self.revalidating.swap(true, Ordering::AcqRel)
+    }
+
+    pub fn finish_revalidation(&self) {
+        self.revalidating.store(false, Ordering::Release);
+    }
+
+    /// Risposta per il client, con 304 se le sue condizioni corrispondono alla copia in cache
+    pub fn to_response(&self, request_headers: &HeaderMap, head_request: bool, outcome: &'static str) -> Response<hyper::Body> {
+        let mut response = if self.matches_client_validators(request_headers) {
+            let mut response = Response::new(hyper::Body::empty());
+            *response.status_mut() = StatusCode::NOT_MODIFIED;
+            for name in [CACHE_CONTROL, CONTENT_LOCATION, DATE, ETAG, EXPIRES, VARY] {
+                for value in self.headers.get_all(&name) {
+                    response.headers_mut().append(name.clone(), value.clone());
+                }
+            }
+            response
+        } else {
+            let body = if head_request { hyper::Body::empty() } else { hyper::Body::from(self.body.clone()) };
+            let mut response = Response::new(body);
+            *response.status_mut() = self.status;
+            *response.headers_mut() = self.headers.clone();
+            response
+        };
+        response.headers_mut().insert(AGE, HeaderValue::from(self.age().as_secs()));
+        response.headers_mut().insert(X_CACHE, HeaderValue::from_static(outcome));
+        response
+    }
+
+    fn matches_client_validators(&self, request_headers: &HeaderMap) -> bool {
+        if self.status != StatusCode::OK {
+            return false;
+        }
+        // If-None-Match ha la precedenza su If-Modified-Since (RFC 9110, sezione 13.2.2)
+        if let Some(if_none_match) = request_headers.get(IF_NONE_MATCH).and_then(|value| value.to_str().ok()) {
+            let etag = self.headers.get(ETAG).and_then(|value| value.to_str().ok());
+            return if_none_match
+                .split(',')
+                .map(str::trim)
+                .any(|tag| tag == "*" || etag.is_some_and(|etag| weak_eq(tag, etag)));
+        }
+        let since = header_date(request_headers, IF_MODIFIED_SINCE);
+        let modified = header_date(&self.headers, LAST_MODIFIED);
+        matches!((since, modified), (Some(since), Some(modified)) if modified <= since)
+    }
+
+    fn matches_vary(&self, request_headers: &HeaderMap) -> bool {
+        self.vary.iter().all(|(name, value)| request_value(request_headers, name) == *value)
+    }
+}
+
+/// Cache in memoria delle risposte dei backend, condivisa da tutti i listener.
+/// Le voci usate meno di recente vengono scartate oltre `max_size_bytes`.
+pub struct ResponseCache {
+    max_size_bytes: usize,
+    max_entry_bytes: usize,
+    store: Mutex<Store>,
+}
+
+struct Store {
+    /// Per ogni URL, una risposta per ogni variante di `Vary`
+    entries: LruCache<String, Vec<Arc<CachedResponse>>>,
+    size: usize,
+}
+
+/// Risposta memorizzabile in arrivo: diventa una voce quando il corpo è completo
+struct PendingEntry {
+    key: String,
+    status: StatusCode,
+    headers: HeaderMap,
+    vary: Vec<(HeaderName, String)>,
+    received_at: Instant,
+    /// Con `Content-Length` la voce è completa all'ultimo byte: hyper non legge oltre
+    expected_len: Option<usize>,
+}
+
+impl ResponseCache {
+    pub fn new(config: &CacheConfig) -> Self {
+        Self {
+            max_size_bytes: config.max_size_bytes,
+            max_entry_bytes: config.max_entry_bytes,
+            store: Mutex::new(Store { entries: LruCache::unbounded(), size: 0 }),
+        }
+    }
+
+    /// Chiave primaria: host e path con query string
+    pub fn key(req: &Request<hyper::Body>) -> String {
+        let host = req.headers()
+            .get(HOST)
+            .and_then(|value| value.to_str().ok())
+            .or_else(|| req.uri().authority().map(|authority| authority.as_str()))
+            .unwrap_or("");
+        let path = req.uri().path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
+        format!("{host}{path}")
+    }
+
+    pub fn max_entry_bytes(&self) -> usize {
+        self.max_entry_bytes
+    }
+
+    pub fn lookup(&self, key: &str, request_headers: &HeaderMap) -> Option<Arc<CachedResponse>> {
+        let mut store = self.store.lock().unwrap();
+        store.entries
+            .get(key)?
+            .iter()
+            .find(|entry| entry.matches_vary(request_headers))
+            .cloned()
+    }
+
+    pub fn invalidate(&self, key: &str) {
+        let mut store = self.store.lock().unwrap();
+        if let Some(variants) = store.entries.pop(key) {
+            store.size -= variants.iter().map(|entry| entry.size).sum::<usize>();
+        }
+    }
+
+    /// Aggiorna la copia in cache con gli header di un 304 (RFC 9111, sezione 4.3.4)
+    pub fn refresh(&self, key: &str, entry: &CachedResponse, not_modified: &HeaderMap) -> Arc<CachedResponse> {
+        let mut headers = entry.headers.clone();
+        for name in not_modified.keys() {
+            // Le varianti sono già state calcolate con il Vary originale; i cookie di un
+            // client non vanno serviti a tutti gli altri
+            if [CONTENT_LENGTH, CONTENT_ENCODING, CONTENT_RANGE, TRANSFER_ENCODING, VARY, SET_COOKIE].contains(name) {
+                continue;
+            }
+            headers.remove(name);
+            for value in not_modified.get_all(name) {
+                headers.append(name.clone(), value.clone());
+            }
+        }
+        let refreshed = Arc::new(CachedResponse::new(
+            entry.status,
+            headers,
+            entry.body.clone(),
+            entry.vary.clone(),
+            Instant::now(),
+        ));
+        self.insert(key.to_string(), Arc::clone(&refreshed));
+        refreshed
+    }
+
+    /// Se la risposta è memorizzabile il corpo viene copiato in cache mentre arriva al client.
+    /// Il flag indica se la copia è in corso.
+    pub fn store(
+        self: &Arc<Self>,
+        key: String,
+        request: &CacheControl,
+        request_headers: &HeaderMap,
+        mut response: Response<hyper::Body>,
+    ) -> (Response<hyper::Body>, bool) {
+        response.headers_mut().insert(X_CACHE, HeaderValue::from_static("MISS"));
+        let Some(vary) = self.storable(request, request_headers, response.status(), response.headers()) else {
+            return (response, false);
+        };
+
+        let (parts, body) = response.into_parts();
+        let mut headers = parts.headers.clone();
+        headers.remove(X_CACHE);
+        let pending = PendingEntry {
+            key,
+            status: parts.status,
+            expected_len: content_length(&headers),
+            headers,
+            vary,
+            received_at: Instant::now(),
+        };
+        let mut caching = CachingBody {
+            inner: body,
+            cache: Arc::clone(self),
+            pending: Some(pending),
+            buffer: Vec::new(),
+        };
+        // Un corpo vuoto potrebbe non essere mai letto
+        if caching.pending.as_ref().is_some_and(|pending| pending.expected_len == Some(0)) {
+            caching.complete();
+        }
+        (Response::from_parts(parts, hyper::Body::wrap_stream(caching)), true)
+    }
+
+    /// Valori di `Vary` della richiesta se la risposta può essere memorizzata (RFC 9111, sezione 3)
+    pub fn storable(
+        &self,
+        request: &CacheControl,
+        request_headers: &HeaderMap,
+        status: StatusCode,
+        headers: &HeaderMap,
+    ) -> Option<Vec<(HeaderName, String)>> {
+        let control = CacheControl::parse(headers);
+        if request.no_store || control.no_store || control.private {
+            return None;
+        }
+        if !CACHEABLE_STATUS.contains(&status.as_u16())
+            || headers.contains_key(SET_COOKIE)
+            || headers.contains_key(CONTENT_RANGE)
+        {
+            return None;
+        }
+        // Risposte a richieste autenticate solo se il backend le dichiara condivisibili
+        if request_headers.contains_key(AUTHORIZATION)
+            && !(control.public || control.s_maxage.is_some() || control.must_revalidate)
+        {
+            return None;
+        }
+        if content_length(headers).is_some_and(|len| len > self.max_entry_bytes) {
+            return None;
+        }
+        // Senza scadenza esplicita ha senso solo se si può rivalidare
+        let lifetime = freshness_lifetime(&control, headers).unwrap_or(Duration::ZERO);
+        if lifetime.is_zero() && !(headers.contains_key(ETAG) || headers.contains_key(LAST_MODIFIED)) {
+            return None;
+        }
+
+        let mut vary = Vec::new();
+        for field in headers.get_all(VARY).iter().filter_map(|value| value.to_str().ok()).flat_map(|value| value.split(',')) {
+            let field = field.trim();
+            if field == "*" {
+                return None;
+            }
+            if let Ok(name) = HeaderName::from_bytes(field.as_bytes()) {
+                let value = request_value(request_headers, &name);
+                vary.push((name, value));
+            }
+        }
+        Some(vary)
+    }
+
+    fn insert(&self, key: String, entry: Arc<CachedResponse>) {
+        if entry.size > self.max_entry_bytes {
+            return;
+        }
+        let mut store = self.store.lock().unwrap();
+        let Store { entries, size } = &mut *store;
+
+        let variants = entries.get_or_insert_mut(key, Vec::new);
+        if let Some(index) = variants.iter().position(|existing| existing.vary == entry.vary) {
+            *size -= variants.remove(index).size;
+        }
+        *size += entry.size;
+        variants.push(entry);
+
+        while *size > self.max_size_bytes {
+            let Some((_, evicted)) = entries.pop_lru() else { break };
+            *size -= evicted.iter().map(|entry| entry.size).sum::<usize>();
+        }
+    }
+}
+
+/// Corpo della risposta che ne tiene una copia (fino a `max_entry_bytes`) e la mette
+/// in cache solo se arriva completo
+struct CachingBody {
+    inner: hyper::Body,
+    cache: Arc<ResponseCache>,
+    pending: Option<PendingEntry>,
+    buffer: Vec<u8>,
+}
+
+impl CachingBody {
+    fn complete(&mut self) {
+        if let Some(pending) = self.pending.take() {
+            let body = Bytes::from(std::mem::take(&mut self.buffer));
+            let entry = CachedResponse::new(pending.status, pending.headers, body, pending.vary, pending.received_at);
+            self.cache.insert(pending.key, Arc::new(entry));
+        }
+    }
+}
+
+impl Stream for CachingBody {
+    type Item = Result<Bytes, hyper::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.inner).poll_data(cx) {
+            Poll::Ready(Some(Ok(chunk))) => {
+                if let Some(pending) = &this.pending {
+                    if this.buffer.len() + chunk.len() > this.cache.max_entry_bytes {
+                        this.pending = None;
+                        this.buffer = Vec::new();
+                    } else {
+                        this.buffer.extend_from_slice(&chunk);
+                        if pending.expected_len == Some(this.buffer.len()) {
+                            this.complete();
+                        }
+                    }
+                }
+                Poll::Ready(Some(Ok(chunk)))
+            }
+            Poll::Ready(Some(Err(e))) => {
+                this.pending = None;
+                Poll::Ready(Some(Err(e)))
+            }
+            Poll::Ready(None) => {
+                this.complete();
+                Poll::Ready(None)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Durata di validità esplicita: s-maxage, max-age o Expires rispetto a Date
+fn freshness_lifetime(control: &CacheControl, headers: &HeaderMap) -> Option<Duration> {
+    if let Some(secs) = control.s_maxage.or(control.max_age) {
+        return Some(Duration::from_secs(secs));
+    }
+    headers.get(EXPIRES)?;
+    // Un Expires non valido (es. "0") indica una risposta già scaduta
+    let Some(expires) = header_date(headers, EXPIRES) else {
+        return Some(Duration::ZERO);
+    };
+    let date = header_date(headers, DATE).unwrap_or_else(SystemTime::now);
+    Some(expires.duration_since(date).unwrap_or(Duration::ZERO))
+}
+
+fn content_length(headers: &HeaderMap) -> Option<usize> {
+    headers
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+}
+
+fn header_date(headers: &HeaderMap, name: HeaderName) -> Option<SystemTime> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| httpdate::parse_http_date(value).ok())
+}
+
+/// Valore degli header della richiesta usato per distinguere le varianti.
+/// Accept-Encoding è normalizzato: client con le stesse preferenze condividono la variante compressa.
+pub fn request_value(headers: &HeaderMap, name: &HeaderName) -> String {
+    let values = headers.get_all(name).iter().filter_map(|value| value.to_str().ok());
+    if name == ACCEPT_ENCODING {
+        let mut codings = values.flat_map(parse_accept_encoding).collect::<Vec<_>>();
+        codings.sort();
+        return codings
+            .iter()
+            .map(|(coding, qvalue)| format!("{coding};q={qvalue}"))
+            .collect::<Vec<_>>()
+            .join(",");
+    }
+    values.collect::<Vec<_>>().join(", ")
+}
+
+/// Confronto debole tra entity tag (RFC 9110, sezione 8.8.3.2)
+fn weak_eq(a: &str, b: &str) -> bool {
+    a.trim_start_matches("W/") == b.trim_start_matches("W/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&str, &str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (HeaderName::from_bytes(name.as_bytes()).unwrap(), HeaderValue::from_str(value).unwrap()))
+            .collect()
+    }
+
+    fn entry(response_headers: &[(&str, &str)], body: &'static [u8]) -> CachedResponse {
+        CachedResponse::new(StatusCode::OK, headers(response_headers), Bytes::from_static(body), Vec::new(), Instant::now())
+    }
+
+    fn cache(max_size_bytes: usize, max_entry_bytes: usize) -> ResponseCache {
+        ResponseCache::new(&CacheConfig { max_size_bytes, max_entry_bytes, ..CacheConfig::default() })
+    }
+
+    fn lifetime(response_headers: &[(&str, &str)]) -> Option<Duration> {
+        let response_headers = headers(response_headers);
+        freshness_lifetime(&CacheControl::parse(&response_headers), &response_headers)
+    }
+
+    #[test]
+    fn cache_control_parse() {
+        let control = CacheControl::parse(&headers(&[
+            ("cache-control", "Public, max-age=60, s-maxage=\"120\""),
+            ("cache-control", "stale-while-revalidate=30, stale-if-error=600, proxy-revalidate, no-cache=\"set-cookie\""),
+        ]));
+        assert!(control.public && control.must_revalidate && control.no_cache);
+        assert!(!control.no_store && !control.private);
+        assert_eq!(control.max_age, Some(60));
+        assert_eq!(control.s_maxage, Some(120));
+        assert_eq!(control.stale_while_revalidate, Some(30));
+        assert_eq!(control.stale_if_error, Some(600));
+
+        // Valori non numerici vengono ignorati
+        let control = CacheControl::parse(&headers(&[("cache-control", "no-store, private, max-age=abc")]));
+        assert!(control.no_store && control.private);
+        assert_eq!(control.max_age, None);
+    }
+
+    #[test]
+    fn freshness_lifetime_prefers_s_maxage_then_max_age_then_expires() {
+        assert_eq!(lifetime(&[("cache-control", "max-age=60, s-maxage=120")]), Some(Duration::from_secs(120)));
+        assert_eq!(
+            lifetime(&[("cache-control", "max-age=60"), ("expires", "Thu, 01 Jan 2037 00:00:00 GMT")]),
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(
+            lifetime(&[("date", "Mon, 01 Jan 2024 00:00:00 GMT"), ("expires", "Mon, 01 Jan 2024 00:05:00 GMT")]),
+            Some(Duration::from_secs(300))
+        );
+        // Expires nel passato o non valido: già scaduta
+        assert_eq!(
+            lifetime(&[("date", "Mon, 01 Jan 2024 00:05:00 GMT"), ("expires", "Mon, 01 Jan 2024 00:00:00 GMT")]),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(lifetime(&[("expires", "0")]), Some(Duration::ZERO));
+        assert_eq!(lifetime(&[("etag", "\"v1\"")]), None);
+    }
+
+    #[test]
+    fn storable_rules() {
+        let cache = cache(1024, 1024);
+        let storable = |request: &[(&str, &str)], response: &[(&str, &str)]| {
+            let request = headers(request);
+            cache.storable(&CacheControl::parse(&request), &request, StatusCode::OK, &headers(response)).is_some()
+        };
+
+        assert!(storable(&[], &[("cache-control", "max-age=60")]));
+        assert!(storable(&[], &[("etag", "\"v1\"")]));
+        assert!(!storable(&[], &[]));
+        assert!(!storable(&[], &[("cache-control", "private, max-age=60")]));
+        assert!(!storable(&[("cache-control", "no-store")], &[("cache-control", "max-age=60")]));
+        assert!(!storable(&[], &[("cache-control", "max-age=60"), ("set-cookie", "id=1")]));
+        assert!(!storable(&[], &[("cache-control", "max-age=60"), ("vary", "Accept-Language, *")]));
+        assert!(!storable(&[], &[("cache-control", "max-age=60"), ("content-length", "2048")]));
+        // Richieste autenticate solo se la risposta è dichiarata condivisibile
+        assert!(!storable(&[("authorization", "Bearer t")], &[("cache-control", "max-age=60")]));
+        assert!(storable(&[("authorization", "Bearer t")], &[("cache-control", "public, max-age=60")]));
+        assert!(storable(&[("authorization", "Bearer t")], &[("cache-control", "s-maxage=60")]));
+        let response = headers(&[("cache-control", "max-age=60")]);
+        assert!(cache.storable(&CacheControl::default(), &HeaderMap::new(), StatusCode::INTERNAL_SERVER_ERROR, &response).is_none());
+    }
+
+    #[test]
+    fn storable_records_vary_values() {
+        let cache = cache(1024, 1024);
+        let request = headers(&[("accept-language", "it"), ("accept-encoding", "gzip, br")]);
+        let vary = cache
+            .storable(
+                &CacheControl::default(),
+                &request,
+                StatusCode::OK,
+                &headers(&[("cache-control", "max-age=60"), ("vary", "Accept-Language, Accept-Encoding")]),
+            )
+            .unwrap();
+        assert_eq!(vary[0], (HeaderName::from_static("accept-language"), "it".to_string()));
+        // Accept-Encoding normalizzato: l'ordine del client non crea varianti diverse
+        assert_eq!(vary[1].1, request_value(&headers(&[("accept-encoding", "br,gzip")]), &ACCEPT_ENCODING));
+    }
+
+    #[test]
+    fn client_validators() {
+        let cached = entry(&[("etag", "W/\"v1\""), ("last-modified", "Mon, 01 Jan 2024 00:00:00 GMT")], b"body");
+        let matches = |request: &[(&str, &str)]| cached.matches_client_validators(&headers(request));
+
+        assert!(matches(&[("if-none-match", "\"v1\"")]));
+        assert!(matches(&[("if-none-match", "\"v0\", W/\"v1\"")]));
+        assert!(matches(&[("if-none-match", "*")]));
+        assert!(!matches(&[("if-none-match", "\"v2\"")]));
+        assert!(matches(&[("if-modified-since", "Mon, 01 Jan 2024 00:00:00 GMT")]));
+        assert!(!matches(&[("if-modified-since", "Sun, 31 Dec 2023 00:00:00 GMT")]));
+        // If-None-Match ha la precedenza su If-Modified-Since
+        assert!(!matches(&[("if-none-match", "\"v2\""), ("if-modified-since", "Mon, 01 Jan 2024 00:00:00 GMT")]));
+        assert!(!matches(&[]));
+
+        let to_response = cached.to_response(&headers(&[("if-none-match", "\"v1\"")]), false, "HIT");
+        assert_eq!(to_response.status(), StatusCode::NOT_MODIFIED);
+    }
+
+    #[test]
+    fn lru_eviction_by_size() {
+        // Voci senza header: la dimensione è quella del corpo
+        let cache = cache(250, 200);
+        cache.insert("a".to_string(), Arc::new(entry(&[], &[0; 100])));
+        cache.insert("b".to_string(), Arc::new(entry(&[], &[0; 100])));
+        assert!(cache.lookup("a", &HeaderMap::new()).is_some());
+
+        // "b" è la meno usata di recente
+        cache.insert("c".to_string(), Arc::new(entry(&[], &[0; 100])));
+        assert!(cache.lookup("b", &HeaderMap::new()).is_none());
+        assert!(cache.lookup("a", &HeaderMap::new()).is_some());
+        assert!(cache.lookup("c", &HeaderMap::new()).is_some());
+        assert_eq!(cache.store.lock().unwrap().size, 200);
+
+        // Oltre max_entry_bytes la voce non entra
+        cache.insert("d".to_string(), Arc::new(entry(&[], &[0; 201])));
+        assert!(cache.lookup("d", &HeaderMap::new()).is_none());
+        assert_eq!(cache.store.lock().unwrap().size, 200);
+    }
+}
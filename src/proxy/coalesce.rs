@@ -0,0 +1,264 @@
+use crate::proxy::cache::{request_value, ResponseCache, X_CACHE};
+use futures::{stream, StreamExt};
+use hyper::body::{Bytes, HttpBody};
+use hyper::header::{HeaderMap, HeaderName, HeaderValue, ACCEPT_ENCODING, IF_MODIFIED_SINCE, IF_NONE_MATCH};
+use hyper::{Request, Response, StatusCode};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+
+type Published = Option<Arc<SharedResponse>>;
+
+/// Risposta completa del backend, condivisa con le richieste identiche in attesa
+pub struct SharedResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+    /// Valori di `Vary` della richiesta che l'ha ottenuta
+    pub vary: Vec<(HeaderName, String)>,
+}
+
+impl SharedResponse {
+    pub fn to_response(&self) -> Response<hyper::Body> {
+        let mut response = Response::new(hyper::Body::from(self.body.clone()));
+        *response.status_mut() = self.status;
+        *response.headers_mut() = self.headers.clone();
+        response.headers_mut().insert(X_CACHE, HeaderValue::from_static("COALESCED"));
+        response
+    }
+}
+
+/// Single-flight delle richieste verso i backend: tra le richieste identiche in corso
+/// solo la prima (leader) arriva al backend, le altre aspettano la sua risposta.
+pub struct RequestCoalescer {
+    flights: Arc<Mutex<HashMap<String, watch::Receiver<Published>>>>,
+    wait: Duration,
+}
+
+pub enum Flight {
+    Leader(Leader),
+    Follower(Follower),
+}
+
+impl RequestCoalescer {
+    pub fn new(wait: Duration) -> Self {
+        Self {
+            flights: Arc::new(Mutex::new(HashMap::new())),
+            wait,
+        }
+    }
+
+    /// Metodo, URL e header da cui può dipendere la risposta (codifica e condizioni)
+    pub fn key(req: &Request<hyper::Body>) -> String {
+        let headers = req.headers();
+        format!(
+            "{} {}\n{}\n{}\n{}",
+            req.method(),
+            ResponseCache::key(req),
+            request_value(headers, &ACCEPT_ENCODING),
+            request_value(headers, &IF_NONE_MATCH),
+            request_value(headers, &IF_MODIFIED_SINCE),
+        )
+    }
+
+    pub fn join(&self, key: String) -> Flight {
+        let mut flights = self.flights.lock().unwrap();
+        if let Some(receiver) = flights.get(&key) {
+            return Flight::Follower(Follower { receiver: receiver.clone(), wait: self.wait });
+        }
+        let (sender, receiver) = watch::channel(None);
+        flights.insert(key.clone(), receiver);
+        Flight::Leader(Leader { key, flights: Arc::clone(&self.flights), sender })
+    }
+}
+
+/// Richiesta che va al backend per tutte; se viene abbandonata i follower proseguono da soli
+pub struct Leader {
+    key: String,
+    flights: Arc<Mutex<HashMap<String, watch::Receiver<Published>>>>,
+    sender: watch::Sender<Published>,
+}
+
+impl Leader {
+    /// Legge il corpo (fino a `max_body_bytes`) e lo condivide con i follower.
+    /// `vary` è `None` se la risposta non è condivisibile: allora passa al client così com'è.
+    pub async fn publish(
+        self,
+        response: Response<hyper::Body>,
+        vary: Option<Vec<(HeaderName, String)>>,
+        max_body_bytes: usize,
+    ) -> Response<hyper::Body> {
+        let Some(vary) = vary else {
+            return response;
+        };
+
+        let (parts, mut body) = response.into_parts();
+        let mut chunks = Vec::new();
+        let mut size = 0;
+        while let Some(chunk) = body.data().await {
+            match chunk {
+                Ok(chunk) => {
+                    size += chunk.len();
+                    chunks.push(chunk);
+                    // Troppo grande da condividere: il leader continua in streaming
+                    if size > max_body_bytes {
+                        let rest = stream::iter(chunks.into_iter().map(Ok)).chain(body);
+                        return Response::from_parts(parts, hyper::Body::wrap_stream(rest));
+                    }
+                }
+                Err(e) => {
+                    let rest = stream::iter(chunks.into_iter().map(Ok)).chain(stream::once(async { Err(e) }));
+                    return Response::from_parts(parts, hyper::Body::wrap_stream(rest));
+                }
+            }
+        }
+
+        let body = Bytes::from(chunks.concat());
+        let shared = Arc::new(SharedResponse {
+            status: parts.status,
+            headers: parts.headers.clone(),
+            body: body.clone(),
+            vary,
+        });
+        self.sender.send_replace(Some(shared));
+        Response::from_parts(parts, hyper::Body::from(body))
+    }
+}
+
+impl Drop for Leader {
+    fn drop(&mut self) {
+        self.flights.lock().unwrap().remove(&self.key);
+    }
+}
+
+pub struct Follower {
+    receiver: watch::Receiver<Published>,
+    wait: Duration,
+}
+
+impl Follower {
+    /// Risposta del leader, `None` se non arriva in tempo o non è condivisibile
+    pub async fn wait(mut self) -> Option<Arc<SharedResponse>> {
+        match tokio::time::timeout(self.wait, self.receiver.wait_for(Option::is_some)).await {
+            Ok(Ok(published)) => published.clone(),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coalescer() -> RequestCoalescer {
+        RequestCoalescer::new(Duration::from_secs(5))
+    }
+
+    fn request(uri: &str, accept_encoding: Option<&str>) -> Request<hyper::Body> {
+        let mut builder = Request::get(uri).header("host", "example.com");
+        if let Some(accept_encoding) = accept_encoding {
+            builder = builder.header(ACCEPT_ENCODING, accept_encoding);
+        }
+        builder.body(hyper::Body::empty()).unwrap()
+    }
+
+    fn backend_response(body: &'static str) -> Response<hyper::Body> {
+        Response::builder()
+            .status(StatusCode::OK)
+            .header("content-type", "text/plain")
+            .body(hyper::Body::from(body))
+            .unwrap()
+    }
+
+    fn leader(flight: Flight) -> Leader {
+        match flight {
+            Flight::Leader(leader) => leader,
+            Flight::Follower(_) => panic!("expected a leader"),
+        }
+    }
+
+    fn follower(flight: Flight) -> Follower {
+        match flight {
+            Flight::Follower(follower) => follower,
+            Flight::Leader(_) => panic!("expected a follower"),
+        }
+    }
+
+    #[tokio::test]
+    async fn follower_receives_the_leader_response() {
+        let coalescer = coalescer();
+        let key = RequestCoalescer::key(&request("/page", None));
+        let leader = leader(coalescer.join(key.clone()));
+        let waiting = tokio::spawn(follower(coalescer.join(key.clone())).wait());
+
+        let response = leader.publish(backend_response("hello"), Some(Vec::new()), 1024).await;
+        assert_eq!(hyper::body::to_bytes(response.into_body()).await.unwrap(), "hello");
+
+        let shared = waiting.await.unwrap().expect("leader response");
+        let response = shared.to_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "text/plain");
+        assert_eq!(response.headers()[X_CACHE], "COALESCED");
+        assert_eq!(hyper::body::to_bytes(response.into_body()).await.unwrap(), "hello");
+
+        // Volo concluso: la richiesta successiva va di nuovo al backend
+        assert!(matches!(coalescer.join(key), Flight::Leader(_)));
+    }
+
+    #[tokio::test]
+    async fn follower_falls_through_when_the_leader_is_dropped() {
+        let coalescer = coalescer();
+        let key = RequestCoalescer::key(&request("/page", None));
+        let leader = leader(coalescer.join(key.clone()));
+        let waiting = follower(coalescer.join(key.clone()));
+
+        drop(leader);
+        let started = tokio::time::Instant::now();
+        assert!(waiting.wait().await.is_none());
+        // Niente attesa fino al timeout
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(matches!(coalescer.join(key), Flight::Leader(_)));
+    }
+
+    #[tokio::test]
+    async fn follower_falls_through_on_a_non_shareable_response() {
+        let coalescer = coalescer();
+        let key = RequestCoalescer::key(&request("/private", None));
+        let leader = leader(coalescer.join(key.clone()));
+        let waiting = tokio::spawn(follower(coalescer.join(key)).wait());
+
+        let response = leader.publish(backend_response("secret"), None, 1024).await;
+        assert_eq!(hyper::body::to_bytes(response.into_body()).await.unwrap(), "secret");
+        assert!(waiting.await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn follower_falls_through_on_a_body_too_large_to_share() {
+        let coalescer = coalescer();
+        let key = RequestCoalescer::key(&request("/large", None));
+        let leader = leader(coalescer.join(key.clone()));
+        let waiting = tokio::spawn(follower(coalescer.join(key)).wait());
+
+        let response = leader.publish(backend_response("0123456789"), Some(Vec::new()), 4).await;
+        // Il leader riceve comunque tutto il corpo
+        assert_eq!(hyper::body::to_bytes(response.into_body()).await.unwrap(), "0123456789");
+        assert!(waiting.await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn different_requests_do_not_coalesce() {
+        let coalescer = coalescer();
+        let keys = [
+            RequestCoalescer::key(&request("/a", None)),
+            RequestCoalescer::key(&request("/b", None)),
+            RequestCoalescer::key(&request("/a?page=2", None)),
+            RequestCoalescer::key(&request("/a", Some("gzip"))),
+        ];
+        let leaders: Vec<Leader> = keys.iter().map(|key| leader(coalescer.join(key.clone()))).collect();
+        assert_eq!(leaders.len(), keys.len());
+
+        // Stessa richiesta di un volo in corso: follower
+        assert!(matches!(coalescer.join(RequestCoalescer::key(&request("/a", None))), Flight::Follower(_)));
+    }
+}
@@ -0,0 +1,406 @@
+use crate::config::CompressionConfig;
+use crate::proxy::grpc::is_grpc;
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use futures::Stream;
+use hyper::body::{Bytes, HttpBody};
+use hyper::header::{HeaderValue, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, VARY};
+use hyper::{Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tracing::info;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Codifiche supportate per il corpo delle risposte
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentCoding {
+    #[serde(rename = "br")]
+    Brotli,
+    Zstd,
+    Gzip,
+    Deflate,
+}
+
+impl ContentCoding {
+    /// Nome del token in `Accept-Encoding` e `Content-Encoding`
+    pub fn token(&self) -> &'static str {
+        match self {
+            ContentCoding::Brotli => "br",
+            ContentCoding::Zstd => "zstd",
+            ContentCoding::Gzip => "gzip",
+            ContentCoding::Deflate => "deflate",
+        }
+    }
+}
+
+/// Comprime la risposta in streaming se il client lo accetta e il contenuto lo merita.
+/// Il corpo non viene mai letto tutto in memoria: ogni blocco viene compresso appena arriva.
+pub fn compress_response(
+    mut response: Response<hyper::Body>,
+    accept_encoding: Option<&str>,
+    head_request: bool,
+    config: &CompressionConfig,
+) -> Response<hyper::Body> {
+    let content_type = response.headers()
+        .get(CONTENT_TYPE)
+        .and_then(|ct| ct.to_str().ok())
+        .unwrap_or("unknown");
+
+    info!("Compression check - Content-Type: {}, Accept-Encoding: {:?}",
+          content_type, accept_encoding);
+    // gRPC ha la sua compressione per messaggio (grpc-encoding): il corpo non va toccato
+    if is_grpc(response.headers()) {
+        return response;
+    }
+    // Controlla se il content-type è comprimibile
+    if !config.enabled || config.algorithms.is_empty() || !is_compressible(&response, config) {
+        return response;
+    }
+    // La rappresentazione dipende da Accept-Encoding anche quando questo client non comprime
+    add_vary_accept_encoding(&mut response);
+
+    if head_request || !has_body(&response) || is_small(&response, config.min_size) {
+        return response;
+    }
+
+    match negotiate(accept_encoding, &config.algorithms) {
+        Some(coding) => compress_stream(response, coding, config),
+        None => response, // identity, esplicita o perché nessuna codifica è accettata
+    }
+}
+
+/// Sceglie la codifica secondo RFC 9110 (sezione 12.5.3): vince il q-value più alto,
+/// a parità l'ordine di `offered`. `None` significa inviare il corpo così com'è.
+pub fn negotiate(accept_encoding: Option<&str>, offered: &[ContentCoding]) -> Option<ContentCoding> {
+    // Senza header qualsiasi codifica sarebbe lecita, ma non sappiamo cosa il client sa decodificare
+    let accept_encoding = accept_encoding?;
+
+    let explicit = parse_accept_encoding(accept_encoding);
+
+    let lookup = |token: &str| explicit.iter().find(|(coding, _)| coding == token).map(|(_, q)| *q);
+    let wildcard = lookup("*");
+
+    let mut best: Option<(ContentCoding, u16)> = None;
+    for &coding in offered {
+        let qvalue = lookup(coding.token()).or(wildcard).unwrap_or(0);
+        if qvalue > 0 && best.is_none_or(|(_, best_q)| qvalue > best_q) {
+            best = Some((coding, qvalue));
+        }
+    }
+    let (coding, qvalue) = best?;
+
+    // Se il client preferisce esplicitamente identity non comprimiamo
+    match lookup("identity") {
+        Some(identity) if identity > qvalue => None,
+        _ => Some(coding),
+    }
+}
+
+/// Voci di `Accept-Encoding` come (codifica in minuscolo, q-value in millesimi)
+pub fn parse_accept_encoding(accept_encoding: &str) -> Vec<(String, u16)> {
+    let mut codings = Vec::new();
+    for item in accept_encoding.split(',') {
+        let mut params = item.split(';');
+        let coding = params.next().unwrap_or("").trim().to_ascii_lowercase();
+        if coding.is_empty() {
+            continue;
+        }
+        let mut qvalue = Some(1000);
+        for param in params {
+            if let Some((name, value)) = param.split_once('=') {
+                if name.trim().eq_ignore_ascii_case("q") {
+                    qvalue = parse_qvalue(value.trim());
+                }
+            }
+        }
+        // Una voce con q-value non valido viene ignorata
+        if let Some(qvalue) = qvalue {
+            // "x-gzip" è un alias di gzip
+            let coding = if coding == "x-gzip" { "gzip".to_string() } else { coding };
+            codings.push((coding, qvalue));
+        }
+    }
+    codings
+}
+
+/// q-value in millesimi: "0", "0.5", "1.000" ... (al massimo tre decimali)
+fn parse_qvalue(value: &str) -> Option<u16> {
+    let (integer, fraction) = value.split_once('.').unwrap_or((value, ""));
+    if fraction.len() > 3 || !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let thousandths = format!("{fraction:0<3}").parse::<u16>().ok()?;
+    match integer {
+        "0" => Some(thousandths),
+        "1" if thousandths == 0 => Some(1000),
+        _ => None,
+    }
+}
+
+/// Il content-type rispetta gli elenchi di inclusione ed esclusione
+fn is_compressible(response: &Response<hyper::Body>, config: &CompressionConfig) -> bool {
+    if response.headers().contains_key(CONTENT_ENCODING) {
+        return false;
+    }
+
+    let media_type = response.headers()
+        .get(CONTENT_TYPE)
+        .and_then(|ct| ct.to_str().ok())
+        .map(|ct| ct.split(';').next().unwrap_or("").trim().to_ascii_lowercase());
+
+    match media_type {
+        Some(media_type) => {
+            let matches = |pattern: &String| media_type_matches(&media_type, pattern);
+            (config.content_types.is_empty() || config.content_types.iter().any(matches))
+                && !config.exclude_content_types.iter().any(matches)
+        }
+        None => config.content_types.is_empty(),
+    }
+}
+
+fn media_type_matches(media_type: &str, pattern: &str) -> bool {
+    match pattern.strip_suffix("/*") {
+        Some(kind) => media_type
+            .split_once('/')
+            .is_some_and(|(media_kind, _)| media_kind.eq_ignore_ascii_case(kind)),
+        None => media_type.eq_ignore_ascii_case(pattern),
+    }
+}
+
+/// Risposte senza corpo o parziali (range) restano come sono
+fn has_body(response: &Response<hyper::Body>) -> bool {
+    let status = response.status();
+    !(status.is_informational()
+        || status == StatusCode::NO_CONTENT
+        || status == StatusCode::NOT_MODIFIED
+        || status == StatusCode::PARTIAL_CONTENT
+        || response.headers().contains_key(CONTENT_RANGE))
+}
+
+fn is_small(response: &Response<hyper::Body>, min_size: u64) -> bool {
+    content_length(response).is_some_and(|len| len < min_size)
+}
+
+fn content_length(response: &Response<hyper::Body>) -> Option<u64> {
+    response.headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+}
+
+fn add_vary_accept_encoding(response: &mut Response<hyper::Body>) {
+    let already_varies = response.headers()
+        .get_all(VARY)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|field| {
+            let field = field.trim();
+            field == "*" || field.eq_ignore_ascii_case("accept-encoding")
+        });
+    if !already_varies {
+        response.headers_mut().append(VARY, HeaderValue::from_static("Accept-Encoding"));
+    }
+}
+
+fn compress_stream(
+    response: Response<hyper::Body>,
+    coding: ContentCoding,
+    config: &CompressionConfig,
+) -> Response<hyper::Body> {
+    let encoder = match Encoder::new(coding, config) {
+        Ok(encoder) => encoder,
+        Err(e) => {
+            info!("Cannot start {} encoder, sending uncompressed: {}", coding.token(), e);
+            return response;
+        }
+    };
+
+    let (mut parts, body) = response.into_parts();
+    // Senza lunghezza nota è uno stream (chunked): ogni blocco va consegnato subito
+    let flush_each_chunk = !parts.headers.contains_key(CONTENT_LENGTH);
+
+    parts.headers.insert(CONTENT_ENCODING, HeaderValue::from_static(coding.token()));
+    parts.headers.remove(CONTENT_LENGTH);
+
+    let body = hyper::Body::wrap_stream(CompressedBody {
+        inner: body,
+        encoder: Some(encoder),
+        flush_each_chunk,
+    });
+    Response::from_parts(parts, body)
+}
+
+/// Encoder in scrittura su un buffer che viene svuotato ad ogni blocco
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(ZlibEncoder<Vec<u8>>),
+    Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+}
+
+impl Encoder {
+    fn new(coding: ContentCoding, config: &CompressionConfig) -> io::Result<Self> {
+        Ok(match coding {
+            ContentCoding::Gzip => Encoder::Gzip(GzEncoder::new(Vec::new(), Compression::new(config.gzip_level))),
+            // "deflate" in HTTP è il formato zlib, non deflate grezzo
+            ContentCoding::Deflate => Encoder::Deflate(ZlibEncoder::new(Vec::new(), Compression::new(config.deflate_level))),
+            ContentCoding::Brotli => Encoder::Brotli(Box::new(brotli::CompressorWriter::new(Vec::new(), 4096, config.brotli_level, 22))),
+            ContentCoding::Zstd => Encoder::Zstd(zstd::stream::write::Encoder::new(Vec::new(), config.zstd_level)?),
+        })
+    }
+
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            Encoder::Gzip(encoder) => encoder,
+            Encoder::Deflate(encoder) => encoder,
+            Encoder::Brotli(encoder) => encoder.as_mut(),
+            Encoder::Zstd(encoder) => encoder,
+        }
+    }
+
+    /// Output prodotto finora
+    fn take_output(&mut self) -> Vec<u8> {
+        let output = match self {
+            Encoder::Gzip(encoder) => encoder.get_mut(),
+            Encoder::Deflate(encoder) => encoder.get_mut(),
+            Encoder::Brotli(encoder) => encoder.get_mut(),
+            Encoder::Zstd(encoder) => encoder.get_mut(),
+        };
+        std::mem::take(output)
+    }
+
+    /// Chiude lo stream compresso e restituisce l'output rimanente
+    fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Deflate(encoder) => encoder.finish(),
+            Encoder::Brotli(encoder) => Ok(encoder.into_inner()),
+            Encoder::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+/// Corpo compresso al volo: in memoria resta solo lo stato dell'encoder
+/// e l'output del blocco corrente
+struct CompressedBody {
+    inner: hyper::Body,
+    encoder: Option<Encoder>,
+    flush_each_chunk: bool,
+}
+
+impl Stream for CompressedBody {
+    type Item = Result<Bytes, BoxError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            let Some(encoder) = this.encoder.as_mut() else {
+                return Poll::Ready(None);
+            };
+
+            match Pin::new(&mut this.inner).poll_data(cx) {
+                Poll::Ready(Some(Ok(chunk))) => {
+                    let writer = encoder.writer();
+                    let written = writer.write_all(&chunk).and_then(|_| {
+                        if this.flush_each_chunk { writer.flush() } else { Ok(()) }
+                    });
+                    if let Err(e) = written {
+                        this.encoder = None;
+                        return Poll::Ready(Some(Err(e.into())));
+                    }
+                    let output = encoder.take_output();
+                    // L'encoder può trattenere i dati finché non ha un blocco pieno
+                    if !output.is_empty() {
+                        return Poll::Ready(Some(Ok(Bytes::from(output))));
+                    }
+                }
+                Poll::Ready(Some(Err(e))) => {
+                    this.encoder = None;
+                    return Poll::Ready(Some(Err(e.into())));
+                }
+                Poll::Ready(None) => {
+                    let encoder = this.encoder.take().expect("encoder checked above");
+                    return Poll::Ready(Some(encoder.finish().map(Bytes::from).map_err(Into::into)));
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ContentCoding::*;
+
+    const OFFERED: [ContentCoding; 4] = [Brotli, Zstd, Gzip, Deflate];
+
+    fn pick(accept_encoding: &str) -> Option<ContentCoding> {
+        negotiate(Some(accept_encoding), &OFFERED)
+    }
+
+    #[test]
+    fn qvalue_parsing() {
+        assert_eq!(parse_qvalue("1"), Some(1000));
+        assert_eq!(parse_qvalue("1.000"), Some(1000));
+        assert_eq!(parse_qvalue("0"), Some(0));
+        assert_eq!(parse_qvalue("0.5"), Some(500));
+        assert_eq!(parse_qvalue("0.125"), Some(125));
+        for malformed in ["", ".5", "1.5", "2", "0.1234", "0.a", "-0", "abc"] {
+            assert_eq!(parse_qvalue(malformed), None, "{malformed:?}");
+        }
+    }
+
+    #[test]
+    fn highest_qvalue_wins() {
+        assert_eq!(pick("gzip;q=0.8, deflate"), Some(Deflate));
+        assert_eq!(pick("gzip, br;q=0.5"), Some(Gzip));
+        assert_eq!(pick("GZIP ; Q=0.9"), Some(Gzip));
+    }
+
+    #[test]
+    fn ties_follow_server_order() {
+        assert_eq!(pick("gzip, deflate, br"), Some(Brotli));
+        assert_eq!(pick("deflate, gzip"), Some(Gzip));
+        assert_eq!(negotiate(Some("deflate, gzip"), &[Deflate, Gzip]), Some(Deflate));
+    }
+
+    #[test]
+    fn zero_qvalue_refuses_a_coding() {
+        assert_eq!(pick("gzip;q=0"), None);
+        assert_eq!(pick("gzip;q=0, deflate"), Some(Deflate));
+        assert_eq!(pick("*;q=0"), None);
+        // Una codifica esplicita vince sul wildcard che rifiuta le altre
+        assert_eq!(pick("*;q=0, gzip"), Some(Gzip));
+        assert_eq!(pick("*, br;q=0, zstd;q=0"), Some(Gzip));
+    }
+
+    #[test]
+    fn identity_preference() {
+        assert_eq!(pick("identity"), None);
+        assert_eq!(pick("identity, gzip;q=0.5"), None);
+        assert_eq!(pick("identity;q=0.5, gzip"), Some(Gzip));
+        // A parità la compressione resta lecita
+        assert_eq!(pick("identity, gzip"), Some(Gzip));
+        assert_eq!(pick(""), None);
+        assert_eq!(negotiate(None, &OFFERED), None);
+    }
+
+    #[test]
+    fn x_gzip_is_gzip() {
+        assert_eq!(pick("x-gzip"), Some(Gzip));
+        assert_eq!(parse_accept_encoding("x-gzip;q=0.5"), [("gzip".to_string(), 500)]);
+    }
+
+    #[test]
+    fn malformed_qvalue_ignores_the_entry() {
+        assert_eq!(pick("br;q=2, gzip"), Some(Gzip));
+        assert_eq!(pick("br;q=abc"), None);
+        assert_eq!(parse_accept_encoding("br;q=0.5000, , gzip;level=1"), [("gzip".to_string(), 1000)]);
+    }
+}
@@ -0,0 +1,193 @@
+use crate::proxy::timeout::ProxyTimeout;
+use hyper::body::HttpBody;
+use hyper::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+use hyper::{Response, StatusCode};
+use std::time::Duration;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+pub const GRPC_STATUS: &str = "grpc-status";
+pub const GRPC_MESSAGE: &str = "grpc-message";
+pub const GRPC_TIMEOUT: &str = "grpc-timeout";
+
+/// Messaggi di errore generati dal proxy più lunghi di così non vengono letti
+const MAX_MESSAGE_BYTES: usize = 1024;
+
+/// Codici di stato gRPC usati dal proxy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrpcCode {
+    Unknown = 2,
+    DeadlineExceeded = 4,
+    PermissionDenied = 7,
+    Unimplemented = 12,
+    Internal = 13,
+    Unavailable = 14,
+    Unauthenticated = 16,
+}
+
+impl GrpcCode {
+    /// Mappatura standard da status HTTP a codice gRPC (doc/http-grpc-status-mapping.md)
+    pub fn from_http(status: StatusCode) -> Self {
+        match status {
+            StatusCode::BAD_REQUEST => GrpcCode::Internal,
+            StatusCode::UNAUTHORIZED => GrpcCode::Unauthenticated,
+            StatusCode::FORBIDDEN => GrpcCode::PermissionDenied,
+            StatusCode::NOT_FOUND => GrpcCode::Unimplemented,
+            StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT => GrpcCode::Unavailable,
+            StatusCode::INTERNAL_SERVER_ERROR => GrpcCode::Internal,
+            _ => GrpcCode::Unknown,
+        }
+    }
+
+    fn from_timeout(timeout: ProxyTimeout) -> Self {
+        match timeout {
+            // Il backend non è raggiungibile: il client può riprovare altrove
+            ProxyTimeout::Connect => GrpcCode::Unavailable,
+            ProxyTimeout::FirstByte | ProxyTimeout::Request | ProxyTimeout::BodyIdle => GrpcCode::DeadlineExceeded,
+        }
+    }
+}
+
+/// `application/grpc`, anche con suffisso (`+proto`, `+json`) o parametri; non gRPC-Web
+pub fn is_grpc(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("application/grpc"))
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('+') || rest.starts_with(';'))
+}
+
+/// Scadenza della chiamata chiesta dal client: `grpc-timeout`, fino a 8 cifre seguite
+/// dall'unità (H, M, S, m, u, n)
+pub fn grpc_timeout(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(GRPC_TIMEOUT)?.to_str().ok()?;
+    let (digits, unit) = value.split_at(value.len().checked_sub(1)?);
+    if digits.is_empty() || digits.len() > 8 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let amount: u64 = digits.parse().ok()?;
+    Some(match unit {
+        "H" => Duration::from_secs(amount * 3600),
+        "M" => Duration::from_secs(amount * 60),
+        "S" => Duration::from_secs(amount),
+        "m" => Duration::from_millis(amount),
+        "u" => Duration::from_micros(amount),
+        "n" => Duration::from_nanos(amount),
+        _ => return None,
+    })
+}
+
+/// Risposta "trailers-only": 200 con lo stato gRPC negli header e nessun messaggio
+pub fn error_response(code: GrpcCode, message: &str) -> Response<hyper::Body> {
+    let mut response = Response::new(hyper::Body::empty());
+    response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("application/grpc"));
+    response.headers_mut().extend(status_headers(code, message));
+    response
+}
+
+/// Trailer con cui chiudere una risposta gRPC interrotta da un errore del backend
+pub fn error_trailers(error: BoxError) -> Option<HeaderMap> {
+    let (code, message) = match error.downcast_ref::<ProxyTimeout>() {
+        Some(timeout) => (GrpcCode::from_timeout(*timeout), timeout.to_string()),
+        None => (GrpcCode::Unavailable, format!("backend stream failed: {error}")),
+    };
+    Some(status_headers(code, &message))
+}
+
+/// Le risposte non gRPC (errori del proxy o di un intermediario) diventano errori gRPC:
+/// i client gRPC non saprebbero cosa farsene di un 502 in testo semplice
+pub async fn into_grpc_response(response: Response<hyper::Body>) -> Response<hyper::Body> {
+    if is_grpc(response.headers()) {
+        return response;
+    }
+
+    let status = response.status();
+    let code = match response.headers().get("X-Timeout-Reason").and_then(|value| value.to_str().ok()) {
+        Some("connect") => GrpcCode::Unavailable,
+        Some(_) => GrpcCode::DeadlineExceeded,
+        None => GrpcCode::from_http(status),
+    };
+    // I messaggi del proxy sono testo breve; pagine HTML o corpi lunghi non vengono usati
+    let plain_text = response.headers()
+        .get(CONTENT_TYPE)
+        .is_none_or(|value| value.as_bytes().starts_with(b"text/plain"));
+    let mut message = Vec::new();
+    if plain_text {
+        let mut body = response.into_body();
+        while let Some(Ok(chunk)) = body.data().await {
+            message.extend_from_slice(&chunk);
+            if message.len() > MAX_MESSAGE_BYTES {
+                message.clear();
+                break;
+            }
+        }
+    }
+    let mut message = String::from_utf8_lossy(&message).trim().to_string();
+    if message.is_empty() {
+        message = format!("HTTP {status}");
+    }
+    error_response(code, &message)
+}
+
+fn status_headers(code: GrpcCode, message: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(GRPC_STATUS, HeaderValue::from(code as u16));
+    if let Ok(value) = HeaderValue::from_str(&percent_encode(message)) {
+        headers.insert(GRPC_MESSAGE, value);
+    }
+    headers
+}
+
+/// `grpc-message` è percent-encoded: restano solo i caratteri ASCII stampabili tranne '%'
+fn percent_encode(message: &str) -> String {
+    let mut encoded = String::with_capacity(message.len());
+    for byte in message.bytes() {
+        if (0x20..=0x7e).contains(&byte) && byte != b'%' {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timeout(value: &str) -> Option<Duration> {
+        let mut headers = HeaderMap::new();
+        headers.insert(GRPC_TIMEOUT, HeaderValue::from_str(value).unwrap());
+        grpc_timeout(&headers)
+    }
+
+    #[test]
+    fn every_unit() {
+        assert_eq!(timeout("2H"), Some(Duration::from_secs(2 * 3600)));
+        assert_eq!(timeout("3M"), Some(Duration::from_secs(180)));
+        assert_eq!(timeout("4S"), Some(Duration::from_secs(4)));
+        assert_eq!(timeout("250m"), Some(Duration::from_millis(250)));
+        assert_eq!(timeout("750u"), Some(Duration::from_micros(750)));
+        assert_eq!(timeout("100n"), Some(Duration::from_nanos(100)));
+        assert_eq!(timeout("0S"), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn at_most_eight_digits() {
+        assert_eq!(timeout("99999999H"), Some(Duration::from_secs(99_999_999 * 3600)));
+        assert_eq!(timeout("00000001S"), Some(Duration::from_secs(1)));
+        assert_eq!(timeout("100000000n"), None);
+        assert_eq!(timeout("999999999999999999999H"), None);
+    }
+
+    #[test]
+    fn malformed_values_are_ignored() {
+        for value in ["", "S", "10", "10s", "10ms", "-1S", "+1S", "1.5S", " 1S", "1 S", "1SS", "H1"] {
+            assert_eq!(timeout(value), None, "{value:?}");
+        }
+        assert_eq!(grpc_timeout(&HeaderMap::new()), None);
+    }
+}
@@ -1,87 +1,471 @@
-use crate::backend::pool::BackendPool;
-use crate::proxy::request::forward_request;
-use crate::proxy::response::{handle_proxy_error, no_healthy_backends};
-use hyper::{Request, Response,StatusCode};
-use std::time::Duration;
+use crate::backend::pool::{BackendPool, ListenerPool};
+use crate::proxy::request::{forward_request, request_hash_key};
+use crate::proxy::limiter::ConcurrencyLimiter;
+use crate::proxy::response::{create_error_response, handle_proxy_error, hold_until_body_end, modify_response, no_healthy_backends, overloaded, without_body};
+use hyper::{Method, Request, Response, StatusCode, Version};
+use std::time::{Duration, Instant};
 use std::convert::Infallible;
 use std::future::Future;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 use hyper::Client;
-use tracing::{info, error};
-use crate::backend::{Backend};
-use tokio::sync::Semaphore;
+use tracing::{debug, info, error, warn};
+use crate::backend::{BackendProtocol, BackendStatus, SelectionContext};
 use std::sync::Arc;
 use hyper_rustls::HttpsConnector;
 use hyper::client::HttpConnector;
 use std::sync::atomic::Ordering;
+use hyper::header::{RANGE, SET_COOKIE};
+use crate::config::{CompressionConfig, ConcurrencyConfig, Config, TimeoutConfig};
+use crate::proxy::cache::{CacheControl, CachedResponse, ResponseCache};
+use crate::proxy::coalesce::{Flight, RequestCoalescer};
+use crate::proxy::grpc::{self, into_grpc_response, is_grpc, GrpcCode};
+use crate::proxy::retry::{PreparedRequest, RetryPolicy};
+use crate::proxy::sticky::{apply_pending_cookie, PendingCookie, StickySessions};
+use crate::proxy::timeout::Timeouts;
+use crate::proxy::tunnel::{forward_upgrade, is_upgrade_request, run_tunnel};
+use std::collections::HashMap;
+use std::sync::Mutex;
 
 // Definiamo un tipo per chiarezza
 type ClientType = Client<HttpsConnector<HttpConnector>, hyper::Body>;
 
+/// Impostazioni del proxy ricavate dalla config, condivise tra tutte le richieste
+#[derive(Default)]
+pub struct ProxySettings {
+    pub sticky: Option<StickySessions>,
+    pub retry: Option<RetryPolicy>,
+    pub timeouts: TimeoutConfig,
+    pub concurrency: ConcurrencyConfig,
+    pub compression: CompressionConfig,
+    pub cache: Option<Arc<ResponseCache>>,
+    pub coalescer: Option<RequestCoalescer>,
+    /// Token richiesto dagli endpoint admin
+    pub admin_token: Option<String>,
+}
+
+impl ProxySettings {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            sticky: config.sticky_session.as_ref().map(StickySessions::new),
+            retry: config.retry.clone().map(RetryPolicy::new),
+            timeouts: config.timeouts.clone(),
+            concurrency: config.concurrency.clone(),
+            compression: config.compression.clone(),
+            cache: config.cache.as_ref().map(|cache| Arc::new(ResponseCache::new(cache))),
+            coalescer: config.cache
+                .as_ref()
+                .filter(|cache| cache.coalesce_requests)
+                .map(|cache| RequestCoalescer::new(Duration::from_millis(cache.coalesce_timeout_ms))),
+            admin_token: config.admin.token.clone(),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct ProxyHandler {
     pub backend_pool: BackendPool,
-    pub http_client: ClientType,
-    pub concurrency_limiter: Arc<Semaphore>, 
+    /// Un client per ogni connect timeout in uso (globale o override dei backend) e protocollo
+    pub http_clients: Arc<Mutex<HashMap<(Duration, bool), ClientType>>>,
+    pub concurrency_limiter: Arc<ConcurrencyLimiter>,
+    pub settings: Arc<ProxySettings>,
+    /// Pool dei listener aggiuntivi (TCP), solo per `/admin/listeners`
+    pub listeners: Arc<Vec<ListenerPool>>,
 }
 
 impl ProxyHandler {
-    pub fn new(backend_pool: BackendPool) -> Self {
-        let https = hyper_rustls::HttpsConnectorBuilder::new()
-            .with_native_roots()
-            .https_or_http()
-            .enable_http1()
-            .build();
-        // 2. Crea il client con il connettore HTTPS
-        let http_client = Client::builder()
-            .pool_idle_timeout(Duration::from_secs(30))
-            .build(https);
-
+    pub fn new(backend_pool: BackendPool, settings: Arc<ProxySettings>) -> Self {
         Self { 
             backend_pool,
-            http_client,
-            concurrency_limiter: Arc::new(Semaphore::new(500)), 
+            http_clients: Arc::new(Mutex::new(HashMap::new())),
+            concurrency_limiter: Arc::new(ConcurrencyLimiter::new(&settings.concurrency)),
+            settings,
+            listeners: Arc::new(Vec::new()),
         }
     }
 
+    pub fn with_listeners(mut self, listeners: Vec<ListenerPool>) -> Self {
+        self.listeners = Arc::new(listeners);
+        self
+    }
+
+    fn client_for(&self, connect_timeout: Duration, protocol: BackendProtocol) -> ClientType {
+        let http2 = protocol.is_http2();
+        self.http_clients
+            .lock()
+            .unwrap()
+            .entry((connect_timeout, http2))
+            .or_insert_with(|| {
+                let mut http = HttpConnector::new();
+                http.enforce_http(false);
+                http.set_connect_timeout(Some(connect_timeout));
+                let builder = hyper_rustls::HttpsConnectorBuilder::new()
+                    .with_native_roots()
+                    .https_or_http();
+                // HTTP/2 solo per i backend che lo dichiarano: h2 via ALPN, h2c con prior knowledge
+                let https = if http2 {
+                    builder.enable_http2().wrap_connector(http)
+                } else {
+                    builder.enable_http1().wrap_connector(http)
+                };
+                // 2. Crea il client con il connettore HTTPS
+                Client::builder()
+                    .pool_idle_timeout(Duration::from_secs(30))
+                    .http2_only(http2)
+                    .build(https)
+            })
+            .clone()
+    }
+
     pub async fn handle_request(&self, req: Request<hyper::Body>) -> Result<Response<hyper::Body>, Infallible> {
-        // solo 500 permessi
-        let _permit = self.concurrency_limiter.acquire().await.unwrap();
         // Se é una richiesta di healthcheck
         if req.uri().path().starts_with("/health/") {
             return self.handle_health_check(req).await;
         }
+        // WebSocket e altri upgrade diventano tunnel, senza cache né retry
+        if is_upgrade_request(req.headers()) {
+            return Ok(self.proxy_upgrade(req).await);
+        }
+        // gRPC: niente cache, e gli errori del proxy diventano stati gRPC
+        if is_grpc(req.headers()) {
+            return Ok(self.proxy_grpc(req).await);
+        }
+        // Le risposte in cache non passano dal limite di concorrenza
+        let response = match &self.settings.cache {
+            Some(cache) => self.handle_cached(cache, req).await,
+            None => self.proxy_request(req).await,
+        };
+        // Il cookie sticky arriva solo a questo client, dopo l'eventuale copia in cache
+        Ok(apply_pending_cookie(response))
+    }
+
+    /// Richieste GET e HEAD servite dalla cache quando possibile; le modifiche riuscite la invalidano
+    async fn handle_cached(&self, cache: &Arc<ResponseCache>, req: Request<hyper::Body>) -> Response<hyper::Body> {
+        let key = ResponseCache::key(&req);
+        let method = req.method().clone();
+        if method != Method::GET && method != Method::HEAD {
+            let response = self.proxy_request(req).await;
+            // RFC 9111, sezione 4.4
+            if !method.is_safe() && (response.status().is_success() || response.status().is_redirection()) {
+                cache.invalidate(&key);
+            }
+            return response;
+        }
+
+        let request_control = CacheControl::parse(req.headers());
+        if request_control.no_store || req.headers().contains_key(RANGE) {
+            return self.proxy_request(req).await;
+        }
+        let head_request = method == Method::HEAD;
+        let request_headers = req.headers().clone();
+
+        let cached = cache.lookup(&key, &request_headers);
+        if let Some(entry) = &cached {
+            if entry.is_fresh(&request_control) {
+                return entry.to_response(&request_headers, head_request, "HIT");
+            }
+            if entry.serve_while_revalidating(&request_control) {
+                self.revalidate_in_background(cache, key, Arc::clone(entry), &req);
+                return entry.to_response(&request_headers, head_request, "STALE");
+            }
+        }
+
+        let Some(coalescer) = &self.settings.coalescer else {
+            return self.fetch_and_store(cache, key, cached, &request_control, req).await;
+        };
+        // Richieste identiche contemporanee: una sola arriva al backend, le altre ne ricevono una copia
+        match coalescer.join(RequestCoalescer::key(&req)) {
+            Flight::Leader(leader) => {
+                let response = self.fetch_and_store(cache, key, cached, &request_control, req).await;
+                let vary = cache.storable(&request_control, &request_headers, response.status(), response.headers());
+                leader.publish(response, vary, cache.max_entry_bytes()).await
+            }
+            Flight::Follower(follower) => {
+                // Solo una risposta che questa richiesta potrebbe avere dalla cache
+                let shared = follower.wait().await.filter(|shared| {
+                    cache.storable(&request_control, &request_headers, shared.status, &shared.headers).as_ref() == Some(&shared.vary)
+                });
+                if let Some(shared) = shared {
+                    return shared.to_response();
+                }
+                // Il leader può aver rinnovato la copia in cache con un 304
+                let cached = cache.lookup(&key, &request_headers);
+                if let Some(entry) = cached.as_ref().filter(|entry| entry.is_fresh(&request_control)) {
+                    return entry.to_response(&request_headers, head_request, "HIT");
+                }
+                self.fetch_and_store(cache, key, cached, &request_control, req).await
+            }
+        }
+    }
+
+    /// Richiesta al backend per una copia assente o scaduta, poi memorizzata se possibile
+    async fn fetch_and_store(
+        &self,
+        cache: &Arc<ResponseCache>,
+        key: String,
+        cached: Option<Arc<CachedResponse>>,
+        request_control: &CacheControl,
+        mut req: Request<hyper::Body>,
+    ) -> Response<hyper::Body> {
+        let head_request = req.method() == Method::HEAD;
+        let request_headers = req.headers().clone();
+
+        // Copia scaduta: il backend risponde 304 se è ancora valida
+        let validated = cached.as_ref().is_some_and(|entry| entry.has_validators());
+        if let Some(entry) = cached.as_ref().filter(|_| validated) {
+            entry.add_validators(req.headers_mut());
+        }
+        let response = self.proxy_request(req).await;
+
+        if let Some(entry) = cached {
+            if validated && response.status() == StatusCode::NOT_MODIFIED {
+                return cache
+                    .refresh(&key, &entry, response.headers())
+                    .to_response(&request_headers, head_request, "REVALIDATED");
+            }
+            if response.status().is_server_error() && entry.serve_on_error(request_control) {
+                warn!("Serving stale {} after {} from the backend", key, response.status());
+                return entry.to_response(&request_headers, head_request, "STALE");
+            }
+        }
+        if head_request {
+            return response;
+        }
+        cache.store(key, request_control, &request_headers, response).0
+    }
+
+    /// Rinnova una copia scaduta senza far aspettare il client (stale-while-revalidate)
+    fn revalidate_in_background(
+        &self,
+        cache: &Arc<ResponseCache>,
+        key: String,
+        entry: Arc<CachedResponse>,
+        req: &Request<hyper::Body>,
+    ) {
+        if !entry.start_revalidation() {
+            return;
+        }
+        let mut background = Request::new(hyper::Body::empty());
+        *background.uri_mut() = req.uri().clone();
+        *background.headers_mut() = req.headers().clone();
+        if let Some(addr) = req.extensions().get::<std::net::SocketAddr>() {
+            background.extensions_mut().insert(*addr);
+        }
+        entry.add_validators(background.headers_mut());
+
+        let handler = self.clone();
+        let cache = Arc::clone(cache);
+        let request_headers = req.headers().clone();
+        tokio::spawn(async move {
+            let response = handler.proxy_request(background).await;
+            if response.status() == StatusCode::NOT_MODIFIED {
+                cache.refresh(&key, &entry, response.headers());
+            } else {
+                // Il corpo va letto fino in fondo perché finisca in cache
+                let (response, stored) = cache.store(key, &CacheControl::default(), &request_headers, response);
+                if stored {
+                    let _ = hyper::body::to_bytes(response.into_body()).await;
+                }
+            }
+            entry.finish_revalidation();
+        });
+    }
+
+    /// Chiamate gRPC: solo su HTTP/2 e verso backend h2/h2c. Il backend viene scelto per
+    /// ogni chiamata, non per connessione, anche quando il client ne multiplexa molte.
+    async fn proxy_grpc(&self, req: Request<hyper::Body>) -> Response<hyper::Body> {
+        if req.version() != Version::HTTP_2 {
+            return modify_response(grpc::error_response(GrpcCode::Internal, "gRPC requires HTTP/2"));
+        }
+        modify_response(into_grpc_response(self.proxy_request(req).await).await)
+    }
+
+    /// Inoltra la richiesta ai backend, con limite di concorrenza e retry
+    async fn proxy_request(&self, req: Request<hyper::Body>) -> Response<hyper::Body> {
+        // Limite globale di richieste in corso: oltre la coda si scarta subito con un 503
+        let _permit = match self.concurrency_limiter.acquire().await {
+            Ok(permit) => permit,
+            Err(reason) => {
+                warn!(
+                    "Shedding {} {}: {:?} ({} requests queued)",
+                    req.method(), req.uri(), reason, self.concurrency_limiter.queued()
+                );
+                return overloaded(self.settings.concurrency.retry_after_secs);
+            }
+        };
         // Richiesta normale
         info!("Incoming request: {} {}", req.method(), req.uri());
 
-        // Prendi il backend e incrementa le connessioni nel pool
-        let backend_state = match self.backend_pool.select_and_increment().await {
-            Some(backend) => {
-                info!("Selected backend: {}", backend.backend.url);
-        println!("INCREMENTED: {} (now: {})", backend.backend.url, backend.connections.load(Ordering::Relaxed));
-                backend
-            },
-            None => {
-                error!("No healthy backends available");
-                return Ok(no_healthy_backends());
+        let mut ctx = self.selection_context(&req);
+        // Le sessioni sticky non valgono per gRPC: il backend del cookie potrebbe non parlare HTTP/2
+        let sticky = self.settings.sticky.as_ref().filter(|_| !is_grpc(req.headers()));
+        let sticky_backend = sticky.and_then(|sticky| sticky.backend_from_request(req.headers()));
+
+        // Con i retry attivi i corpi piccoli restano in memoria per poter ripetere la richiesta
+        let (replay, mut streaming) = match &self.settings.retry {
+            Some(retry) => {
+                retry.record_request();
+                match retry.prepare(req).await {
+                    Ok(PreparedRequest::Replayable(replay)) => (Some(replay), None),
+                    Ok(PreparedRequest::Streaming(req)) => (None, Some(req)),
+                    Err(e) => return create_error_response(StatusCode::BAD_REQUEST, e.to_string()),
+                }
+            }
+            None => (None, Some(req)),
+        };
+
+        let mut attempt = 0;
+        let mut previous: Option<Response<hyper::Body>> = None;
+        loop {
+            // Prendi il backend e incrementa le connessioni nel pool;
+            // sessione sticky: il backend del cookie al primo tentativo
+            let pinned = sticky_backend.as_deref().filter(|_| attempt == 0);
+            let selected = self.backend_pool.select_pinned_or_increment(pinned, &ctx).await;
+            // Guardia della richiesta in corso: il conteggio delle connessioni resta giusto
+            // anche se il client si disconnette o il tentativo viene abbandonato
+            let mut backend_state = match selected {
+                Some(backend) => {
+                    info!("Selected backend: {}", backend.backend.url);
+                    debug!("Connections to {}: {}", backend.backend.url, backend.connections.load(Ordering::Relaxed));
+                    backend
+                },
+                None => {
+                    // Nessun altro backend per il retry: restano status e header del tentativo precedente
+                    if let Some(previous) = previous {
+                        return previous;
+                    }
+                    error!("No healthy backends available");
+                    return no_healthy_backends();
+                }
+            };
+            // Hardcoded backend-1 1 secondo di risposta per testare algoritmo di least-connection
+            //if backend_state.backend.url ==  "http://127.0.0.1:8081" {
+             //   backend_state.backend.simulate_delay().await;
+            //}
+            let req = match &replay {
+                Some(replay) => replay.build(),
+                None => streaming.take().expect("non-replayable requests are forwarded once"),
+            };
+            // Fai il forward della richiesta e aggiungi header e in caso compremi
+            let started = Instant::now();
+            let timeouts = Timeouts::resolve(&self.settings.timeouts, &backend_state.backend.timeouts);
+            let client = self.client_for(timeouts.connect, backend_state.backend.protocol);
+            let result = forward_request(req, &backend_state.backend, &client, &timeouts, &self.settings.compression).await;
+            // Errori di connessione, timeout e 5xx alimentano circuit breaker e outlier detection
+            let success = matches!(&result, Ok(resp) if !resp.status().is_server_error());
+            self.backend_pool.report_outcome(&mut backend_state, success);
+            // Latenza per peak-EWMA (anche gli errori: un backend che fallisce lentamente è lento)
+            backend_state.latency.observe(started.elapsed());
+
+            let retry = match (&self.settings.retry, &replay) {
+                (Some(policy), Some(replay)) if attempt < policy.max_retries() && policy.should_retry(replay.method(), &result) => {
+                    Some((policy, replay))
+                }
+                _ => None,
+            };
+
+            let forward = match result {
+                Ok(resp) => resp,
+                Err(e) => handle_proxy_error(e)
+            };
+            // La connessione resta contata finché il corpo della risposta non è stato inviato
+            let backend_name = backend_state.backend.name.clone();
+            let mut forward = hold_until_body_end(forward, backend_state);
+
+            if let Some((policy, replay)) = retry {
+                if policy.try_consume_budget() {
+                    warn!(
+                        "Retrying {} {} on another backend after {} from {}",
+                        replay.method(), replay.uri(), forward.status(), backend_name
+                    );
+                    ctx.exclude.push(backend_name);
+                    // Il corpo terrebbe occupati connessione e guardia del backend durante il retry
+                    previous = Some(without_body(forward));
+                    attempt += 1;
+                    continue;
+                }
+                warn!("Retry budget exhausted, not retrying {} {}", replay.method(), replay.uri());
+            }
+
+            if let Some(sticky) = sticky {
+                if sticky_backend.as_deref() != Some(backend_name.as_str()) {
+                    forward.extensions_mut().insert(PendingCookie(sticky.cookie_for(&backend_name)));
+                }
+            }
+
+            return forward;
+        }
+    }
+
+    /// Upgrade (WebSocket e simili): dopo il 101 client e backend restano collegati da un tunnel
+    async fn proxy_upgrade(&self, mut req: Request<hyper::Body>) -> Response<hyper::Body> {
+        // Il permesso copre solo l'handshake: i tunnel aperti non occupano il limite delle richieste
+        let _permit = match self.concurrency_limiter.acquire().await {
+            Ok(permit) => permit,
+            Err(reason) => {
+                warn!("Shedding upgrade {}: {:?}", req.uri(), reason);
+                return overloaded(self.settings.concurrency.retry_after_secs);
             }
         };
-        // Hardcoded backend-1 1 secondo di risposta per testare algoritmo di least-connection
-        //if backend_state.backend.url ==  "http://127.0.0.1:8081" {
-         //   backend_state.backend.simulate_delay().await;
-        //}
-        // Fai il forward della richiesta e aggiungi header e in caso compremi
-        let forward = match forward_request(req, &backend_state.backend, &self.http_client).await {
-            Ok(resp) => resp,
-            Err(e) => handle_proxy_error(e)
+        info!("Incoming upgrade request: {} {}", req.method(), req.uri());
+
+        let ctx = self.selection_context(&req);
+        let sticky_backend = self.settings.sticky.as_ref().and_then(|sticky| sticky.backend_from_request(req.headers()));
+        let selected = self.backend_pool.select_pinned_or_increment(sticky_backend.as_deref(), &ctx).await;
+        let Some(mut backend_state) = selected else {
+            error!("No healthy backends available");
+            return no_healthy_backends();
         };
-        // Dopo il forward decrementa le connessioni nel pool
-        self.backend_pool.decrement_connections(&backend_state.backend.name).await;
-        println!("Decremeted: {} (now: {})", backend_state.backend.url, backend_state.connections.load(Ordering::Relaxed));
+        info!("Selected backend: {}", backend_state.backend.url);
+
+        let client_upgrade = hyper::upgrade::on(&mut req);
+        let started = Instant::now();
+        let timeouts = Timeouts::resolve(&self.settings.timeouts, &backend_state.backend.timeouts);
+        let client = self.client_for(timeouts.connect, BackendProtocol::Http1);
+        let result = forward_upgrade(req, &backend_state.backend, &client, &timeouts).await;
+        let success = matches!(&result, Ok((resp, _)) if !resp.status().is_server_error());
+        self.backend_pool.report_outcome(&mut backend_state, success);
+        backend_state.latency.observe(started.elapsed());
 
-        Ok(forward)
+        let backend_name = backend_state.backend.name.clone();
+        let mut response = match result {
+            Ok((response, Some(backend_upgrade))) => {
+                // Il tunnel resta tra le connessioni attive del backend finché non viene chiuso
+                let idle = Duration::from_millis(self.settings.timeouts.tunnel_idle_ms);
+                tokio::spawn(run_tunnel(client_upgrade, backend_upgrade, backend_state, idle));
+                modify_response(response)
+            }
+            // Il backend ha rifiutato l'upgrade: risposta normale
+            Ok((response, None)) => hold_until_body_end(modify_response(response), backend_state),
+            Err(e) => handle_proxy_error(e),
+        };
 
+        if let Some(sticky) = &self.settings.sticky {
+            if sticky_backend.as_deref() != Some(backend_name.as_str()) {
+                response.headers_mut().append(SET_COOKIE, sticky.cookie_for(&backend_name));
+            }
+        }
+        response
+    }
+
+    fn selection_context(&self, req: &Request<hyper::Body>) -> SelectionContext {
+        SelectionContext {
+            hash: if self.backend_pool.strategy.uses_hash_key() {
+                request_hash_key(req, &self.backend_pool.hash_key)
+            } else {
+                None
+            },
+            // gRPC richiede HTTP/2 fino al backend
+            exclude: if is_grpc(req.headers()) {
+                self.backend_pool.state.load()
+                    .iter()
+                    .filter(|backend_state| !backend_state.backend.protocol.is_http2())
+                    .map(|backend_state| backend_state.backend.name.clone())
+                    .collect()
+            } else {
+                Vec::new()
+            },
+        }
     }
 
     async fn handle_health_check(&self, req: Request<hyper::Body>) -> Result<Response<hyper::Body>, Infallible> {
@@ -89,8 +473,8 @@ impl ProxyHandler {
         let backend_name = req.uri().path().trim_start_matches("/health/");
 
         if let Some(backend) = self.backend_pool.get_backend_by_name(backend_name).await {
-            // Richiesta diretta per vedere se é healthy
-            let is_healthy = self.direct_health_check(&backend.backend).await;
+            // Stato dell'ultimo health check attivo
+            let is_healthy = backend.status == BackendStatus::Healthy;
 
             let status = if is_healthy { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
             return Ok(Response::builder().status(status).body(hyper::Body::from("")).unwrap());
@@ -99,16 +483,50 @@ impl ProxyHandler {
         Ok(Response::builder().status(StatusCode::NOT_FOUND).body(hyper::Body::from("")).unwrap())
     }
 
-    async fn direct_health_check(&self, backend: &Backend) -> bool {
-        let client = reqwest::Client::builder()
-            .timeout(Duration::from_secs(3))
-            .build()
-            .unwrap();
-        match client.get(&backend.url).send().await {
-            Ok(resp) => resp.status().is_success(),
-            Err(_) => false,
+    /// Endpoint admin, serviti solo sul listener `admin.bind`: stato dei backend e dei
+    /// listener per `load-balancer backends`
+    pub fn handle_admin(&self, req: &Request<hyper::Body>) -> Response<hyper::Body> {
+        if let Some(token) = &self.settings.admin_token {
+            let authorized = req.headers()
+                .get(hyper::header::AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .is_some_and(|provided| constant_time_eq(provided.as_bytes(), token.as_bytes()));
+            if !authorized {
+                return create_error_response(StatusCode::UNAUTHORIZED, "Unauthorized".to_string());
+            }
+        }
+        match req.uri().path() {
+            "/admin/backends" => self.handle_admin_backends(),
+            "/admin/listeners" => self.handle_admin_listeners(),
+            _ => create_error_response(StatusCode::NOT_FOUND, "Not Found".to_string()),
         }
     }
+
+    fn handle_admin_backends(&self) -> Response<hyper::Body> {
+        json_response(&self.backend_pool.snapshot())
+    }
+
+    fn handle_admin_listeners(&self) -> Response<hyper::Body> {
+        let snapshots: Vec<_> = self.listeners.iter().map(ListenerPool::snapshot).collect();
+        json_response(&snapshots)
+    }
+}
+
+/// Confronto che non rivela con i tempi di risposta quanti byte del token sono giusti
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn json_response<T: serde::Serialize>(value: &T) -> Response<hyper::Body> {
+    match serde_json::to_vec(value) {
+        Ok(body) => Response::builder()
+            .status(StatusCode::OK)
+            .header("content-type", "application/json")
+            .body(hyper::Body::from(body))
+            .unwrap(),
+        Err(e) => create_error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
 }
 
 impl hyper::service::Service<Request<hyper::Body>> for ProxyHandler {
@@ -0,0 +1,115 @@
+use crate::config::ConcurrencyConfig;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// Perché una richiesta è stata scartata invece di essere servita
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shed {
+    QueueFull,
+    QueueTimeout,
+}
+
+/// Limite globale di richieste in corso, condiviso da tutti i listener.
+/// Oltre il limite le richieste aspettano in una coda limitata per al massimo
+/// `max_queue_ms`; quelle che non trovano posto vengono scartate subito.
+#[derive(Debug)]
+pub struct ConcurrencyLimiter {
+    permits: Semaphore,
+    queued: AtomicUsize,
+    max_queue: usize,
+    max_queue_time: Duration,
+}
+
+impl ConcurrencyLimiter {
+    pub fn new(config: &ConcurrencyConfig) -> Self {
+        Self {
+            permits: Semaphore::new(config.max_concurrent_requests),
+            queued: AtomicUsize::new(0),
+            max_queue: config.max_queue,
+            max_queue_time: Duration::from_millis(config.max_queue_ms),
+        }
+    }
+
+    pub async fn acquire(&self) -> Result<SemaphorePermit<'_>, Shed> {
+        if let Ok(permit) = self.permits.try_acquire() {
+            return Ok(permit);
+        }
+
+        let reserved = self
+            .queued
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |queued| {
+                (queued < self.max_queue).then_some(queued + 1)
+            })
+            .is_ok();
+        if !reserved {
+            return Err(Shed::QueueFull);
+        }
+        // Il posto in coda si libera anche se il client se ne va mentre aspetta
+        let _slot = QueueSlot(&self.queued);
+
+        match tokio::time::timeout(self.max_queue_time, self.permits.acquire()).await {
+            Ok(Ok(permit)) => Ok(permit),
+            // Il semaforo non viene mai chiuso: resta solo il caso del timeout
+            _ => Err(Shed::QueueTimeout),
+        }
+    }
+
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+}
+
+/// Posto occupato nella coda di attesa, rilasciato quando l'attesa finisce o viene annullata
+struct QueueSlot<'a>(&'a AtomicUsize);
+
+impl Drop for QueueSlot<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(max_concurrent_requests: usize, max_queue: usize) -> ConcurrencyLimiter {
+        ConcurrencyLimiter::new(&ConcurrencyConfig {
+            max_concurrent_requests,
+            max_queue,
+            max_queue_ms: 60_000,
+            ..ConcurrencyConfig::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn cancelled_wait_releases_the_queue_slot() {
+        let limiter = limiter(1, 1);
+        let _busy = limiter.acquire().await.unwrap();
+
+        // Client disconnesso mentre è in coda: hyper abbandona il future
+        let waiting = tokio::time::timeout(Duration::from_millis(10), limiter.acquire()).await;
+        assert!(waiting.is_err());
+        assert_eq!(limiter.queued(), 0);
+
+        // Il posto è di nuovo disponibile per la richiesta successiva
+        let mut next = Box::pin(limiter.acquire());
+        assert!(futures::poll!(next.as_mut()).is_pending());
+        assert_eq!(limiter.queued(), 1);
+        assert_eq!(limiter.acquire().await.unwrap_err(), Shed::QueueFull);
+        drop(next);
+        assert_eq!(limiter.queued(), 0);
+    }
+
+    #[tokio::test]
+    async fn queued_request_gets_the_released_permit() {
+        let limiter = limiter(1, 1);
+        let busy = limiter.acquire().await.unwrap();
+        let mut next = Box::pin(limiter.acquire());
+        assert!(futures::poll!(next.as_mut()).is_pending());
+
+        drop(busy);
+        assert!(next.await.is_ok());
+        assert_eq!(limiter.queued(), 0);
+    }
+}
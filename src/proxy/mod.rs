@@ -1,7 +1,16 @@
+pub mod cache;
+pub mod coalesce;
+pub mod compress;
+pub mod grpc;
 pub mod handler;
+pub mod limiter;
 pub mod request;
 pub mod response;
+pub mod retry;
+pub mod sticky;
+pub mod timeout;
+pub mod tunnel;
 
-pub use handler::ProxyHandler;
+pub use handler::{ProxyHandler, ProxySettings};
 pub use request::forward_request;
 pub use response::handle_proxy_error;
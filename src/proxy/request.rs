@@ -2,12 +2,15 @@ use hyper::{Client, Request, Response};
 use hyper_rustls::HttpsConnector;
 use tracing::{info};
 use anyhow::{Context, Ok, Result};
-use flate2::write::GzEncoder;
-use flate2::Compression;
-use std::io::Write;
+use crate::config::CompressionConfig;
+use crate::proxy::compress::compress_response;
+use crate::proxy::grpc::{grpc_timeout, is_grpc};
 use crate::proxy::response::modify_response;
 use hyper::client::HttpConnector;
 use hyper::Uri;
+use crate::backend::{BackendProtocol, HashKey};
+use crate::lb::algorithms::hash_of;
+use crate::proxy::timeout::{BodyLimits, ProxyTimeout, Timeouts};
 
 type CLientType = HttpsConnector<HttpConnector>;
 
@@ -15,16 +18,66 @@ pub async fn forward_request(
     req: Request<hyper::Body>,
     backend: &crate::backend::server::Backend,
     client: &Client<CLientType>,
+    timeouts: &Timeouts,
+    compression: &CompressionConfig,
 ) -> Result<Response<hyper::Body>> {
-    let backend_uri_str = prepare_backend_uri(req.uri(), &backend.url);
-    let parsed_uri: Uri = backend_uri_str.parse()
-        .context("Failed to parse backend URI")?;
+    // gRPC: la scadenza la decide il client con `grpc-timeout` e gli stream possono restare
+    // a lungo senza messaggi, quindi `request_ms` e `body_idle_ms` non si applicano
+    let (first_byte, request, body_idle) = if is_grpc(req.headers()) {
+        match grpc_timeout(req.headers()) {
+            Some(limit) => (limit, Some(limit), None),
+            None => (timeouts.first_byte, None, None),
+        }
+    } else {
+        (timeouts.first_byte.min(timeouts.request), Some(timeouts.request), Some(timeouts.body_idle))
+    };
+    let deadline = request.and_then(|limit| tokio::time::Instant::now().checked_add(limit));
 
     let accept_encoding = req.headers()
         .get("accept-encoding")
         .and_then(|h| h.to_str().ok())
         .map(|s| s.to_string());
 
+    let head_request = req.method() == hyper::Method::HEAD;
+
+    let mut backend_req = backend_request(req, backend, backend.protocol)?;
+    strip_hop_by_hop(backend_req.headers_mut());
+
+    info!("Forwarding request to: {}", backend_req.uri());
+
+    // Il connect timeout è nel connettore del client, qui l'attesa degli header
+    let backend_response = match tokio::time::timeout(first_byte, client.request(backend_req)).await {
+        std::result::Result::Ok(std::result::Result::Ok(response)) => response,
+        std::result::Result::Ok(Err(e)) if is_connect_timeout(&e) => return Err(ProxyTimeout::Connect.into()),
+        std::result::Result::Ok(Err(e)) => return Err(e).context("Failed to forward request to backend"),
+        Err(_) => match request {
+            Some(limit) if first_byte >= limit => return Err(ProxyTimeout::Request.into()),
+            _ => return Err(ProxyTimeout::FirstByte.into()),
+        },
+    };
+    let mut backend_response = backend_response;
+    strip_hop_by_hop(backend_response.headers_mut());
+    // Timeout del corpo e trailer vengono gestiti insieme alla guardia del backend
+    backend_response.extensions_mut().insert(BodyLimits { idle: body_idle, deadline });
+
+    // Compressione in streaming: i blocchi passano al client man mano che arrivano
+    let compressed_response = compress_response(backend_response, accept_encoding.as_deref(), head_request, compression);
+
+    // 10. Modifiche finali (es. header di sicurezza) e ritorno
+    Ok(modify_response(compressed_response))
+}
+
+/// Richiesta per il backend: URI riscritto, versione del protocollo usato verso il
+/// backend, Host del backend (`:authority` con HTTP/2) e header di tracing
+pub fn backend_request(
+    req: Request<hyper::Body>,
+    backend: &crate::backend::server::Backend,
+    protocol: BackendProtocol,
+) -> Result<Request<hyper::Body>> {
+    let backend_uri_str = prepare_backend_uri(req.uri(), &backend.url);
+    let parsed_uri: Uri = backend_uri_str.parse()
+        .context("Failed to parse backend URI")?;
+
     let client_ip = req.extensions()
         .get::<std::net::SocketAddr>()
         .map(|addr| addr.ip().to_string())
@@ -34,7 +87,13 @@ pub async fn forward_request(
 
     parts.uri = parsed_uri.clone();
 
-    if let Some(host) = parsed_uri.host() {
+    // Anche le richieste arrivate in HTTP/2 proseguono in HTTP/1.1 verso i backend http1
+    parts.version = if protocol.is_http2() { hyper::Version::HTTP_2 } else { hyper::Version::HTTP_11 };
+
+    if protocol.is_http2() {
+        // Con HTTP/2 l'authority viaggia nello pseudo-header `:authority`, preso dall'URI
+        parts.headers.remove(hyper::header::HOST);
+    } else if let Some(host) = parsed_uri.host() {
         let host_val = if let Some(port) = parsed_uri.port() {
             format!("{}:{}", host, port)
         } else {
@@ -48,111 +107,86 @@ pub async fn forward_request(
 
     add_tracing_headers(&mut parts.headers, &client_ip);
 
-    let backend_req = Request::from_parts(parts, body);
-
-    info!("Forwarding request to: {}", backend_req.uri());
-
-    let backend_response = client.request(backend_req).await
-        .context("Failed to forward request to backend")?;
-
-    let compressed_response = compress_response_adaptive(backend_response, accept_encoding.as_deref())
-        .await
-        .context("Response compression failed")?;
-
-    // 10. Modifiche finali (es. header di sicurezza) e ritorno
-    Ok(modify_response(compressed_response))
+    Ok(Request::from_parts(parts, body))
 }
 
-async fn compress_response_adaptive(
-    response: Response<hyper::Body>,
-    accept_encoding: Option<&str>,
-) -> Result<Response<hyper::Body>> {
-    
-    let content_type = response.headers()
-        .get("content-type")
-        .and_then(|ct| ct.to_str().ok())
-        .unwrap_or("unknown");
-
-    info!("Compression check - Content-Type: {}, Accept-Encoding: {:?}", 
-          content_type, accept_encoding);
-    // Controlla se il content-type è comprimibile
-    if !should_compress(&response) {
-        return Ok(response);
+/// Rimuove gli header validi solo sulla singola connessione (RFC 9110 §7.6.1),
+/// compresi quelli elencati in `Connection`. `TE: trailers` resta, serve a gRPC.
+pub fn strip_hop_by_hop(headers: &mut hyper::HeaderMap) {
+    let listed: Vec<hyper::header::HeaderName> = headers
+        .get_all(hyper::header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|token| token.trim().parse().ok())
+        .collect();
+    for name in listed {
+        headers.remove(name);
     }
-    
-    // Scegli algoritmo di compressione
-    let algorithm = choose_compression_algorithm(accept_encoding);
-    
-    match algorithm {
-        Some("gzip") => compress_gzip(response).await,
-        Some("identity") => Ok(response), // Explicit no compression
-        _ => Ok(response),  // Nessun algoritmo supportato
-    }
-}
 
-fn choose_compression_algorithm(accept_encoding: Option<&str>) -> Option<&'static str> {
-    accept_encoding.and_then(|ae| {
-        if ae.contains("gzip") {
-            Some("gzip")
-        }
-        else if ae.contains("identity") {
-            Some("identity")
-
-        } else {
-            None
-        }
-    })
+    for name in [
+        hyper::header::CONNECTION,
+        hyper::header::TRANSFER_ENCODING,
+        hyper::header::UPGRADE,
+    ] {
+        headers.remove(name);
+    }
+    headers.remove("keep-alive");
+    headers.remove("proxy-connection");
+
+    let te_trailers = headers
+        .get_all(hyper::header::TE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|token| token.trim().eq_ignore_ascii_case("trailers"));
+    headers.remove(hyper::header::TE);
+    if te_trailers {
+        headers.insert(hyper::header::TE, hyper::header::HeaderValue::from_static("trailers"));
+    }
 }
 
-fn should_compress(response: &Response<hyper::Body>) -> bool {
-
-    if response.headers().contains_key("content-encoding") {
-        return false;
-    }
-    
-    if let Some(content_type) = response.headers().get("content-type") {
-        let ct = content_type.to_str().unwrap_or("");
-        !(ct.starts_with("image/") || 
-          ct.starts_with("video/") || 
-          ct.starts_with("audio/") ||
-          ct.contains("octet-stream") ||
-          ct.contains("compressed") ||
-          ct.contains("zip"))
-    } else {
-        true
+/// Hash dell'attributo della richiesta scelto come chiave di consistent hashing
+pub fn request_hash_key(req: &Request<hyper::Body>, key: &HashKey) -> Option<u64> {
+    match key {
+        HashKey::ClientIp => req.extensions()
+            .get::<std::net::SocketAddr>()
+            .map(|addr| hash_of(&addr.ip())),
+        HashKey::Header(name) => req.headers()
+            .get(name.as_str())
+            .map(|value| hash_of(value.as_bytes())),
+        HashKey::Cookie(name) => cookie_value(req.headers(), name)
+            .map(hash_of),
+        HashKey::Path => Some(hash_of(req.uri().path())),
     }
 }
 
-async fn compress_gzip(response: Response<hyper::Body>) -> Result<Response<hyper::Body>> {
-    let (mut parts, body) = response.into_parts();
-    let body_bytes = hyper::body::to_bytes(body)
-        .await
-        .context("Failed to read body for gzip compression")?;
+/// Valore di un cookie dall'header `Cookie` (anche se ripetuto)
+pub fn cookie_value<'a>(headers: &'a hyper::HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(hyper::header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
 
-     if body_bytes.len() < 150 {  
-        return Ok(Response::from_parts(parts, hyper::Body::from(body_bytes)));
+pub fn is_connect_timeout(error: &hyper::Error) -> bool {
+    if !error.is_connect() {
+        return false;
     }
-    
-    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
-    encoder.write_all(&body_bytes)
-        .context("gzip compression failed")?;
-    
-    let compressed = encoder.finish()?;
-    
-    if compressed.len() >= body_bytes.len() {
-        return Ok(Response::from_parts(parts, hyper::Body::from(body_bytes)));
+    let mut source = std::error::Error::source(error);
+    while let Some(cause) = source {
+        if cause.downcast_ref::<std::io::Error>().is_some_and(|e| e.kind() == std::io::ErrorKind::TimedOut) {
+            return true;
+        }
+        source = cause.source();
     }
-
-    parts.headers.insert(
-        "content-encoding", 
-        "gzip".parse().unwrap()
-    );
-    parts.headers.remove("content-length");
-
-    Ok(Response::from_parts(parts, hyper::Body::from(compressed)))
+    false
 }
 
-
 fn prepare_backend_uri(original_uri: &hyper::Uri, backend_url: &str) -> String {
     let path_and_query = original_uri
         .path_and_query()
@@ -192,7 +226,11 @@ fn add_tracing_headers(headers: &mut hyper::HeaderMap, client_ip: &str) {
     // Imposta il protocollo (se non presente)
     headers.entry("X-Forwarded-Proto").or_insert_with(|| "http".parse().unwrap());
 
-    // Imposta l'IP reale (se non presente)
-    headers.entry("X-Real-IP").or_insert_with(|| client_ip.parse().unwrap());
+    // IP reale sempre dalla connessione (o dall'header PROXY): quello inviato dal client
+    // è falsificabile e viene sostituito
+    headers.remove("X-Real-IP");
+    if let std::result::Result::Ok(val) = client_ip.parse() {
+        headers.insert("X-Real-IP", val);
+    }
 }
 
@@ -1,7 +1,18 @@
-use hyper::{Response, StatusCode};
-use tracing::error;
+use crate::proxy::grpc::{error_trailers, is_grpc};
+use crate::proxy::timeout::{BodyLimits, ProxyTimeout, TimedBody};
+use futures::Stream;
+use hyper::body::{Bytes, HttpBody, SizeHint};
+use hyper::{HeaderMap, Response, StatusCode, Version};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tracing::{debug, error};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
 
 pub fn handle_proxy_error(error: anyhow::Error) -> Response<hyper::Body> {
+    if let Some(timeout) = ProxyTimeout::find(&error) {
+        return gateway_timeout(timeout);
+    }
     error!("Proxy error: {}", error);
 
     Response::builder()
@@ -10,6 +21,23 @@ pub fn handle_proxy_error(error: anyhow::Error) -> Response<hyper::Body> {
         .unwrap()
 }
 
+/// 504 con un messaggio diverso per ogni timeout, per capire dove si è bloccata la richiesta
+pub fn gateway_timeout(timeout: ProxyTimeout) -> Response<hyper::Body> {
+    error!("Gateway timeout: {}", timeout);
+
+    let reason = match timeout {
+        ProxyTimeout::Connect => "connect",
+        ProxyTimeout::FirstByte => "first-byte",
+        ProxyTimeout::Request => "request",
+        ProxyTimeout::BodyIdle => "body-idle",
+    };
+    Response::builder()
+        .status(StatusCode::GATEWAY_TIMEOUT)
+        .header("X-Timeout-Reason", reason)
+        .body(hyper::Body::from(format!("Gateway Timeout: {timeout}")))
+        .unwrap()
+}
+
 pub fn no_healthy_backends() -> Response<hyper::Body> {
     Response::builder()
         .status(StatusCode::SERVICE_UNAVAILABLE)
@@ -17,6 +45,15 @@ pub fn no_healthy_backends() -> Response<hyper::Body> {
         .unwrap()
 }
 
+/// 503 per sovraccarico del load balancer, con il tempo dopo cui riprovare
+pub fn overloaded(retry_after_secs: u64) -> Response<hyper::Body> {
+    Response::builder()
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .header("Retry-After", retry_after_secs)
+        .body(hyper::Body::from("Service overloaded, retry later"))
+        .unwrap()
+}
+
 pub fn compression_failed(error: anyhow::Error) -> Response<hyper::Body> {
     error!("Compression failed: {}", error);
 
@@ -46,3 +83,212 @@ pub fn modify_response(mut response: Response<hyper::Body>) -> Response<hyper::B
     );
     response
 }
+
+/// Solo status e header di una risposta: il corpo, e con lui la connessione al backend,
+/// viene rilasciato subito
+pub fn without_body(response: Response<hyper::Body>) -> Response<hyper::Body> {
+    let (mut parts, _body) = response.into_parts();
+    parts.headers.remove(hyper::header::CONTENT_LENGTH);
+    Response::from_parts(parts, hyper::Body::empty())
+}
+
+/// Tiene viva `guard` finché il corpo della risposta non è stato inviato tutto
+/// al client (o abbandonato), anche quando la risposta è in streaming. Qui si applicano
+/// anche i `BodyLimits` della richiesta al backend, se presenti.
+pub fn hold_until_body_end<G>(mut response: Response<hyper::Body>, guard: G) -> Response<hyper::Body>
+where
+    G: Send + Unpin + 'static,
+{
+    let limits = response.extensions_mut().remove::<BodyLimits>();
+    // Solo le risposte HTTP/2 dei backend possono avere trailer; per gRPC anche gli errori
+    // diventano trailer
+    let grpc = is_grpc(response.headers());
+    let trailers = response.version() == Version::HTTP_2;
+    response.map(|body| {
+        let body = match limits {
+            Some(limits) => TimedBody::new(body, limits.idle, limits.deadline),
+            None => TimedBody::new(body, None, None),
+        };
+        let body = GuardedBody::new(body, guard);
+        if grpc {
+            body_with_trailers(body, error_trailers)
+        } else if trailers {
+            body_with_trailers(body, |_| None)
+        } else {
+            hyper::Body::wrap_stream(body)
+        }
+    })
+}
+
+/// Corpo hyper da un `HttpBody` qualsiasi senza perdere i trailer, che `wrap_stream`
+/// scarta. Un task copia i blocchi su un canale, al ritmo con cui il client li legge.
+/// Se il corpo fallisce, `on_error` può chiuderlo con dei trailer invece di interromperlo.
+pub fn body_with_trailers<B, F>(mut body: B, on_error: F) -> hyper::Body
+where
+    B: HttpBody<Data = Bytes> + Send + Unpin + 'static,
+    B::Error: Into<BoxError> + Send,
+    F: FnOnce(BoxError) -> Option<HeaderMap> + Send + 'static,
+{
+    let (mut sender, receiver) = hyper::Body::channel();
+    tokio::spawn(async move {
+        // Client disconnesso: il corpo del backend viene abbandonato subito, anche se
+        // il backend non sta inviando niente (SSE, stream gRPC inattivi)
+        let failure = loop {
+            let next = tokio::select! {
+                next = body.data() => next,
+                _ = client_gone(&mut sender) => return,
+            };
+            match next {
+                Some(Ok(chunk)) => {
+                    if sender.send_data(chunk).await.is_err() {
+                        return;
+                    }
+                }
+                Some(Err(e)) => break e.into(),
+                None => {
+                    let trailers = tokio::select! {
+                        trailers = body.trailers() => trailers,
+                        _ = client_gone(&mut sender) => return,
+                    };
+                    match trailers {
+                        Ok(Some(trailers)) => {
+                            let _ = sender.send_trailers(trailers).await;
+                            return;
+                        }
+                        Ok(None) => return,
+                        Err(e) => break e.into(),
+                    }
+                }
+            }
+        };
+        debug!("Response body failed: {}", failure);
+        match on_error(failure) {
+            Some(trailers) => {
+                let _ = sender.send_trailers(trailers).await;
+            }
+            None => sender.abort(),
+        }
+    });
+    receiver
+}
+
+/// Si completa quando il corpo restituito al client viene abbandonato: `poll_ready`
+/// registra il task e hyper lo sveglia quando il ricevitore viene chiuso
+async fn client_gone(sender: &mut hyper::body::Sender) {
+    futures::future::poll_fn(|cx| match sender.poll_ready(cx) {
+        Poll::Ready(Err(_)) => Poll::Ready(()),
+        _ => Poll::Pending,
+    })
+    .await
+}
+
+/// Corpo che rilascia `guard` quando finisce o viene abbandonato; dimensione e trailer
+/// restano quelli del corpo originale
+pub struct GuardedBody<G, B = hyper::Body> {
+    inner: B,
+    guard: Option<G>,
+}
+
+impl<G, B> GuardedBody<G, B> {
+    pub fn new(inner: B, guard: G) -> Self {
+        Self { inner, guard: Some(guard) }
+    }
+}
+
+impl<G: Unpin, B: HttpBody<Data = Bytes> + Unpin> HttpBody for GuardedBody<G, B> {
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_data(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Bytes, B::Error>>> {
+        let this = self.get_mut();
+        let next = Pin::new(&mut this.inner).poll_data(cx);
+        if matches!(next, Poll::Ready(None) | Poll::Ready(Some(Err(_)))) {
+            this.guard = None;
+        }
+        next
+    }
+
+    fn poll_trailers(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<Option<HeaderMap>, B::Error>> {
+        Pin::new(&mut self.get_mut().inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        HttpBody::size_hint(&self.inner)
+    }
+}
+
+impl<G: Unpin, B: HttpBody<Data = Bytes> + Unpin> Stream for GuardedBody<G, B> {
+    type Item = Result<Bytes, B::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.poll_data(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// Come la guardia di una richiesta in corso: decrementa il contatore quando viene rilasciata
+    struct Counted(Arc<AtomicUsize>);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.0.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    async fn released(connections: &AtomicUsize) -> bool {
+        let wait = async {
+            while connections.load(Ordering::SeqCst) != 0 {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(1), wait).await.is_ok()
+    }
+
+    fn relayed(connections: &Arc<AtomicUsize>) -> (hyper::body::Sender, hyper::Body) {
+        connections.fetch_add(1, Ordering::SeqCst);
+        let (backend, backend_body) = hyper::Body::channel();
+        let body = GuardedBody::new(backend_body, Counted(Arc::clone(connections)));
+        (backend, body_with_trailers(body, |_| None))
+    }
+
+    #[tokio::test]
+    async fn idle_stream_is_released_when_the_client_goes_away() {
+        let connections = Arc::new(AtomicUsize::new(0));
+        let (mut backend, mut client) = relayed(&connections);
+
+        backend.send_data(Bytes::from_static(b"data: 1\n\n")).await.unwrap();
+        assert_eq!(client.data().await.unwrap().unwrap(), "data: 1\n\n");
+
+        // Il backend tiene aperto lo stream senza inviare niente
+        drop(client);
+        assert!(released(&connections).await);
+        drop(backend);
+    }
+
+    #[tokio::test]
+    async fn trailers_are_relayed_and_release_the_guard() {
+        let connections = Arc::new(AtomicUsize::new(0));
+        let (mut backend, mut client) = relayed(&connections);
+
+        backend.send_data(Bytes::from_static(b"message")).await.unwrap();
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", "0".parse().unwrap());
+        backend.send_trailers(trailers).await.unwrap();
+        drop(backend);
+
+        assert_eq!(client.data().await.unwrap().unwrap(), "message");
+        assert!(client.data().await.is_none());
+        assert_eq!(client.trailers().await.unwrap().unwrap()["grpc-status"], "0");
+        assert!(released(&connections).await);
+    }
+}
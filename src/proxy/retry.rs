@@ -0,0 +1,318 @@
+use crate::backend::latency::now_ns;
+use crate::config::RetryConfig;
+use crate::proxy::timeout::ProxyTimeout;
+use anyhow::Context;
+use hyper::body::{Bytes, HttpBody};
+use hyper::{HeaderMap, Method, Request, Response, Uri, Version};
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Politica di retry: quando ripetere una richiesta su un altro backend
+/// e quanti retry concedere in totale per non moltiplicare il carico.
+pub struct RetryPolicy {
+    config: RetryConfig,
+    budget: Mutex<BudgetWindow>,
+}
+
+#[derive(Default)]
+struct BudgetWindow {
+    start_ns: u64,
+    requests: u32,
+    retries: u32,
+}
+
+pub enum PreparedRequest {
+    Replayable(ReplayableRequest),
+    /// Corpo troppo grande o di dimensione ignota: un solo tentativo
+    Streaming(Request<hyper::Body>),
+}
+
+/// Richiesta con il corpo in memoria, ricostruibile per ogni tentativo
+pub struct ReplayableRequest {
+    method: Method,
+    uri: Uri,
+    version: Version,
+    headers: HeaderMap,
+    client_addr: Option<SocketAddr>,
+    body: Bytes,
+}
+
+impl ReplayableRequest {
+    pub fn method(&self) -> &Method {
+        &self.method
+    }
+
+    pub fn uri(&self) -> &Uri {
+        &self.uri
+    }
+
+    pub fn build(&self) -> Request<hyper::Body> {
+        let mut req = Request::new(hyper::Body::from(self.body.clone()));
+        *req.method_mut() = self.method.clone();
+        *req.uri_mut() = self.uri.clone();
+        *req.version_mut() = self.version;
+        *req.headers_mut() = self.headers.clone();
+        if let Some(addr) = self.client_addr {
+            req.extensions_mut().insert(addr);
+        }
+        req
+    }
+}
+
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE | Method::PUT | Method::DELETE
+    )
+}
+
+impl RetryPolicy {
+    pub fn new(config: RetryConfig) -> Self {
+        Self {
+            config,
+            budget: Mutex::new(BudgetWindow::default()),
+        }
+    }
+
+    pub fn max_retries(&self) -> u32 {
+        self.config.max_retries
+    }
+
+    /// Tiene in memoria il corpo se ha una dimensione nota entro `max_body_bytes`;
+    /// altrimenti restituisce la richiesta intatta, che verrà inoltrata una volta sola.
+    /// Upload chunked o grandi non vengono mai bufferizzati.
+    pub async fn prepare(&self, req: Request<hyper::Body>) -> anyhow::Result<PreparedRequest> {
+        let replayable = req
+            .body()
+            .size_hint()
+            .exact()
+            .is_some_and(|size| size <= self.config.max_body_bytes as u64);
+        if !replayable {
+            return Ok(PreparedRequest::Streaming(req));
+        }
+
+        let client_addr = req.extensions().get::<SocketAddr>().copied();
+        let (parts, body) = req.into_parts();
+        // Dimensione nota e piccola: la lettura è limitata a `max_body_bytes`
+        let body = hyper::body::to_bytes(body)
+            .await
+            .context("Failed to read request body")?;
+
+        Ok(PreparedRequest::Replayable(ReplayableRequest {
+            method: parts.method,
+            uri: parts.uri,
+            version: parts.version,
+            headers: parts.headers,
+            client_addr,
+            body,
+        }))
+    }
+
+    /// L'esito di un tentativo giustifica un nuovo tentativo su un altro backend.
+    /// Gli errori di connessione si ripetono per ogni metodo (la richiesta non è partita),
+    /// reset, attesa della risposta scaduta e status configurati solo per i metodi idempotenti.
+    pub fn should_retry(&self, method: &Method, result: &anyhow::Result<Response<hyper::Body>>) -> bool {
+        match result {
+            Ok(resp) => is_idempotent(method) && self.config.retry_on_status.contains(&resp.status().as_u16()),
+            Err(e) => {
+                if let Some(timeout) = ProxyTimeout::find(e) {
+                    return match timeout {
+                        ProxyTimeout::Connect => true,
+                        ProxyTimeout::FirstByte => is_idempotent(method),
+                        // Tempo totale già speso o risposta già iniziata
+                        ProxyTimeout::Request | ProxyTimeout::BodyIdle => false,
+                    };
+                }
+                match e.chain().find_map(|cause| cause.downcast_ref::<hyper::Error>()) {
+                    Some(hyper_error) if hyper_error.is_connect() => true,
+                    Some(_) => is_idempotent(method),
+                    None => false,
+                }
+            }
+        }
+    }
+
+    /// Conta una richiesta in arrivo per il budget globale
+    pub fn record_request(&self) {
+        let mut window = self.current_window();
+        window.requests = window.requests.saturating_add(1);
+    }
+
+    /// Consuma un retry dal budget globale: i retry restano entro `budget_percent`
+    /// delle richieste della finestra (con un minimo fisso per il traffico basso)
+    pub fn try_consume_budget(&self) -> bool {
+        let mut window = self.current_window();
+        let allowed = (window.requests as u64 * self.config.budget_percent as u64 / 100)
+            .max(self.config.min_retries_per_window as u64);
+        if window.retries as u64 >= allowed {
+            return false;
+        }
+        window.retries += 1;
+        true
+    }
+
+    fn current_window(&self) -> std::sync::MutexGuard<'_, BudgetWindow> {
+        let now = now_ns();
+        let window_ns = Duration::from_secs(self.config.budget_window_secs).as_nanos() as u64;
+        let mut window = self.budget.lock().unwrap();
+        if now.saturating_sub(window.start_ns) > window_ns {
+            *window = BudgetWindow {
+                start_ns: now,
+                ..BudgetWindow::default()
+            };
+        }
+        window
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::StatusCode;
+    use tokio::net::TcpListener;
+
+    fn policy(config: RetryConfig) -> RetryPolicy {
+        RetryPolicy::new(config)
+    }
+
+    fn status(code: u16) -> anyhow::Result<Response<hyper::Body>> {
+        let mut response = Response::new(hyper::Body::empty());
+        *response.status_mut() = StatusCode::from_u16(code).unwrap();
+        Ok(response)
+    }
+
+    fn timeout(timeout: ProxyTimeout) -> anyhow::Result<Response<hyper::Body>> {
+        Err(anyhow::Error::from(timeout).context("Failed to forward request to backend"))
+    }
+
+    /// Errore di connessione vero: nessuno ascolta sulla porta
+    async fn connect_error() -> anyhow::Result<Response<hyper::Body>> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        let error = hyper::Client::new().get(format!("http://{addr}/").parse().unwrap()).await.unwrap_err();
+        assert!(error.is_connect());
+        Err(anyhow::Error::from(error).context("Failed to forward request to backend"))
+    }
+
+    /// Connessione accettata e chiusa senza risposta: la richiesta può essere già arrivata
+    async fn reset_error() -> anyhow::Result<Response<hyper::Body>> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            drop(stream);
+        });
+        let error = hyper::Client::new().get(format!("http://{addr}/").parse().unwrap()).await.unwrap_err();
+        assert!(!error.is_connect());
+        Err(anyhow::Error::from(error).context("Failed to forward request to backend"))
+    }
+
+    #[tokio::test]
+    async fn connect_errors_are_retried_for_every_method() {
+        let policy = policy(RetryConfig::default());
+        let refused = connect_error().await;
+        for method in [Method::GET, Method::POST, Method::PATCH] {
+            assert!(policy.should_retry(&method, &refused), "{method}");
+            assert!(policy.should_retry(&method, &timeout(ProxyTimeout::Connect)), "{method}");
+        }
+    }
+
+    #[tokio::test]
+    async fn other_errors_are_retried_only_for_idempotent_methods() {
+        let policy = policy(RetryConfig::default());
+        let reset = reset_error().await;
+        let first_byte = timeout(ProxyTimeout::FirstByte);
+        for method in [Method::GET, Method::HEAD, Method::PUT, Method::DELETE] {
+            assert!(policy.should_retry(&method, &reset), "{method}");
+            assert!(policy.should_retry(&method, &first_byte), "{method}");
+        }
+        for method in [Method::POST, Method::PATCH] {
+            assert!(!policy.should_retry(&method, &reset), "{method}");
+            assert!(!policy.should_retry(&method, &first_byte), "{method}");
+        }
+    }
+
+    #[test]
+    fn spent_time_and_unknown_errors_are_not_retried() {
+        let policy = policy(RetryConfig::default());
+        assert!(!policy.should_retry(&Method::GET, &timeout(ProxyTimeout::Request)));
+        assert!(!policy.should_retry(&Method::GET, &timeout(ProxyTimeout::BodyIdle)));
+        assert!(!policy.should_retry(&Method::GET, &Err(anyhow::anyhow!("Failed to parse backend URI"))));
+    }
+
+    #[test]
+    fn configured_status_codes_on_idempotent_methods() {
+        let policy = policy(RetryConfig {
+            retry_on_status: vec![502, 503],
+            ..RetryConfig::default()
+        });
+        assert!(policy.should_retry(&Method::GET, &status(502)));
+        assert!(policy.should_retry(&Method::GET, &status(503)));
+        assert!(!policy.should_retry(&Method::GET, &status(500)));
+        assert!(!policy.should_retry(&Method::GET, &status(200)));
+        // La richiesta è arrivata al backend: POST non si ripete
+        assert!(!policy.should_retry(&Method::POST, &status(503)));
+    }
+
+    #[test]
+    fn budget_has_a_floor_for_low_traffic() {
+        let policy = policy(RetryConfig {
+            budget_percent: 20,
+            min_retries_per_window: 3,
+            budget_window_secs: 60,
+            ..RetryConfig::default()
+        });
+        // Nessuna richiesta registrata: valgono i retry minimi
+        for _ in 0..3 {
+            assert!(policy.try_consume_budget());
+        }
+        assert!(!policy.try_consume_budget());
+    }
+
+    #[test]
+    fn budget_follows_the_request_percentage() {
+        let policy = policy(RetryConfig {
+            budget_percent: 20,
+            min_retries_per_window: 1,
+            budget_window_secs: 60,
+            ..RetryConfig::default()
+        });
+        for _ in 0..50 {
+            policy.record_request();
+        }
+        for _ in 0..10 {
+            assert!(policy.try_consume_budget());
+        }
+        assert!(!policy.try_consume_budget());
+
+        // Altre richieste nella stessa finestra liberano altri retry
+        for _ in 0..5 {
+            policy.record_request();
+        }
+        assert!(policy.try_consume_budget());
+        assert!(!policy.try_consume_budget());
+    }
+
+    #[test]
+    fn budget_refills_with_a_new_window() {
+        let mut policy = policy(RetryConfig {
+            budget_percent: 0,
+            min_retries_per_window: 2,
+            budget_window_secs: 60,
+            ..RetryConfig::default()
+        });
+        assert!(policy.try_consume_budget());
+        assert!(policy.try_consume_budget());
+        assert!(!policy.try_consume_budget());
+
+        // Finestra scaduta: il conteggio riparte da zero
+        policy.config.budget_window_secs = 0;
+        policy.record_request();
+        policy.config.budget_window_secs = 60;
+        assert!(policy.try_consume_budget());
+        assert!(policy.try_consume_budget());
+        assert!(!policy.try_consume_budget());
+    }
+}
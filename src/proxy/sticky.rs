@@ -0,0 +1,217 @@
+use crate::config::StickySessionConfig;
+use crate::proxy::request::cookie_value;
+use hmac::{Hmac, Mac};
+use hyper::header::{HeaderValue, SET_COOKIE};
+use hyper::{HeaderMap, Response};
+use rand::RngCore;
+use sha2::Sha256;
+use tracing::warn;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Sessioni sticky: il load balancer emette un cookie firmato con il nome del backend
+/// scelto e lo usa per instradare le richieste successive sullo stesso backend.
+/// Cookie sticky per il client, tenuto tra le estensioni della risposta e aggiunto agli
+/// header solo all'uscita: le copie in cache non devono legare tutti allo stesso backend
+#[derive(Debug, Clone)]
+pub struct PendingCookie(pub HeaderValue);
+
+/// Sposta negli header il cookie sticky in sospeso, se c'è
+pub fn apply_pending_cookie(mut response: Response<hyper::Body>) -> Response<hyper::Body> {
+    if let Some(PendingCookie(cookie)) = response.extensions_mut().remove::<PendingCookie>() {
+        response.headers_mut().append(SET_COOKIE, cookie);
+    }
+    response
+}
+
+pub struct StickySessions {
+    cookie_name: String,
+    key: Vec<u8>,
+    max_age_secs: Option<u64>,
+}
+
+impl StickySessions {
+    pub fn new(config: &StickySessionConfig) -> Self {
+        let key = match &config.secret {
+            Some(secret) => secret.as_bytes().to_vec(),
+            None => {
+                warn!("sticky_session.secret not set: using a random key, sessions will not survive a restart");
+                let mut key = vec![0u8; 32];
+                rand::thread_rng().fill_bytes(&mut key);
+                key
+            }
+        };
+
+        Self {
+            cookie_name: config.cookie_name.clone(),
+            key,
+            max_age_secs: config.max_age_secs,
+        }
+    }
+
+    /// Nome del backend indicato dal cookie, solo se la firma è valida
+    pub fn backend_from_request(&self, headers: &HeaderMap) -> Option<String> {
+        let value = cookie_value(headers, &self.cookie_name)?;
+        let (encoded_name, signature) = value.split_once('.')?;
+
+        let mut mac = self.mac();
+        mac.update(encoded_name.as_bytes());
+        mac.verify_slice(&decode_hex(signature)?).ok()?;
+
+        String::from_utf8(decode_hex(encoded_name)?).ok()
+    }
+
+    /// Header `Set-Cookie` che lega il client al backend indicato
+    pub fn cookie_for(&self, backend_name: &str) -> HeaderValue {
+        // Nome in esadecimale: nel cookie non finiscono caratteri non ammessi
+        let encoded_name = encode_hex(backend_name.as_bytes());
+        let mut mac = self.mac();
+        mac.update(encoded_name.as_bytes());
+        let signature = encode_hex(&mac.finalize().into_bytes());
+
+        let mut cookie = format!(
+            "{}={}.{}; Path=/; HttpOnly; SameSite=Lax",
+            self.cookie_name, encoded_name, signature
+        );
+        if let Some(max_age) = self.max_age_secs {
+            cookie.push_str(&format!("; Max-Age={max_age}"));
+        }
+
+        HeaderValue::from_str(&cookie).expect("cookie name validated at startup")
+    }
+
+    fn mac(&self) -> HmacSha256 {
+        HmacSha256::new_from_slice(&self.key).expect("HMAC accepts keys of any length")
+    }
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::pool::{BackendPool, SelectionContext};
+    use crate::backend::server::{Backend, BackendStatus, LoadBalancingStrategy};
+    use crate::config::BackendConfig;
+
+    fn sessions(secret: &str) -> StickySessions {
+        StickySessions::new(&StickySessionConfig {
+            cookie_name: "lb_backend".to_string(),
+            secret: Some(secret.to_string()),
+            max_age_secs: Some(3600),
+        })
+    }
+
+    /// Header `Cookie` con il valore di `Set-Cookie` (senza attributi), accanto ad altri cookie
+    fn request_with(cookie: &HeaderValue) -> HeaderMap {
+        let pair = cookie.to_str().unwrap().split(';').next().unwrap().to_string();
+        let mut headers = HeaderMap::new();
+        headers.insert(hyper::header::COOKIE, format!("theme=dark; {pair}").parse().unwrap());
+        headers
+    }
+
+    /// Cookie `lb_backend` con nome e firma dati
+    fn forged(encoded_name: &str, signature: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(hyper::header::COOKIE, format!("lb_backend={encoded_name}.{signature}").parse().unwrap());
+        headers
+    }
+
+    fn parts(cookie: &HeaderValue) -> (String, String) {
+        let pair = cookie.to_str().unwrap().split(';').next().unwrap();
+        let (_, value) = pair.split_once('=').unwrap();
+        let (name, signature) = value.split_once('.').unwrap();
+        (name.to_string(), signature.to_string())
+    }
+
+    #[test]
+    fn cookie_round_trip() {
+        let sticky = sessions("secret");
+        let cookie = sticky.cookie_for("backend-1");
+        let value = cookie.to_str().unwrap();
+        assert!(value.starts_with("lb_backend="));
+        assert!(value.contains("; HttpOnly; SameSite=Lax; Max-Age=3600"));
+        assert_eq!(sticky.backend_from_request(&request_with(&cookie)).as_deref(), Some("backend-1"));
+    }
+
+    #[test]
+    fn tampered_name_is_rejected() {
+        let sticky = sessions("secret");
+        let (_, signature) = parts(&sticky.cookie_for("backend-1"));
+        let other = encode_hex(b"backend-2");
+        assert_eq!(sticky.backend_from_request(&forged(&other, &signature)), None);
+    }
+
+    #[test]
+    fn tampered_mac_is_rejected() {
+        let sticky = sessions("secret");
+        let (name, signature) = parts(&sticky.cookie_for("backend-1"));
+        let mut flipped = signature.clone().into_bytes();
+        flipped[0] = if flipped[0] == b'0' { b'1' } else { b'0' };
+        let flipped = String::from_utf8(flipped).unwrap();
+        assert_eq!(sticky.backend_from_request(&forged(&name, &flipped)), None);
+        assert_eq!(sticky.backend_from_request(&forged(&name, &signature[..32])), None);
+        assert_eq!(sticky.backend_from_request(&forged(&name, "zz")), None);
+        assert_eq!(sticky.backend_from_request(&forged(&name, "")), None);
+        // Firmato con un'altra chiave
+        let (_, foreign) = parts(&sessions("other secret").cookie_for("backend-1"));
+        assert_eq!(sticky.backend_from_request(&forged(&name, &foreign)), None);
+    }
+
+    #[test]
+    fn missing_or_malformed_cookie() {
+        let sticky = sessions("secret");
+        assert_eq!(sticky.backend_from_request(&HeaderMap::new()), None);
+        let mut headers = HeaderMap::new();
+        headers.insert(hyper::header::COOKIE, "lb_backend=no-signature".parse().unwrap());
+        assert_eq!(sticky.backend_from_request(&headers), None);
+    }
+
+    fn backend_config(name: &str) -> BackendConfig {
+        BackendConfig {
+            name: name.to_string(),
+            url: format!("http://{name}"),
+            weight: None,
+            health_check: None,
+            timeouts: None,
+            max_connections: None,
+            protocol: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn removed_backend_falls_back_to_normal_selection() {
+        let pool = BackendPool::new(
+            ["a", "b"].iter().map(|name| Backend::new(format!("http://{name}"), name.to_string(), 1)).collect(),
+            LoadBalancingStrategy::RoundRobin,
+        );
+        for name in ["a", "b"] {
+            pool.update_backend_status(name, BackendStatus::Healthy).await;
+        }
+        let sticky = sessions("secret");
+        let headers = request_with(&sticky.cookie_for("b"));
+        let pinned = sticky.backend_from_request(&headers);
+        let ctx = SelectionContext::default();
+
+        let guard = pool.select_pinned_or_increment(pinned.as_deref(), &ctx).await.unwrap();
+        assert_eq!(guard.backend_state().backend.name, "b");
+        drop(guard);
+
+        // "b" tolto dalla config: il cookie resta valido ma la scelta torna alla strategia
+        pool.reconcile(&[backend_config("a")]);
+        let guard = pool.select_pinned_or_increment(pinned.as_deref(), &ctx).await.unwrap();
+        assert_eq!(guard.backend_state().backend.name, "a");
+    }
+}
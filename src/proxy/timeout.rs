@@ -0,0 +1,166 @@
+use crate::config::{BackendTimeouts, TimeoutConfig};
+use futures::Stream;
+use hyper::body::{Bytes, HttpBody, SizeHint};
+use hyper::HeaderMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time::{Instant, Sleep};
+
+/// Timeout scattato verso un backend; ognuno ha la sua risposta 504
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyTimeout {
+    Connect,
+    FirstByte,
+    Request,
+    BodyIdle,
+}
+
+impl fmt::Display for ProxyTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ProxyTimeout::Connect => "timed out connecting to the backend",
+            ProxyTimeout::FirstByte => "timed out waiting for the backend response",
+            ProxyTimeout::Request => "backend request exceeded the total timeout",
+            ProxyTimeout::BodyIdle => "backend stopped sending the response body",
+        })
+    }
+}
+
+impl std::error::Error for ProxyTimeout {}
+
+impl ProxyTimeout {
+    /// Timeout presente nella catena di errori di un tentativo
+    pub fn find(error: &anyhow::Error) -> Option<ProxyTimeout> {
+        error.chain().find_map(|cause| cause.downcast_ref::<ProxyTimeout>()).copied()
+    }
+}
+
+/// Timeout effettivi per un backend: quelli globali con gli override del backend
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    pub connect: Duration,
+    pub first_byte: Duration,
+    pub request: Duration,
+    pub body_idle: Duration,
+}
+
+impl Timeouts {
+    pub fn resolve(global: &TimeoutConfig, backend: &BackendTimeouts) -> Self {
+        Self {
+            connect: Duration::from_millis(backend.connect_ms.unwrap_or(global.connect_ms)),
+            first_byte: Duration::from_millis(backend.first_byte_ms.unwrap_or(global.first_byte_ms)),
+            request: Duration::from_millis(backend.request_ms.unwrap_or(global.request_ms)),
+            body_idle: Duration::from_millis(backend.body_idle_ms.unwrap_or(global.body_idle_ms)),
+        }
+    }
+}
+
+/// Limiti del corpo decisi insieme alla richiesta al backend; viaggiano nelle extension
+/// della risposta e si applicano dove il corpo viene consegnato al client (`hold_until_body_end`)
+#[derive(Debug, Clone, Copy)]
+pub struct BodyLimits {
+    pub idle: Option<Duration>,
+    pub deadline: Option<Instant>,
+}
+
+/// Corpo della risposta del backend con timeout di inattività e scadenza totale della richiesta;
+/// senza uno dei due (stream gRPC) quel limite non si applica
+pub struct TimedBody {
+    inner: hyper::Body,
+    idle: Option<Duration>,
+    idle_timer: Option<Pin<Box<Sleep>>>,
+    deadline: Option<Pin<Box<Sleep>>>,
+    done: bool,
+}
+
+impl TimedBody {
+    pub fn new(inner: hyper::Body, idle: Option<Duration>, deadline: Option<Instant>) -> Self {
+        Self {
+            inner,
+            idle,
+            idle_timer: idle.map(|idle| Box::pin(tokio::time::sleep(idle))),
+            deadline: deadline.map(|deadline| Box::pin(tokio::time::sleep_until(deadline))),
+            done: false,
+        }
+    }
+}
+
+impl TimedBody {
+    /// Timeout scaduto mentre il backend non ha niente da inviare
+    fn expired(&mut self, cx: &mut Context<'_>) -> Option<ProxyTimeout> {
+        if self.deadline.as_mut().is_some_and(|deadline| deadline.as_mut().poll(cx).is_ready()) {
+            Some(ProxyTimeout::Request)
+        } else if self.idle_timer.as_mut().is_some_and(|timer| timer.as_mut().poll(cx).is_ready()) {
+            Some(ProxyTimeout::BodyIdle)
+        } else {
+            None
+        }
+    }
+}
+
+impl HttpBody for TimedBody {
+    type Data = Bytes;
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+
+    fn poll_data(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = self.get_mut();
+        if this.done {
+            return Poll::Ready(None);
+        }
+
+        match Pin::new(&mut this.inner).poll_data(cx) {
+            Poll::Ready(Some(Ok(chunk))) => {
+                if let (Some(timer), Some(idle)) = (this.idle_timer.as_mut(), this.idle) {
+                    timer.as_mut().reset(Instant::now() + idle);
+                }
+                Poll::Ready(Some(Ok(chunk)))
+            }
+            Poll::Ready(Some(Err(e))) => {
+                this.done = true;
+                Poll::Ready(Some(Err(e.into())))
+            }
+            Poll::Ready(None) => {
+                this.done = true;
+                Poll::Ready(None)
+            }
+            Poll::Pending => match this.expired(cx) {
+                Some(timeout) => {
+                    this.done = true;
+                    Poll::Ready(Some(Err(timeout.into())))
+                }
+                None => Poll::Pending,
+            },
+        }
+    }
+
+    /// I trailer (gRPC) arrivano dopo l'ultimo blocco e rispettano gli stessi timeout
+    fn poll_trailers(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.inner).poll_trailers(cx) {
+            Poll::Ready(result) => Poll::Ready(result.map_err(Into::into)),
+            Poll::Pending => match this.expired(cx) {
+                Some(timeout) => Poll::Ready(Err(timeout.into())),
+                None => Poll::Pending,
+            },
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        HttpBody::size_hint(&self.inner)
+    }
+}
+
+impl Stream for TimedBody {
+    type Item = Result<Bytes, Box<dyn std::error::Error + Send + Sync>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.poll_data(cx)
+    }
+}
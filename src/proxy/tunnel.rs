@@ -0,0 +1,133 @@
+use crate::backend::pool::{ConnectionGuard, Traffic};
+use crate::backend::BackendProtocol;
+use crate::proxy::request::{backend_request, is_connect_timeout};
+use crate::proxy::timeout::{ProxyTimeout, Timeouts};
+use anyhow::{Context, Result};
+use hyper::client::HttpConnector;
+use hyper::header::{HeaderMap, CONNECTION, UPGRADE};
+use hyper::upgrade::OnUpgrade;
+use hyper::{Client, Request, Response, StatusCode};
+use hyper_rustls::HttpsConnector;
+use std::io;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tracing::{info, warn};
+
+const BUFFER_SIZE: usize = 16 * 1024;
+
+/// Richiesta di upgrade: `Connection: upgrade` e il protocollo richiesto in `Upgrade`
+pub fn is_upgrade_request(headers: &HeaderMap) -> bool {
+    headers.contains_key(UPGRADE)
+        && headers
+            .get_all(CONNECTION)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|token| token.trim().eq_ignore_ascii_case("upgrade"))
+}
+
+/// Inoltra la richiesta di upgrade al backend. Con un 101 restituisce anche la
+/// connessione del backend da collegare al client; altrimenti la risposta è normale.
+pub async fn forward_upgrade(
+    req: Request<hyper::Body>,
+    backend: &crate::backend::server::Backend,
+    client: &Client<HttpsConnector<HttpConnector>>,
+    timeouts: &Timeouts,
+) -> Result<(Response<hyper::Body>, Option<OnUpgrade>)> {
+    // Gli upgrade esistono solo in HTTP/1.1, anche verso backend HTTP/2
+    let backend_req = backend_request(req, backend, BackendProtocol::Http1)?;
+    info!("Forwarding upgrade request to: {}", backend_req.uri());
+
+    let first_byte = timeouts.first_byte.min(timeouts.request);
+    let mut response = match tokio::time::timeout(first_byte, client.request(backend_req)).await {
+        Ok(Ok(response)) => response,
+        Ok(Err(e)) if is_connect_timeout(&e) => return Err(ProxyTimeout::Connect.into()),
+        Ok(Err(e)) => return Err(e).context("Failed to forward upgrade request to backend"),
+        Err(_) => return Err(ProxyTimeout::FirstByte.into()),
+    };
+
+    if response.status() != StatusCode::SWITCHING_PROTOCOLS {
+        return Ok((response, None));
+    }
+    let backend_upgrade = hyper::upgrade::on(&mut response);
+    Ok((response, Some(backend_upgrade)))
+}
+
+/// Collega client e backend finché uno dei due chiude o il tunnel resta inattivo.
+/// La guardia tiene il tunnel tra le connessioni attive del backend per tutta la sua durata.
+pub async fn run_tunnel(
+    client_upgrade: OnUpgrade,
+    backend_upgrade: OnUpgrade,
+    backend_state: ConnectionGuard,
+    idle: Duration,
+) {
+    let backend_name = backend_state.backend.name.clone();
+    let (client, backend) = match tokio::try_join!(client_upgrade, backend_upgrade) {
+        Ok(upgraded) => upgraded,
+        Err(e) => {
+            warn!("Upgrade to backend {} failed: {}", backend_name, e);
+            return;
+        }
+    };
+
+    info!("Tunnel to backend {} opened", backend_name);
+    match splice(client, backend, idle, &backend_state.traffic).await {
+        Ok((sent, received)) => info!(
+            "Tunnel to backend {} closed ({} bytes sent, {} bytes received)",
+            backend_name, sent, received
+        ),
+        Err(e) => info!("Tunnel to backend {} closed: {}", backend_name, e),
+    }
+    drop(backend_state);
+}
+
+/// Copia in entrambe le direzioni; la chiusura di un lato viene propagata all'altro.
+/// I byte vengono sommati anche a `traffic` mentre passano, non solo alla chiusura.
+pub async fn splice<C, B>(client: C, backend: B, idle: Duration, traffic: &Traffic) -> io::Result<(u64, u64)>
+where
+    C: AsyncRead + AsyncWrite,
+    B: AsyncRead + AsyncWrite,
+{
+    let (mut client_read, mut client_write) = tokio::io::split(client);
+    let (mut backend_read, mut backend_write) = tokio::io::split(backend);
+    let mut client_buf = vec![0u8; BUFFER_SIZE];
+    let mut backend_buf = vec![0u8; BUFFER_SIZE];
+    let (mut sent, mut received) = (0u64, 0u64);
+    let (mut client_open, mut backend_open) = (true, true);
+
+    let idle_timer = tokio::time::sleep(idle);
+    tokio::pin!(idle_timer);
+
+    while client_open || backend_open {
+        tokio::select! {
+            read = client_read.read(&mut client_buf), if client_open => {
+                let n = read?;
+                if n == 0 {
+                    client_open = false;
+                    backend_write.shutdown().await?;
+                } else {
+                    backend_write.write_all(&client_buf[..n]).await?;
+                    sent += n as u64;
+                    traffic.sent.fetch_add(n as u64, Ordering::Relaxed);
+                }
+            }
+            read = backend_read.read(&mut backend_buf), if backend_open => {
+                let n = read?;
+                if n == 0 {
+                    backend_open = false;
+                    client_write.shutdown().await?;
+                } else {
+                    client_write.write_all(&backend_buf[..n]).await?;
+                    received += n as u64;
+                    traffic.received.fetch_add(n as u64, Ordering::Relaxed);
+                }
+            }
+            _ = &mut idle_timer => {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "idle timeout"));
+            }
+        }
+        idle_timer.as_mut().reset(tokio::time::Instant::now() + idle);
+    }
+    Ok((sent, received))
+}